@@ -0,0 +1,6 @@
+drop index feed_entry_guid_idx;
+
+alter table feed_entry
+    drop column guid,
+    drop column link,
+    drop column author;
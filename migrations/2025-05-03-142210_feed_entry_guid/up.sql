@@ -0,0 +1,15 @@
+-- fields needed to dedupe and display fetched entries
+alter table feed_entry
+    -- stable identifier given by the feed (or generated by the parser)
+    add column guid text,
+    add column link text,
+    add column author text;
+
+update feed_entry set guid = id::text where guid is null;
+
+alter table feed_entry
+    alter column guid set not null;
+
+-- idx ensures a feed entry is only stored once per feed
+create unique index feed_entry_guid_idx
+on feed_entry (feed_id, guid);
@@ -0,0 +1,4 @@
+drop index feed_next_fetch_at_idx;
+
+alter table feed
+    drop column next_fetch_at;
@@ -0,0 +1,6 @@
+-- when the scheduler should next enqueue the feed, null means as soon as possible
+alter table feed
+    add column next_fetch_at timestamptz;
+
+create index feed_next_fetch_at_idx
+on feed (next_fetch_at);
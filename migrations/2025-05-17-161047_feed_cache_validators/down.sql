@@ -0,0 +1,4 @@
+alter table feed
+    drop column etag,
+    drop column last_modified,
+    drop column content_hash;
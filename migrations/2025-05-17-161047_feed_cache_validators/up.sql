@@ -0,0 +1,6 @@
+-- validators of the last successful fetch, used to make conditional requests
+alter table feed
+    add column etag text,
+    add column last_modified text,
+    -- hex encoded sha256 of the last fetched body
+    add column content_hash text;
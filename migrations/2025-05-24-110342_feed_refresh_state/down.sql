@@ -0,0 +1,4 @@
+alter table feed
+    drop column consecutive_failures,
+    drop column last_success_at,
+    drop column publisher_interval_secs;
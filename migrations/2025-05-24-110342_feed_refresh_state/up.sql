@@ -0,0 +1,6 @@
+-- state used to compute when a feed should be fetched next
+alter table feed
+    add column consecutive_failures integer not null default 0,
+    add column last_success_at timestamptz,
+    -- minimum refresh interval advertised by the feed (`<ttl>`, `<sy:updatePeriod>`)
+    add column publisher_interval_secs integer;
@@ -0,0 +1,4 @@
+alter table feed
+    drop column last_error;
+
+drop table feed_fetch_log;
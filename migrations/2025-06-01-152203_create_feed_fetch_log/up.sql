@@ -0,0 +1,30 @@
+-- journal of every fetch attempt of a feed
+create table feed_fetch_log (
+    id integer not null primary key generated always as identity,
+    feed_id integer not null,
+
+    fetched_at timestamptz not null,
+    duration_ms integer not null,
+
+    -- null when no response was received
+    http_status integer,
+    -- size of the received body
+    bytes integer,
+
+    -- null on success
+    error_kind text check(error_kind in (
+        'dns', 'connect', 'tls', 'timeout', 'redirect',
+        'http_4xx', 'http_5xx', 'body', 'parse', 'other'
+    )),
+    error_message text,
+
+    foreign key (feed_id) references feed(id)
+        on delete cascade
+);
+
+create index feed_fetch_log_feed_idx
+on feed_fetch_log (feed_id, fetched_at desc);
+
+-- summary of the latest failure shown next to the feed status
+alter table feed
+    add column last_error text;
@@ -0,0 +1,21 @@
+-- durable fetch queue shared by every server instance
+create table fetch_job (
+    id integer not null primary key generated always as identity,
+    -- a feed is queued at most once
+    feed_id integer not null unique,
+
+    -- higher is fetched first, user triggered refreshes go before background polls
+    priority integer not null default 0,
+    -- number of times the job was claimed
+    attempts integer not null default 0,
+
+    enqueued_at timestamptz not null default now(),
+    -- pushed forward when claimed, the job is picked up again if the worker dies
+    visible_at timestamptz not null default now(),
+
+    foreign key (feed_id) references feed(id)
+        on delete cascade
+);
+
+create index fetch_job_claim_idx
+on fetch_job (priority desc, visible_at);
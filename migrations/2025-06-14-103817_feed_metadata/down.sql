@@ -0,0 +1,16 @@
+update user_feed
+set title = coalesce(feed.title, feed.url)
+from feed
+where user_feed.feed_id = feed.id and user_feed.title is null;
+
+alter table user_feed
+    alter column title set not null;
+
+alter table feed
+    drop column title,
+    drop column description,
+    drop column site_link,
+    drop column language,
+    drop column generator,
+    drop column icon_url,
+    drop column logo_url;
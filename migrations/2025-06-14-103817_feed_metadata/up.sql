@@ -0,0 +1,14 @@
+-- metadata advertised by the feed itself, refreshed on each fetch
+alter table feed
+    add column title text,
+    add column description text,
+    -- website the feed belongs to
+    add column site_link text,
+    add column language text,
+    add column generator text,
+    add column icon_url text,
+    add column logo_url text;
+
+-- null follows the title of the feed
+alter table user_feed
+    alter column title drop not null;
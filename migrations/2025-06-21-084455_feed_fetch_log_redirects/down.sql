@@ -0,0 +1,2 @@
+alter table feed_fetch_log
+    drop column redirects;
@@ -0,0 +1,3 @@
+-- redirects followed before reaching the feed, as a list of `{ status, url }`
+alter table feed_fetch_log
+    add column redirects jsonb;
@@ -0,0 +1,10 @@
+update feed_fetch_log
+set error_kind = 'other'
+where error_kind = 'blocked';
+
+alter table feed_fetch_log
+    drop constraint feed_fetch_log_error_kind_check,
+    add constraint feed_fetch_log_error_kind_check check(error_kind in (
+        'dns', 'connect', 'tls', 'timeout', 'redirect',
+        'http_4xx', 'http_5xx', 'body', 'parse', 'other'
+    ));
@@ -0,0 +1,7 @@
+-- requests refused by the outbound policy
+alter table feed_fetch_log
+    drop constraint feed_fetch_log_error_kind_check,
+    add constraint feed_fetch_log_error_kind_check check(error_kind in (
+        'blocked', 'dns', 'connect', 'tls', 'timeout', 'redirect',
+        'http_4xx', 'http_5xx', 'body', 'parse', 'other'
+    ));
@@ -0,0 +1,4 @@
+alter table feed_fetch_log
+    drop column pushed;
+
+drop table websub_subscription;
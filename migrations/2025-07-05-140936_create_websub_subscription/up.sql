@@ -0,0 +1,25 @@
+-- websub subscriptions to the hubs advertised by feeds
+create table websub_subscription (
+    id integer not null primary key generated always as identity,
+    -- a feed is subscribed to a single hub
+    feed_id integer not null unique,
+
+    hub text not null,
+    topic text not null,
+    -- shared with the hub to sign pushed content
+    secret text not null,
+
+    status text check(status in ('pending', 'active', 'denied')) not null,
+    requested_at timestamptz not null,
+
+    -- set once the hub verified the subscription
+    lease_seconds integer,
+    lease_expires_at timestamptz,
+
+    foreign key (feed_id) references feed(id)
+        on delete cascade
+);
+
+-- content pushed by a hub rather than polled
+alter table feed_fetch_log
+    add column pushed boolean not null default false;
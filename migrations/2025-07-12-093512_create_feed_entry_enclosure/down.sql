@@ -0,0 +1 @@
+drop table feed_entry_enclosure;
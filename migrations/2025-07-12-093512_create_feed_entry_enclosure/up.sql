@@ -0,0 +1,19 @@
+-- media attached to an entry, e.g. podcast episodes or videos
+create table feed_entry_enclosure (
+    id integer not null primary key generated always as identity,
+    feed_entry_id integer not null,
+
+    url text not null,
+    mime text,
+    -- in bytes
+    length bigint,
+    duration_secs integer,
+    -- artwork of the media, e.g. an episode cover or a video thumbnail
+    image text,
+
+    foreign key (feed_entry_id) references feed_entry(id)
+        on delete cascade
+);
+
+create unique index feed_entry_enclosure_url_idx
+on feed_entry_enclosure (feed_entry_id, url);
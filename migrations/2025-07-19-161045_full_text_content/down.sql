@@ -0,0 +1,6 @@
+alter table feed_entry
+    drop column full_content,
+    drop column full_content_fetched_at;
+
+alter table user_feed
+    drop column full_text;
@@ -0,0 +1,9 @@
+-- read the article an entry links to rather than the content shipped by the feed
+alter table user_feed
+    add column full_text boolean not null default false;
+
+-- main content extracted from the linked article, shared by every subscriber
+alter table feed_entry
+    add column full_content text,
+    -- also set when the extraction failed, articles are only downloaded once
+    add column full_content_fetched_at timestamptz;
@@ -0,0 +1,5 @@
+alter table feed
+    drop column icon_id,
+    drop column icon_checked_at;
+
+drop table icon;
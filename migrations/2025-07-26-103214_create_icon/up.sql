@@ -0,0 +1,14 @@
+-- feed icons and site favicons, shared by every feed using the same image
+create table icon (
+    id integer not null primary key generated always as identity,
+    -- sha256 of the content, icons are served under it
+    hash text not null unique,
+    mime text not null,
+    content bytea not null,
+    fetched_at timestamptz not null default now()
+);
+
+alter table feed
+    add column icon_id integer references icon(id) on delete set null,
+    -- also set when no icon was found, icons are looked for again once stale
+    add column icon_checked_at timestamptz;
@@ -0,0 +1,14 @@
+alter table user_feed_entry_meta
+    drop constraint user_feed_entry_meta_feed_entry_id_fkey,
+    add constraint user_feed_entry_meta_feed_entry_id_fkey
+        foreign key (feed_entry_id) references feed_entry(id)
+        on delete restrict;
+
+alter table feed
+    drop column retention_max_age_secs,
+    drop column retention_max_entries;
+
+drop index feed_entry_feed_id_date_idx;
+
+alter table feed_entry
+    drop column last_seen_at;
@@ -0,0 +1,18 @@
+-- entries still listed by the latest fetch of their feed are never purged
+alter table feed_entry
+    add column last_seen_at timestamptz not null default now();
+
+create index feed_entry_feed_id_date_idx
+on feed_entry (feed_id, date);
+
+-- limits of the retention job for a single feed, `null` follows the global ones
+alter table feed
+    add column retention_max_age_secs integer,
+    add column retention_max_entries integer;
+
+-- purged entries take their read state along, starred ones are never purged
+alter table user_feed_entry_meta
+    drop constraint user_feed_entry_meta_feed_entry_id_fkey,
+    add constraint user_feed_entry_meta_feed_entry_id_fkey
+        foreign key (feed_entry_id) references feed_entry(id)
+        on delete cascade;
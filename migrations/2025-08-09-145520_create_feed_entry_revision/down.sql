@@ -0,0 +1,8 @@
+alter table user_feed
+    drop column unread_on_update;
+
+drop table feed_entry_revision;
+
+alter table feed_entry
+    drop column content_hash,
+    drop column updated_at;
@@ -0,0 +1,26 @@
+alter table feed_entry
+    -- hash of the title and content as published, edits of the entry change it
+    add column content_hash text,
+    -- last time the entry was seen edited
+    add column updated_at timestamptz;
+
+-- previous versions of edited entries
+create table feed_entry_revision (
+    id integer not null primary key generated always as identity,
+    feed_entry_id integer not null,
+
+    title text not null,
+    content text,
+    -- when this version was replaced by the next one
+    replaced_at timestamptz not null default now(),
+
+    foreign key (feed_entry_id) references feed_entry(id)
+        on delete cascade
+);
+
+create index feed_entry_revision_feed_entry_id_idx
+on feed_entry_revision (feed_entry_id);
+
+-- show edited entries as unread again
+alter table user_feed
+    add column unread_on_update boolean not null default false;
@@ -0,0 +1,12 @@
+drop index feed_owner_url_idx;
+drop index feed_url_idx;
+
+create unique index feed_url_idx
+on feed (url);
+
+alter table feed
+    drop column owner_id,
+    drop column request_headers,
+    drop column user_agent,
+    drop column credentials,
+    drop column accept_invalid_certs;
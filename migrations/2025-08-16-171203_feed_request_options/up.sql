@@ -0,0 +1,19 @@
+-- feeds with custom request settings belong to a single user and are never shared
+alter table feed
+    add column owner_id integer references user_(id) on delete cascade,
+    -- extra headers sent with each request, as an object of names to values
+    add column request_headers jsonb,
+    add column user_agent text,
+    -- encrypted with `fetcher.credentials-key`
+    add column credentials bytea,
+    -- for hosts serving a self-signed certificate
+    add column accept_invalid_certs boolean not null default false;
+
+drop index feed_url_idx;
+
+-- idx ensures shared feeds are unique
+create unique index feed_url_idx
+on feed (url) where owner_id is null;
+
+create unique index feed_owner_url_idx
+on feed (owner_id, url) where owner_id is not null;
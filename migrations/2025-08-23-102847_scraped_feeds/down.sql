@@ -0,0 +1,4 @@
+alter table feed
+    drop constraint feed_scrape_rules_check,
+    drop column scrape_rules,
+    drop column source;
@@ -0,0 +1,6 @@
+-- how entries of a feed are obtained, scraped feeds are built from a web page
+alter table feed
+    add column source text not null default 'url' check(source in ('url', 'scraped')),
+    -- css selectors of the items of a scraped page
+    add column scrape_rules jsonb,
+    add constraint feed_scrape_rules_check check((source = 'scraped') = (scrape_rules is not null));
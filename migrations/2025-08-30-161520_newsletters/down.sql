@@ -0,0 +1,12 @@
+-- subscriptions restrict the deletion of their feed
+delete from user_feed
+where feed_id in (select id from feed where source = 'newsletter');
+
+delete from feed where source = 'newsletter';
+
+alter table feed
+    drop constraint feed_source_check,
+    add constraint feed_source_check check(source in ('url', 'scraped'));
+
+alter table user_
+    drop column newsletter_token;
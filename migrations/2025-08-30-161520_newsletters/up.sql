@@ -0,0 +1,8 @@
+-- newsletters are received on `<newsletter_token>@<newsletter.domain>`
+alter table user_
+    add column newsletter_token text unique;
+
+-- newsletter feeds gather the mails of a sender, they are never fetched
+alter table feed
+    drop constraint feed_source_check,
+    add constraint feed_source_check check(source in ('url', 'scraped', 'newsletter'));
@@ -0,0 +1,8 @@
+drop index feed_entry_fingerprint_date_idx;
+drop index feed_entry_cluster_id_idx;
+drop index feed_entry_canonical_link_idx;
+
+alter table feed_entry
+    drop column canonical_link,
+    drop column fingerprint,
+    drop column cluster_id;
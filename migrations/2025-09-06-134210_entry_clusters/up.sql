@@ -0,0 +1,18 @@
+-- entries telling the same story across feeds, e.g. syndicated articles
+alter table feed_entry
+    -- link without tracking parameters, used to match entries
+    add column canonical_link text,
+    -- simhash of the title and text, near-duplicates differ by a few bits
+    add column fingerprint bigint,
+    -- id of the first entry of the story, `null` until the entry was clustered
+    add column cluster_id integer;
+
+create index feed_entry_canonical_link_idx
+on feed_entry (canonical_link);
+
+create index feed_entry_cluster_id_idx
+on feed_entry (cluster_id);
+
+-- fingerprints are only compared between entries published around the same time
+create index feed_entry_fingerprint_date_idx
+on feed_entry (date) where fingerprint is not null;
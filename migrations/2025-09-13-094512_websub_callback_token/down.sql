@@ -0,0 +1,2 @@
+alter table websub_subscription
+    drop column callback_token;
@@ -0,0 +1,12 @@
+-- part of the callback url, so that only the hub knows where to verify a subscription
+alter table websub_subscription
+    add column callback_token text;
+
+-- known subscriptions are renewed with a token on the next fetch of their feed
+update websub_subscription
+set callback_token = replace(gen_random_uuid()::text, '-', ''),
+    requested_at = now() - interval '30 days',
+    lease_expires_at = now();
+
+alter table websub_subscription
+    alter column callback_token set not null;
@@ -0,0 +1,2 @@
+alter table fetch_job
+    drop column generation;
@@ -0,0 +1,3 @@
+-- bumped when the feed is queued again, a job completed after being queued again is kept
+alter table fetch_job
+    add column generation integer not null default 0;
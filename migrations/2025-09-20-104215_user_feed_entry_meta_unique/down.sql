@@ -0,0 +1 @@
+drop index user_feed_entry_meta_idx;
@@ -0,0 +1,20 @@
+-- duplicates left by feed moves are merged into the oldest row, keeping read and starred flags
+update user_feed_entry_meta as kept
+set read = duplicates.read, starred = duplicates.starred
+from (
+    select min(id) as id, max(read) as read, max(starred) as starred
+    from user_feed_entry_meta
+    group by user_id, feed_entry_id
+    having count(*) > 1
+) as duplicates
+where kept.id = duplicates.id;
+
+delete from user_feed_entry_meta as meta
+using user_feed_entry_meta as kept
+where kept.user_id = meta.user_id
+    and kept.feed_entry_id = meta.feed_entry_id
+    and kept.id < meta.id;
+
+-- idx ensures a user has a single state per entry
+create unique index user_feed_entry_meta_idx
+on user_feed_entry_meta (user_id, feed_entry_id);
@@ -1,23 +1,43 @@
-use std::{env::var, ops, path::Path, sync::Arc};
+use std::{
+	collections::HashMap,
+	env::var,
+	net::SocketAddr,
+	ops,
+	path::{Path, PathBuf},
+	sync::Arc,
+};
 
 use axum::extract::FromRequestParts;
 use diesel::PgConnection;
 use diesel::r2d2::{ConnectionManager, Pool};
 use diesel_migrations::{EmbeddedMigrations, MigrationHarness, embed_migrations};
 use eyre::WrapErr;
-use eyre::eyre;
+use eyre::{bail, eyre};
 use serde::Deserialize;
-use url::Url;
 
 use crate::{
-	database::{PoolConnection, models::FeedId},
-	fetcher::{FetchTask, Fetcher, FetcherHandle},
+	database::PoolConnection,
+	fetcher::{Fetcher, FetcherHandle},
+	newsletter::Newsletters,
+	retention::{Retention, RetentionHandle},
+	scheduler::Scheduler,
 };
 
 #[derive(Deserialize)]
 pub struct Config {
 	pub server: ServerConfig,
 	pub web: WebConfig,
+	pub scheduler: SchedulerConfig,
+	#[serde(default)]
+	pub fetcher: FetcherConfig,
+	#[serde(default)]
+	pub content: ContentConfig,
+	#[serde(default)]
+	pub proxy: ProxyConfig,
+	#[serde(default)]
+	pub retention: RetentionConfig,
+	#[serde(default)]
+	pub newsletter: NewsletterConfig,
 }
 
 #[derive(Deserialize)]
@@ -26,6 +46,10 @@ pub struct ServerConfig {
 	pub database_url: String,
 
 	pub session_secret: String,
+
+	/// Usernames allowed to use the admin api
+	#[serde(default)]
+	pub admins: Vec<String>,
 }
 
 #[derive(Deserialize)]
@@ -33,6 +57,307 @@ pub struct WebConfig {
 	pub base_url: String,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct FetcherConfig {
+	/// Maximum number of feeds fetched at the same time
+	pub concurrency: usize,
+	/// Maximum number of concurrent requests to the same host
+	pub per_host_concurrency: usize,
+	/// Delay between two requests to the same host, in milliseconds
+	pub per_host_delay: u64,
+
+	/// Delay between two polls of the fetch queue when idle, in seconds
+	pub poll_interval: u64,
+	/// Time given to a worker to complete a job before it is handed to another one, in seconds
+	pub visibility_timeout: u64,
+	/// Number of times a job is attempted before being dropped
+	pub max_attempts: i32,
+
+	/// Non-public networks that can be fetched, e.g. `10.0.0.0/8`
+	pub allowed_networks: Vec<String>,
+	/// Maximum size of a response body once decompressed, in bytes
+	pub max_body_size: usize,
+	/// Maximum number of redirects followed for a single request
+	pub max_redirects: usize,
+	/// Time allowed to establish a connection, in seconds
+	pub connect_timeout: u64,
+	/// Time allowed between two reads of a response, in seconds
+	pub read_timeout: u64,
+	/// Time allowed for a whole request, in seconds
+	pub request_timeout: u64,
+
+	/// Whether to subscribe to the websub hubs advertised by feeds, hubs must be able to reach
+	/// `web.base-url`
+	pub websub: bool,
+	/// Lease asked to hubs, in seconds
+	pub websub_lease: u64,
+
+	/// Key encrypting the credentials of feeds, `server.session_secret` is used when unset
+	pub credentials_key: Option<String>,
+}
+
+impl Default for FetcherConfig {
+	fn default() -> Self {
+		Self {
+			concurrency: 16,
+			per_host_concurrency: 2,
+			per_host_delay: 500,
+
+			poll_interval: 5,
+			visibility_timeout: 5 * 60,
+			max_attempts: 3,
+
+			allowed_networks: Vec::new(),
+			max_body_size: 10 * 1024 * 1024,
+			max_redirects: 10,
+			connect_timeout: 10,
+			read_timeout: 30,
+			request_timeout: 60,
+
+			websub: true,
+			websub_lease: 10 * 24 * 60 * 60,
+
+			credentials_key: None,
+		}
+	}
+}
+
+/// Processing of the html content of entries
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct ContentConfig {
+	/// Rewrites applied to contents before they are sanitized, in order
+	pub rewrites: Vec<RewriteStep>,
+	pub sanitizer: SanitizerConfig,
+}
+
+impl Default for ContentConfig {
+	fn default() -> Self {
+		Self {
+			rewrites: vec![
+				RewriteStep::ResolveUrls,
+				RewriteStep::StripTracking,
+				RewriteStep::LazyImages,
+				RewriteStep::PrivacyEmbeds,
+			],
+			sanitizer: SanitizerConfig::default(),
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RewriteStep {
+	/// Resolve relative urls against the entry link
+	ResolveUrls,
+	/// Remove tracking pixels and `utm_*` like parameters of links
+	StripTracking,
+	/// Load images and embeds once scrolled to
+	LazyImages,
+	/// Move youtube embeds to its privacy-enhanced domain
+	PrivacyEmbeds,
+}
+
+/// Policy of the allowlist based sanitizer, on top of the `ammonia` defaults
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct SanitizerConfig {
+	/// Tags kept on top of the defaults
+	pub extra_tags: Vec<String>,
+	/// Tags of the defaults that are removed
+	pub removed_tags: Vec<String>,
+	/// Attributes kept on top of the defaults, by tag
+	pub extra_tag_attributes: HashMap<String, Vec<String>>,
+	/// Hosts iframes can point to, other embeds are dropped
+	pub iframe_hosts: Vec<String>,
+}
+
+impl SanitizerConfig {
+	/// `ammonia` panics on policies keeping tags it removes along with their content, or links
+	/// `rel` attributes it sets itself
+	fn validate(&self) -> eyre::Result<()> {
+		let removed = ammonia::Builder::default().clone_clean_content_tags();
+		let kept_tags = self
+			.extra_tags
+			.iter()
+			.chain(self.extra_tag_attributes.keys());
+		for tag in kept_tags {
+			if removed.contains(tag.to_ascii_lowercase().as_str()) {
+				bail!("`{tag}` is always removed along with its content");
+			}
+		}
+
+		if self
+			.extra_tag_attributes
+			.get("a")
+			.is_some_and(|attributes| attributes.iter().any(|attribute| attribute == "rel"))
+		{
+			bail!("`rel` of links is set by the sanitizer");
+		}
+
+		Ok(())
+	}
+}
+
+impl Default for SanitizerConfig {
+	fn default() -> Self {
+		Self {
+			extra_tags: Vec::new(),
+			removed_tags: Vec::new(),
+			extra_tag_attributes: HashMap::new(),
+			iframe_hosts: vec![
+				"www.youtube-nocookie.com".to_owned(),
+				"player.vimeo.com".to_owned(),
+			],
+		}
+	}
+}
+
+/// Media of entry contents served through the instance, hiding readers from third-party hosts
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct ProxyConfig {
+	/// Whether images of new entry contents are rewritten to go through the proxy
+	pub enabled: bool,
+	/// Key signing proxied urls, `server.session_secret` is used when unset
+	pub key: Option<String>,
+	/// Directory media are cached in
+	pub cache_dir: PathBuf,
+	/// Size of the cache above which the oldest media are evicted, in bytes
+	pub max_cache_size: u64,
+	/// Time after which cached media are downloaded again, in seconds
+	pub max_age: u64,
+}
+
+impl Default for ProxyConfig {
+	fn default() -> Self {
+		Self {
+			enabled: false,
+			key: None,
+			cache_dir: PathBuf::from("./cache/proxy"),
+			max_cache_size: 512 * 1024 * 1024,
+			max_age: 7 * 24 * 60 * 60,
+		}
+	}
+}
+
+/// Purge of old entries, feeds can override the limits through the admin api
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct RetentionConfig {
+	pub enabled: bool,
+	/// Delay between two runs of the retention job, in seconds
+	pub interval: u64,
+	/// Age above which entries are purged, in seconds, `0` keeps them forever
+	pub max_age: u64,
+	/// Number of entries kept per feed, `0` keeps them all
+	pub max_entries: u64,
+}
+
+impl Default for RetentionConfig {
+	fn default() -> Self {
+		Self {
+			enabled: true,
+			interval: 6 * 60 * 60,
+			max_age: 180 * 24 * 60 * 60,
+			max_entries: 1000,
+		}
+	}
+}
+
+/// Embedded mail server receiving the newsletters of users as feeds
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct NewsletterConfig {
+	pub enabled: bool,
+	/// Address the mail server listens on, usually behind the mail server of the domain
+	pub listen: SocketAddr,
+	pub protocol: MailProtocol,
+	/// Domain of the addresses given to users, `<token>@<domain>`
+	pub domain: String,
+	/// Maximum size of a mail, in bytes
+	pub max_message_size: usize,
+}
+
+impl Default for NewsletterConfig {
+	fn default() -> Self {
+		Self {
+			enabled: false,
+			listen: SocketAddr::from(([127, 0, 0, 1], 2525)),
+			protocol: MailProtocol::Smtp,
+			domain: "feeds.localhost".to_owned(),
+			max_message_size: 10 * 1024 * 1024,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MailProtocol {
+	Smtp,
+	/// Answers each recipient on its own, for mail servers delivering through lmtp
+	Lmtp,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SchedulerConfig {
+	pub auto_refresh: bool,
+
+	/// Delay between two fetches of the same feed when nothing better is known, in seconds
+	#[serde(default = "SchedulerConfig::default_refresh_interval")]
+	pub refresh_interval: u64,
+	/// Lower bound of the adaptive refresh interval, in seconds
+	#[serde(default = "SchedulerConfig::default_min_interval")]
+	pub min_interval: u64,
+	/// Upper bound of the adaptive refresh interval, in seconds
+	#[serde(default = "SchedulerConfig::default_max_interval")]
+	pub max_interval: u64,
+	/// Upper bound of the delay between two fetches of a failing feed, in seconds
+	#[serde(default = "SchedulerConfig::default_max_backoff")]
+	pub max_backoff: u64,
+	/// Upper bound of the random delay added to each feed refresh, in seconds
+	#[serde(default = "SchedulerConfig::default_jitter")]
+	pub jitter: u64,
+	/// Delay between two scans of the feed table, in seconds
+	#[serde(default = "SchedulerConfig::default_tick_interval")]
+	pub tick_interval: u64,
+	/// Delay between two fetches of a feed pushed by a websub hub, in seconds
+	#[serde(default = "SchedulerConfig::default_push_interval")]
+	pub push_interval: u64,
+}
+
+impl SchedulerConfig {
+	const fn default_refresh_interval() -> u64 {
+		60 * 60
+	}
+
+	const fn default_min_interval() -> u64 {
+		15 * 60
+	}
+
+	const fn default_max_interval() -> u64 {
+		24 * 60 * 60
+	}
+
+	const fn default_max_backoff() -> u64 {
+		7 * 24 * 60 * 60
+	}
+
+	const fn default_jitter() -> u64 {
+		5 * 60
+	}
+
+	const fn default_tick_interval() -> u64 {
+		30
+	}
+
+	const fn default_push_interval() -> u64 {
+		3 * 24 * 60 * 60
+	}
+}
+
 impl Config {
 	pub fn load_file_from_env() -> eyre::Result<Self> {
 		let config_path = var("FEEDR_SERVER_CONFIG").unwrap_or_else(|_| "./config.toml".into());
@@ -45,15 +370,51 @@ impl Config {
 			std::fs::read_to_string(config_path).wrap_err("could not read the config file")?;
 		let config = toml::from_str::<Self>(&config_content)
 			.wrap_err("config file does not match the expect structure")?;
+		config.validate()?;
 
 		Ok(config)
 	}
+
+	/// Rejects values that would only fail once the services run
+	fn validate(&self) -> eyre::Result<()> {
+		if self.fetcher.concurrency == 0 {
+			bail!("`fetcher.concurrency` must be at least 1");
+		}
+		if self.fetcher.per_host_concurrency == 0 {
+			bail!("`fetcher.per-host-concurrency` must be at least 1");
+		}
+		if self.fetcher.poll_interval == 0 {
+			bail!("`fetcher.poll-interval` must be at least 1 second");
+		}
+		if self.fetcher.visibility_timeout == 0 {
+			bail!("`fetcher.visibility-timeout` must be at least 1 second");
+		}
+		if self.scheduler.tick_interval == 0 {
+			bail!("`scheduler.tick-interval` must be at least 1 second");
+		}
+		if self.retention.interval == 0 {
+			bail!("`retention.interval` must be at least 1 second");
+		}
+
+		self.content
+			.sanitizer
+			.validate()
+			.wrap_err("invalid sanitizer policy")?;
+
+		Ok(())
+	}
 }
 
 #[derive(Debug)]
 pub struct Ressources {
 	pub database_handle: PoolConnection,
 	pub fetcher_handle: FetcherHandle,
+	pub retention_handle: RetentionHandle,
+
+	/// Usernames allowed to use the admin api
+	pub admins: Vec<String>,
+	/// Domain of the newsletter addresses, `None` when newsletters are not received
+	pub newsletter_domain: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -83,35 +444,114 @@ impl Ressources {
 			.build(manager)
 			.wrap_err("could not build database connection pool")?;
 
+		Self::run_migrations(&db_pool).wrap_err("could not run migrations")?;
+
+		// fetcher and scheduler rely on an up-to-date schema
 		tracing::info!("starting fetcher");
-		let fetcher_handle = Fetcher::setup(db_pool.clone()).wrap_err("could not start fetcher")?;
+		let fetcher_handle =
+			Fetcher::setup(db_pool.clone(), config).wrap_err("could not start fetcher")?;
+
+		if config.retention.enabled {
+			tracing::info!("starting retention job");
+		}
+		let retention_handle = Retention::setup(config.retention.clone(), db_pool.clone());
+
+		if config.newsletter.enabled {
+			tracing::info!(listen = %config.newsletter.listen, "starting newsletter receiver");
+			Newsletters::setup(
+				config.newsletter.clone(),
+				db_pool.clone(),
+				fetcher_handle.clone(),
+			)
+			.wrap_err("could not start newsletter receiver")?;
+		}
 
 		let ressources = Self {
 			database_handle: db_pool,
 			fetcher_handle,
+			retention_handle,
+			admins: config.server.admins.clone(),
+			newsletter_domain: config
+				.newsletter
+				.enabled
+				.then(|| config.newsletter.domain.clone()),
 		};
 
-		ressources
-			.run_migrations()
-			.wrap_err("could not run migrations")?;
+		if config.scheduler.auto_refresh {
+			tracing::info!("starting scheduler");
+			Scheduler::setup(
+				config.scheduler.clone(),
+				ressources.database_handle.clone(),
+				ressources.fetcher_handle.clone(),
+			);
+		}
 
 		Ok(RessourcesRef(Arc::new(ressources)))
 	}
 
-	fn run_migrations(&self) -> eyre::Result<()> {
+	fn run_migrations(db_pool: &PoolConnection) -> eyre::Result<()> {
 		const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
 
-		let mut conn = self.database_handle.get()?;
+		let mut conn = db_pool.get()?;
 
 		conn.run_pending_migrations(MIGRATIONS)
 			.map_err(|err| eyre!("{}", err))?;
 
 		Ok(())
 	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn rejects_zero_concurrency_and_intervals() {
+		let example = || {
+			toml::from_str::<Config>(include_str!("../config.example.toml"))
+				.expect("example config is valid toml")
+		};
+		assert!(example().validate().is_ok());
+
+		let breakages: [fn(&mut Config); 6] = [
+			|config| config.fetcher.concurrency = 0,
+			|config| config.fetcher.per_host_concurrency = 0,
+			|config| config.fetcher.poll_interval = 0,
+			|config| config.fetcher.visibility_timeout = 0,
+			|config| config.scheduler.tick_interval = 0,
+			|config| config.retention.interval = 0,
+		];
+		for breakage in breakages {
+			let mut config = example();
+			breakage(&mut config);
+			assert!(config.validate().is_err());
+		}
+	}
+
+	#[test]
+	fn rejects_sanitizer_panics() {
+		let keeps_script = SanitizerConfig {
+			extra_tags: vec!["script".to_owned()],
+			..SanitizerConfig::default()
+		};
+		assert!(keeps_script.validate().is_err());
 
-	pub async fn fetch_url(&self, feed_id: FeedId, url: Url) -> eyre::Result<()> {
-		self.fetcher_handle
-			.fetch_feed(FetchTask { feed_id, url })
-			.await
+		let styles_style = SanitizerConfig {
+			extra_tag_attributes: HashMap::from([("style".to_owned(), vec!["media".to_owned()])]),
+			..SanitizerConfig::default()
+		};
+		assert!(styles_style.validate().is_err());
+
+		let links_rel = SanitizerConfig {
+			extra_tag_attributes: HashMap::from([("a".to_owned(), vec!["rel".to_owned()])]),
+			..SanitizerConfig::default()
+		};
+		assert!(links_rel.validate().is_err());
+
+		let keeps_mark = SanitizerConfig {
+			extra_tags: vec!["mark".to_owned()],
+			..SanitizerConfig::default()
+		};
+		assert!(keeps_mark.validate().is_ok());
 	}
 }
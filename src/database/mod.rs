@@ -1,12 +1,26 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, collections::HashMap};
 
-use diesel::{dsl, prelude::*, r2d2};
+use diesel::{
+	dsl,
+	prelude::*,
+	r2d2,
+	sql_types::{Array, Integer, Nullable, Text},
+	upsert::excluded,
+};
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
 use url::Url;
 
-use self::models::{Feed, FeedId, UserFeedEntryMetaId, UserFeedId};
-use self::models::{UserFeedFolder, UserFeedFolderId, UserId};
+use self::models::{
+	Feed, FeedEntry, FeedEntryEnclosure, FeedEntryId, FeedEntryRevision, FeedFetchLog, FeedId,
+	FeedMetadata,
+};
+use self::models::{
+	Icon, IconId, NewFeedEntry, NewFeedEntryEnclosure, NewFeedEntryRevision, NewIcon,
+};
+use self::models::{NewFetchJob, UserFeedId};
+use self::models::{User, UserFeedEntryMeta, UserFeedFolder, UserFeedFolderId, UserId};
 
 pub mod models;
 pub mod schema;
@@ -14,13 +28,23 @@ pub mod schema;
 pub type PoolConnection = r2d2::Pool<r2d2::ConnectionManager<PgConnection>>;
 pub type PooledConnection = r2d2::PooledConnection<r2d2::ConnectionManager<PgConnection>>;
 
+/// Entries published further apart are not compared by fingerprint
+const CLUSTER_WINDOW_DAYS: i32 = 3;
+/// Number of bits two fingerprints of the same story may differ by
+pub const CLUSTER_MAX_DISTANCE: i32 = 6;
+
+define_sql_function!(fn coalesce(a: Nullable<Text>, b: Nullable<Text>) -> Nullable<Text>);
+define_sql_function!(fn greatest(a: Integer, b: Integer) -> Integer);
+
 impl Feed<'_> {
+	/// Shared feed behind an url, feeds owned by a user are never resolved
 	pub fn resolve_or_create(url: &Url, conn: &mut PooledConnection) -> QueryResult<FeedId> {
 		conn.transaction(|conn| {
 			use crate::database::schema::*;
 			let id = feed::table
 				.select(feed::id)
 				.filter(feed::url.eq(url.as_str()))
+				.filter(feed::owner_id.is_null())
 				.get_result::<FeedId>(conn)
 				.optional()?;
 
@@ -35,6 +59,281 @@ impl Feed<'_> {
 			)
 		})
 	}
+
+	/// Creates a feed built from a web page with css selectors, scraped feeds are never shared
+	pub fn create_scraped(
+		user_id: UserId,
+		url: &Url,
+		rules: &serde_json::Value,
+		conn: &mut PooledConnection,
+	) -> QueryResult<FeedId> {
+		use crate::database::schema::*;
+
+		dsl::insert_into(feed::table)
+			.values((
+				feed::url.eq(url.as_str()),
+				feed::status.eq("fetching"),
+				feed::owner_id.eq(user_id),
+				feed::source.eq("scraped"),
+				feed::scrape_rules.eq(rules),
+			))
+			.returning(feed::id)
+			.get_result(conn)
+	}
+
+	/// Moves a feed to a new url, merging it into the feed already using that url if any
+	///
+	/// Returns the id of the feed now holding the url. Feeds are only merged with feeds of the same
+	/// owner.
+	pub fn relocate(
+		feed_id: FeedId,
+		url: &Url,
+		conn: &mut PooledConnection,
+	) -> QueryResult<FeedId> {
+		use crate::database::schema::*;
+
+		let owner_id = feed::table
+			.find(feed_id)
+			.select(feed::owner_id)
+			.get_result::<Option<UserId>>(conn)?;
+
+		let target = feed::table
+			.select(feed::id)
+			.filter(feed::url.eq(url.as_str()))
+			.filter(feed::owner_id.is_not_distinct_from(owner_id))
+			.filter(feed::id.ne(feed_id))
+			.get_result::<FeedId>(conn)
+			.optional()?;
+
+		let Some(target) = target else {
+			dsl::update(feed::table.find(feed_id))
+				.set(feed::url.eq(url.as_str()))
+				.execute(conn)?;
+			return Ok(feed_id);
+		};
+
+		// entries known by both feeds are deduplicated, user state follows the kept entry
+		move_entry_meta(feed_id, target, None, conn)?;
+
+		let target_guids = feed_entry::table
+			.select(feed_entry::guid)
+			.filter(feed_entry::feed_id.eq(target))
+			.load::<String>(conn)?;
+		dsl::delete(
+			feed_entry::table
+				.filter(feed_entry::feed_id.eq(feed_id))
+				.filter(feed_entry::guid.eq_any(target_guids)),
+		)
+		.execute(conn)?;
+		dsl::update(feed_entry::table.filter(feed_entry::feed_id.eq(feed_id)))
+			.set(feed_entry::feed_id.eq(target))
+			.execute(conn)?;
+
+		// users already subscribed to the target keep their own subscription
+		let target_users = user_feed::table
+			.select(user_feed::user_id)
+			.filter(user_feed::feed_id.eq(target))
+			.load::<UserId>(conn)?;
+		dsl::delete(
+			user_feed::table
+				.filter(user_feed::feed_id.eq(feed_id))
+				.filter(user_feed::user_id.eq_any(target_users)),
+		)
+		.execute(conn)?;
+		dsl::update(user_feed::table.filter(user_feed::feed_id.eq(feed_id)))
+			.set(user_feed::feed_id.eq(target))
+			.execute(conn)?;
+
+		dsl::delete(feed::table.find(feed_id)).execute(conn)?;
+
+		Ok(target)
+	}
+
+	/// Moves a feed to a new url unless another feed of the same owner already uses it
+	///
+	/// Unlike [`Feed::relocate`], feeds are never merged. Returns whether the feed was moved.
+	pub fn rename(feed_id: FeedId, url: &Url, conn: &mut PooledConnection) -> QueryResult<bool> {
+		use crate::database::schema::*;
+
+		let owner_id = feed::table
+			.find(feed_id)
+			.select(feed::owner_id)
+			.get_result::<Option<UserId>>(conn)?;
+
+		let taken = dsl::select(dsl::exists(
+			feed::table
+				.filter(feed::url.eq(url.as_str()))
+				.filter(feed::owner_id.is_not_distinct_from(owner_id))
+				.filter(feed::id.ne(feed_id)),
+		))
+		.get_result::<bool>(conn)?;
+		if taken {
+			return Ok(false);
+		}
+
+		dsl::update(feed::table.find(feed_id))
+			.set(feed::url.eq(url.as_str()))
+			.execute(conn)?;
+		Ok(true)
+	}
+
+	/// Gives a user their own copy of a subscribed feed, so that the settings of its requests are
+	/// not shared with other subscribers
+	///
+	/// Entries and the user state attached to them are copied over. Returns the id of the private
+	/// feed, `None` when the user has no such subscription.
+	pub fn make_private(
+		user_id: UserId,
+		user_feed_id: UserFeedId,
+		conn: &mut PooledConnection,
+	) -> QueryResult<Option<FeedId>> {
+		use crate::database::schema::*;
+
+		conn.transaction(|conn| {
+			let shared = user_feed::table
+				.inner_join(feed::table)
+				.filter(user_feed::id.eq(user_feed_id))
+				.filter(user_feed::user_id.eq(user_id))
+				.select((
+					feed::id,
+					feed::url,
+					feed::owner_id,
+					FeedMetadata::as_select(),
+				))
+				.get_result::<(FeedId, String, Option<UserId>, FeedMetadata)>(conn)
+				.optional()?;
+			let Some((feed_id, url, owner_id, metadata)) = shared else {
+				return Ok(None);
+			};
+			if owner_id.is_some() {
+				return Ok(Some(feed_id));
+			}
+
+			// another subscription of the user to the same url may already have its copy
+			let private = feed::table
+				.select(feed::id)
+				.filter(feed::url.eq(&url))
+				.filter(feed::owner_id.eq(user_id))
+				.filter(feed::source.eq("url"))
+				.get_result::<FeedId>(conn)
+				.optional()?;
+			let private = match private {
+				Some(private) => private,
+				None => dsl::insert_into(feed::table)
+					.values((
+						feed::url.eq(&url),
+						feed::status.eq("fetching"),
+						feed::owner_id.eq(user_id),
+					))
+					.returning(feed::id)
+					.get_result::<FeedId>(conn)?,
+			};
+			dsl::update(feed::table.find(private))
+				.set(&metadata)
+				.execute(conn)?;
+
+			// copies stay in the story of the entry they were made from
+			diesel::sql_query(
+				"insert into feed_entry (feed_id, date, title, content, guid, link, author, \
+				full_content, full_content_fetched_at, last_seen_at, content_hash, updated_at, \
+				canonical_link, fingerprint, cluster_id) \
+				select $2, date, title, content, guid, link, author, full_content, \
+				full_content_fetched_at, last_seen_at, content_hash, updated_at, \
+				canonical_link, fingerprint, coalesce(cluster_id, id) \
+				from feed_entry where feed_id = $1 \
+				on conflict do nothing",
+			)
+			.bind::<Integer, _>(feed_id)
+			.bind::<Integer, _>(private)
+			.execute(conn)?;
+
+			diesel::sql_query(
+				"insert into feed_entry_enclosure (feed_entry_id, url, mime, length, \
+				duration_secs, image) \
+				select target.id, enclosure.url, enclosure.mime, enclosure.length, \
+				enclosure.duration_secs, enclosure.image \
+				from feed_entry_enclosure as enclosure \
+				join feed_entry as source on source.id = enclosure.feed_entry_id \
+				join feed_entry as target on target.guid = source.guid \
+				where source.feed_id = $1 and target.feed_id = $2 \
+				on conflict do nothing",
+			)
+			.bind::<Integer, _>(feed_id)
+			.bind::<Integer, _>(private)
+			.execute(conn)?;
+
+			// entries of a copy made earlier already have their own history
+			diesel::sql_query(
+				"insert into feed_entry_revision (feed_entry_id, title, content, replaced_at) \
+				select target.id, revision.title, revision.content, revision.replaced_at \
+				from feed_entry_revision as revision \
+				join feed_entry as source on source.id = revision.feed_entry_id \
+				join feed_entry as target on target.guid = source.guid \
+				where source.feed_id = $1 and target.feed_id = $2 \
+				and not exists (select from feed_entry_revision as known \
+				where known.feed_entry_id = target.id)",
+			)
+			.bind::<Integer, _>(feed_id)
+			.bind::<Integer, _>(private)
+			.execute(conn)?;
+
+			move_entry_meta(feed_id, private, Some(user_id), conn)?;
+
+			dsl::update(user_feed::table.find(user_feed_id))
+				.set(user_feed::feed_id.eq(private))
+				.execute(conn)?;
+
+			Ok(Some(private))
+		})
+	}
+}
+
+/// Points the state users have on the entries of a feed to the entries of another feed sharing
+/// their guid, only for the given user if any
+///
+/// Users with a state on both entries keep a single one, read or starred when either was.
+fn move_entry_meta(
+	feed_id: FeedId,
+	target: FeedId,
+	user_id: Option<UserId>,
+	conn: &mut PooledConnection,
+) -> QueryResult<()> {
+	const MATCHING: &str = "moved.feed_entry_id = source.id and source.feed_id = $1 \
+		and target.feed_id = $2 and target.guid = source.guid \
+		and ($3 is null or moved.user_id = $3)";
+
+	diesel::sql_query(format!(
+		"update user_feed_entry_meta as kept \
+		set read = greatest(kept.read, moved.read), starred = greatest(kept.starred, moved.starred) \
+		from user_feed_entry_meta as moved, feed_entry as source, feed_entry as target \
+		where {MATCHING} and kept.feed_entry_id = target.id and kept.user_id = moved.user_id"
+	))
+	.bind::<Integer, _>(feed_id)
+	.bind::<Integer, _>(target)
+	.bind::<Nullable<Integer>, _>(user_id)
+	.execute(conn)?;
+
+	diesel::sql_query(format!(
+		"delete from user_feed_entry_meta as moved \
+		using feed_entry as source, feed_entry as target, user_feed_entry_meta as kept \
+		where {MATCHING} and kept.feed_entry_id = target.id and kept.user_id = moved.user_id"
+	))
+	.bind::<Integer, _>(feed_id)
+	.bind::<Integer, _>(target)
+	.bind::<Nullable<Integer>, _>(user_id)
+	.execute(conn)?;
+
+	diesel::sql_query(format!(
+		"update user_feed_entry_meta as moved set feed_entry_id = target.id \
+		from feed_entry as source, feed_entry as target \
+		where {MATCHING}"
+	))
+	.bind::<Integer, _>(feed_id)
+	.bind::<Integer, _>(target)
+	.bind::<Nullable<Integer>, _>(user_id)
+	.execute(conn)?;
+
+	Ok(())
 }
 
 /// A mix between `user_feed` and feed with `user_feed(id)` resolved
@@ -44,22 +343,41 @@ pub struct ResolvedUserFeed<'a> {
 
 	pub url: Cow<'a, str>,
 	pub status: String,
+	pub last_error: Option<Cow<'a, str>>,
 
-	pub title: Cow<'a, str>,
+	/// Set by the user, overrides the upstream values
+	pub title: Option<Cow<'a, str>>,
 	pub description: Option<Cow<'a, str>>,
+	pub full_text: bool,
+
+	pub upstream: FeedMetadata<'a>,
+	/// Hash of the icon of the feed, served at `/icons/{hash}`
+	pub icon: Option<Cow<'a, str>>,
 }
 
 impl ResolvedUserFeed<'_> {
+	/// Title shown to the user, falling back to the url for feeds not yet fetched
+	pub fn display_title(&self) -> &str {
+		self.title
+			.as_deref()
+			.or(self.upstream.title.as_deref())
+			.unwrap_or(&self.url)
+	}
+
 	pub fn resolve_all(user_id: UserId, conn: &mut PooledConnection) -> QueryResult<Vec<Self>> {
 		use crate::database::schema::*;
 		user_feed::table
-			.inner_join(feed::table)
+			.inner_join(feed::table.left_join(icon::table))
 			.select((
 				user_feed::id,
 				feed::url,
 				feed::status,
+				feed::last_error,
 				user_feed::title,
 				user_feed::description,
+				user_feed::full_text,
+				FeedMetadata::as_select(),
+				icon::hash.nullable(),
 			))
 			.filter(user_feed::user_id.eq(user_id))
 			.load::<ResolvedUserFeed>(conn)
@@ -71,17 +389,24 @@ impl ResolvedUserFeed<'_> {
 	) -> QueryResult<Vec<(String, Vec<Self>)>> {
 		use crate::database::schema::*;
 		let feeds = user_feed::table
-			.inner_join(feed::table)
+			.inner_join(feed::table.left_join(icon::table))
 			.left_join(user_feed_folder::table)
-			.order_by((user_feed_folder::title, user_feed::title))
+			.order_by((
+				user_feed_folder::title,
+				coalesce(user_feed::title, feed::title),
+			))
 			.select((
 				user_feed_folder::title.nullable(),
 				(
 					user_feed::id,
 					feed::url,
 					feed::status,
+					feed::last_error,
 					user_feed::title,
 					user_feed::description,
+					user_feed::full_text,
+					FeedMetadata::as_select(),
+					icon::hash.nullable(),
 				),
 			))
 			.filter(user_feed::user_id.eq(user_id))
@@ -106,31 +431,597 @@ impl ResolvedUserFeed<'_> {
 	}
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Queryable)]
+/// Fetch state of a user feed along with its latest fetch attempts
+#[derive(Debug, Clone, Serialize)]
+pub struct ResolvedFeedHealth<'a> {
+	pub status: Cow<'a, str>,
+	pub last_error: Option<Cow<'a, str>>,
+	pub consecutive_failures: i32,
+
+	#[serde(with = "time::serde::rfc3339::option")]
+	pub last_success_at: Option<OffsetDateTime>,
+	#[serde(with = "time::serde::rfc3339::option")]
+	pub next_fetch_at: Option<OffsetDateTime>,
+
+	pub fetch_logs: Vec<FeedFetchLog<'a>>,
+}
+
+impl ResolvedFeedHealth<'_> {
+	/// Number of fetch attempts returned
+	const FETCH_LOG_LIMIT: i64 = 20;
+
+	pub fn resolve(
+		user_id: UserId,
+		user_feed_id: UserFeedId,
+		conn: &mut PooledConnection,
+	) -> QueryResult<Option<Self>> {
+		use crate::database::schema::*;
+		let feed = user_feed::table
+			.inner_join(feed::table)
+			.filter(
+				user_feed::id
+					.eq(user_feed_id)
+					.and(user_feed::user_id.eq(user_id)),
+			)
+			.select(Feed::as_select())
+			.get_result::<Feed>(conn)
+			.optional()?;
+
+		let Some(feed) = feed else {
+			return Ok(None);
+		};
+
+		let fetch_logs = feed_fetch_log::table
+			.filter(feed_fetch_log::feed_id.eq(feed.id))
+			.order_by(feed_fetch_log::fetched_at.desc())
+			.limit(Self::FETCH_LOG_LIMIT)
+			.select(FeedFetchLog::as_select())
+			.load(conn)?;
+
+		Ok(Some(Self {
+			status: feed.status,
+			last_error: feed.last_error,
+			consecutive_failures: feed.consecutive_failures,
+			last_success_at: feed.last_success_at,
+			next_fetch_at: feed.next_fetch_at,
+			fetch_logs,
+		}))
+	}
+}
+
+impl NewFeedEntry<'_> {
+	/// Inserts entries, updating the ones that share a `guid` with an already stored entry of
+	/// the same feed
+	///
+	/// Returns the ids of the stored entries along with their `guid`.
+	pub fn upsert_all(
+		entries: &[Self],
+		conn: &mut PooledConnection,
+	) -> QueryResult<Vec<(FeedEntryId, String)>> {
+		use crate::database::schema::*;
+
+		// a batch cannot update the same row twice, feeds sometimes repeat their guids
+		let entries = entries
+			.iter()
+			.unique_by(|entry| (entry.feed_id, &entry.guid))
+			.collect::<Vec<_>>();
+
+		dsl::insert_into(feed_entry::table)
+			.values(entries)
+			.on_conflict((feed_entry::feed_id, feed_entry::guid))
+			.do_update()
+			.set((
+				feed_entry::title.eq(excluded(feed_entry::title)),
+				feed_entry::content.eq(excluded(feed_entry::content)),
+				feed_entry::link.eq(excluded(feed_entry::link)),
+				feed_entry::author.eq(excluded(feed_entry::author)),
+				feed_entry::content_hash.eq(excluded(feed_entry::content_hash)),
+				feed_entry::canonical_link.eq(excluded(feed_entry::canonical_link)),
+				feed_entry::fingerprint.eq(excluded(feed_entry::fingerprint)),
+				feed_entry::last_seen_at.eq(dsl::now),
+			))
+			.returning((feed_entry::id, feed_entry::guid))
+			.get_results(conn)
+	}
+}
+
+impl FeedEntry<'_> {
+	/// Flags entries edited by their publisher, they become unread again for the subscribers who
+	/// asked for it
+	pub fn mark_edited(
+		feed_id: FeedId,
+		feed_entry_ids: &[FeedEntryId],
+		conn: &mut PooledConnection,
+	) -> QueryResult<()> {
+		use crate::database::schema::*;
+
+		dsl::update(feed_entry::table.filter(feed_entry::id.eq_any(feed_entry_ids)))
+			.set(feed_entry::updated_at.eq(dsl::now))
+			.execute(conn)?;
+
+		dsl::update(
+			user_feed_entry_meta::table
+				.filter(user_feed_entry_meta::feed_entry_id.eq_any(feed_entry_ids))
+				.filter(
+					user_feed_entry_meta::user_id.eq_any(
+						user_feed::table
+							.select(user_feed::user_id)
+							.filter(user_feed::feed_id.eq(feed_id))
+							.filter(user_feed::unread_on_update),
+					),
+				),
+		)
+		.set(user_feed_entry_meta::read.eq(0))
+		.execute(conn)?;
+
+		Ok(())
+	}
+
+	/// Joins entries to the story of a matching entry of another feed, entries without match
+	/// start their own story
+	///
+	/// Entries match when they share their canonical link, or when their fingerprints differ by a
+	/// few bits and they were published around the same time. Entries of private feeds only match
+	/// entries of shared feeds and of feeds of the same owner, shared feeds never match private
+	/// ones. Entries already clustered are left as is.
+	pub fn cluster(
+		feed_entry_ids: &[FeedEntryId],
+		conn: &mut PooledConnection,
+	) -> QueryResult<usize> {
+		diesel::sql_query(
+			"update feed_entry as entry set cluster_id = coalesce(( \
+				select min(coalesce(other.cluster_id, other.id)) from feed_entry as other \
+				join feed as other_feed on other_feed.id = other.feed_id \
+				where other.feed_id <> entry.feed_id \
+				and (other_feed.owner_id is null or other_feed.owner_id = entry_feed.owner_id) \
+				and (other.canonical_link = entry.canonical_link \
+				or (other.fingerprint is not null \
+				and other.date between entry.date - make_interval(days => $2) \
+				and entry.date + make_interval(days => $2) \
+				and bit_count((other.fingerprint # entry.fingerprint)::bit(64)) <= $3))), \
+			entry.id) \
+			from feed as entry_feed \
+			where entry_feed.id = entry.feed_id \
+			and entry.id = any($1) and entry.cluster_id is null",
+		)
+		.bind::<Array<Integer>, _>(feed_entry_ids)
+		.bind::<Integer, _>(CLUSTER_WINDOW_DAYS)
+		.bind::<Integer, _>(CLUSTER_MAX_DISTANCE)
+		.execute(conn)
+	}
+}
+
+impl NewFeedEntryRevision<'_> {
+	/// Keeps the stored version of entries whose content changed, entries stored before content
+	/// hashes existed are not compared
+	///
+	/// Returns the ids of the edited entries.
+	pub fn save_edited(
+		feed_id: FeedId,
+		entries: &[NewFeedEntry],
+		conn: &mut PooledConnection,
+	) -> QueryResult<Vec<FeedEntryId>> {
+		use crate::database::schema::*;
+
+		let guids = entries
+			.iter()
+			.map(|entry| entry.guid.as_ref())
+			.collect::<Vec<_>>();
+		let stored = feed_entry::table
+			.select((
+				feed_entry::id,
+				feed_entry::guid,
+				feed_entry::content_hash.assume_not_null(),
+				feed_entry::title,
+				feed_entry::content,
+			))
+			.filter(feed_entry::feed_id.eq(feed_id))
+			.filter(feed_entry::guid.eq_any(guids))
+			.filter(feed_entry::content_hash.is_not_null())
+			.load::<(FeedEntryId, String, String, String, Option<String>)>(conn)?;
+
+		// like upserts, only the first entry of a repeated guid counts
+		let revisions = stored
+			.into_iter()
+			.filter(|(_, guid, hash, _, _)| {
+				entries
+					.iter()
+					.find(|entry| entry.guid == guid.as_str())
+					.is_some_and(|entry| entry.content_hash != hash.as_str())
+			})
+			.map(
+				|(feed_entry_id, _, _, title, content)| NewFeedEntryRevision {
+					feed_entry_id,
+					title: Cow::Owned(title),
+					content: content.map(Cow::Owned),
+				},
+			)
+			.collect::<Vec<_>>();
+
+		dsl::insert_into(feed_entry_revision::table)
+			.values(&revisions)
+			.execute(conn)?;
+
+		Ok(revisions
+			.iter()
+			.map(|revision| revision.feed_entry_id)
+			.collect())
+	}
+}
+
+impl NewFeedEntryEnclosure<'_> {
+	/// Replaces the enclosures of the given entries
+	pub fn replace_all(
+		feed_entry_ids: &[FeedEntryId],
+		enclosures: &[Self],
+		conn: &mut PooledConnection,
+	) -> QueryResult<usize> {
+		use crate::database::schema::*;
+
+		dsl::delete(
+			feed_entry_enclosure::table
+				.filter(feed_entry_enclosure::feed_entry_id.eq_any(feed_entry_ids)),
+		)
+		.execute(conn)?;
+
+		// entries sometimes list the same media twice, e.g. as an enclosure and in media rss
+		let enclosures = enclosures
+			.iter()
+			.unique_by(|enclosure| (enclosure.feed_entry_id, &enclosure.url))
+			.collect::<Vec<_>>();
+
+		dsl::insert_into(feed_entry_enclosure::table)
+			.values(enclosures)
+			.execute(conn)
+	}
+}
+
+impl Icon<'_> {
+	pub fn by_hash(hash: &str, conn: &mut PooledConnection) -> QueryResult<Option<Icon<'static>>> {
+		use crate::database::schema::*;
+
+		icon::table
+			.filter(icon::hash.eq(hash))
+			.select(Icon::as_select())
+			.get_result(conn)
+			.optional()
+	}
+
+	/// Removes the given icons when no feed points to them anymore
+	pub fn delete_orphans(icon_ids: &[IconId], conn: &mut PooledConnection) -> QueryResult<usize> {
+		use crate::database::schema::*;
+
+		dsl::delete(
+			icon::table
+				.filter(icon::id.eq_any(icon_ids))
+				.filter(dsl::not(dsl::exists(
+					feed::table.filter(feed::icon_id.eq(icon::id.nullable())),
+				))),
+		)
+		.execute(conn)
+	}
+
+	/// Removes every icon no feed points to anymore
+	pub fn delete_unused(conn: &mut PooledConnection) -> QueryResult<usize> {
+		use crate::database::schema::*;
+
+		dsl::delete(icon::table.filter(dsl::not(dsl::exists(
+			feed::table.filter(feed::icon_id.eq(icon::id.nullable())),
+		))))
+		.execute(conn)
+	}
+}
+
+impl NewIcon<'_> {
+	/// Stores the icon, or refreshes the one with the same content
+	pub fn upsert(&self, conn: &mut PooledConnection) -> QueryResult<IconId> {
+		use crate::database::schema::*;
+
+		dsl::insert_into(icon::table)
+			.values(self)
+			.on_conflict(icon::hash)
+			.do_update()
+			.set(icon::fetched_at.eq(excluded(icon::fetched_at)))
+			.returning(icon::id)
+			.get_result(conn)
+	}
+}
+
+impl NewFetchJob {
+	/// Queues the feeds, already queued ones keep their place but can be bumped to a higher
+	/// priority
+	pub fn enqueue_all(jobs: &[Self], conn: &mut PooledConnection) -> QueryResult<usize> {
+		use crate::database::schema::*;
+
+		// a batch cannot update the same row twice
+		let jobs = jobs.iter().unique_by(|job| job.feed_id).collect::<Vec<_>>();
+
+		dsl::insert_into(fetch_job::table)
+			.values(jobs)
+			.on_conflict(fetch_job::feed_id)
+			.do_update()
+			.set((
+				fetch_job::priority
+					.eq(greatest(fetch_job::priority, excluded(fetch_job::priority))),
+				// a job already claimed is kept once completed
+				fetch_job::generation.eq(fetch_job::generation + 1),
+			))
+			.execute(conn)
+	}
+}
+
+/// A `feed_entry` of one of the user feeds with optional `user_feed_entry_meta` resolved
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ResolvedUserEntry<'a> {
-	pub id: UserFeedEntryMetaId,
+	pub id: FeedEntryId,
+	pub feed_id: UserFeedId,
+
+	#[serde(with = "time::serde::rfc3339")]
+	pub date: OffsetDateTime,
+	/// Last time the publisher was seen editing the entry, its revisions hold previous versions
+	#[serde(with = "time::serde::rfc3339::option")]
+	pub updated_at: Option<OffsetDateTime>,
 
 	pub title: Cow<'a, str>,
 	pub content: Option<Cow<'a, str>>,
+	pub link: Option<Cow<'a, str>>,
+	pub author: Option<Cow<'a, str>>,
+	/// Main content of the linked article, once downloaded for a full text subscription
+	pub full_content: Option<Cow<'a, str>>,
 
-	pub read: i32,
-	pub starred: i32,
+	pub read: Option<i32>,
+	pub starred: Option<i32>,
+
+	pub enclosures: Vec<FeedEntryEnclosure<'a>>,
+
+	/// First entry of the story, shared with the duplicates of this entry in other feeds
+	pub cluster_id: FeedEntryId,
+	/// Entries of the same story left out when entries are collapsed
+	pub duplicates: Vec<FeedEntryId>,
 }
 
+/// Columns of a [`ResolvedUserEntry`], enclosures are loaded with a separate query
+type ResolvedUserEntryRow<'a> = (
+	FeedEntryId,
+	UserFeedId,
+	OffsetDateTime,
+	Option<OffsetDateTime>,
+	Cow<'a, str>,
+	Option<Cow<'a, str>>,
+	Option<Cow<'a, str>>,
+	Option<Cow<'a, str>>,
+	Option<Cow<'a, str>>,
+	Option<i32>,
+	Option<i32>,
+	Option<FeedEntryId>,
+);
+
 impl ResolvedUserEntry<'_> {
 	pub fn resolve_all(user_id: UserId, conn: &mut PooledConnection) -> QueryResult<Vec<Self>> {
 		use crate::database::schema::*;
-		user_feed_entry_meta::table
-			.inner_join(feed_entry::table)
+		let rows = user_feed::table
+			.inner_join(feed_entry::table.on(feed_entry::feed_id.eq(user_feed::feed_id)))
+			.left_join(
+				user_feed_entry_meta::table.on(user_feed_entry_meta::feed_entry_id
+					.eq(feed_entry::id)
+					.and(user_feed_entry_meta::user_id.eq(user_id))),
+			)
+			.select((
+				feed_entry::id,
+				user_feed::id,
+				feed_entry::date,
+				feed_entry::updated_at,
+				feed_entry::title,
+				feed_entry::content,
+				feed_entry::link,
+				feed_entry::author,
+				feed_entry::full_content,
+				user_feed_entry_meta::read.nullable(),
+				user_feed_entry_meta::starred.nullable(),
+				feed_entry::cluster_id,
+			))
+			.filter(user_feed::user_id.eq(user_id))
+			.order_by(feed_entry::date.desc())
+			.load::<ResolvedUserEntryRow>(conn)?;
+
+		let entry_ids = rows.iter().map(|row| row.0).collect::<Vec<_>>();
+		let mut enclosures = feed_entry_enclosure::table
+			.select(FeedEntryEnclosure::as_select())
+			.filter(feed_entry_enclosure::feed_entry_id.eq_any(&entry_ids))
+			.order_by(feed_entry_enclosure::id)
+			.load::<FeedEntryEnclosure>(conn)?
+			.into_iter()
+			.into_group_map_by(|enclosure| enclosure.feed_entry_id);
+
+		Ok(rows
+			.into_iter()
+			.map(
+				|(
+					id,
+					feed_id,
+					date,
+					updated_at,
+					title,
+					content,
+					link,
+					author,
+					full_content,
+					read,
+					starred,
+					cluster_id,
+				)| {
+					Self {
+						id,
+						feed_id,
+						date,
+						updated_at,
+						title,
+						content,
+						link,
+						author,
+						full_content,
+						read,
+						starred,
+						enclosures: enclosures.remove(&id).unwrap_or_default(),
+						cluster_id: cluster_id.unwrap_or(id),
+						duplicates: Vec::new(),
+					}
+				},
+			)
+			.collect())
+	}
+
+	/// Keeps the first entry of each story, the others are listed as its duplicates
+	pub fn collapse(entries: Vec<Self>) -> Vec<Self> {
+		let mut collapsed = Vec::<Self>::with_capacity(entries.len());
+		let mut stories = HashMap::<FeedEntryId, usize>::new();
+
+		for entry in entries {
+			if let Some(index) = stories.get(&entry.cluster_id) {
+				collapsed[*index].duplicates.push(entry.id);
+			} else {
+				stories.insert(entry.cluster_id, collapsed.len());
+				collapsed.push(entry);
+			}
+		}
+
+		collapsed
+	}
+}
+
+/// Versions of an entry the user can read, oldest first
+#[derive(Debug, Clone, Serialize)]
+pub struct ResolvedEntryRevisions<'a> {
+	pub revisions: Vec<FeedEntryRevision<'a>>,
+
+	pub title: Cow<'a, str>,
+	pub content: Option<Cow<'a, str>>,
+	#[serde(with = "time::serde::rfc3339::option")]
+	pub updated_at: Option<OffsetDateTime>,
+}
+
+impl ResolvedEntryRevisions<'_> {
+	/// `None` when the entry does not belong to one of the user feeds
+	pub fn resolve(
+		user_id: UserId,
+		feed_entry_id: FeedEntryId,
+		conn: &mut PooledConnection,
+	) -> QueryResult<Option<Self>> {
+		use crate::database::schema::*;
+
+		let current = feed_entry::table
+			.find(feed_entry_id)
+			.filter(dsl::exists(
+				user_feed::table
+					.filter(user_feed::feed_id.eq(feed_entry::feed_id))
+					.filter(user_feed::user_id.eq(user_id)),
+			))
 			.select((
-				user_feed_entry_meta::id,
 				feed_entry::title,
 				feed_entry::content,
-				user_feed_entry_meta::read,
-				user_feed_entry_meta::starred,
+				feed_entry::updated_at,
 			))
-			.filter(user_feed_entry_meta::user_id.eq(user_id))
-			.load::<ResolvedUserEntry>(conn)
+			.get_result::<(Cow<'_, str>, Option<Cow<'_, str>>, Option<OffsetDateTime>)>(conn)
+			.optional()?;
+
+		let Some((title, content, updated_at)) = current else {
+			return Ok(None);
+		};
+
+		let revisions = feed_entry_revision::table
+			.filter(feed_entry_revision::feed_entry_id.eq(feed_entry_id))
+			.order_by(feed_entry_revision::replaced_at.asc())
+			.select(FeedEntryRevision::as_select())
+			.load(conn)?;
+
+		Ok(Some(Self {
+			revisions,
+			title,
+			content,
+			updated_at,
+		}))
+	}
+}
+
+impl UserFeedEntryMeta {
+	/// Marks an entry as read or unread along with its duplicates in the other feeds of the user
+	///
+	/// Returns `None` when the entry does not belong to one of the user feeds.
+	pub fn mark_story_read(
+		user_id: UserId,
+		feed_entry_id: FeedEntryId,
+		read: bool,
+		conn: &mut PooledConnection,
+	) -> QueryResult<Option<usize>> {
+		use crate::database::schema::*;
+
+		conn.transaction(|conn| {
+			let cluster_id = feed_entry::table
+				.find(feed_entry_id)
+				.filter(dsl::exists(
+					user_feed::table
+						.filter(user_feed::feed_id.eq(feed_entry::feed_id))
+						.filter(user_feed::user_id.eq(user_id)),
+				))
+				.select(feed_entry::cluster_id)
+				.get_result::<Option<FeedEntryId>>(conn)
+				.optional()?;
+			let Some(cluster_id) = cluster_id else {
+				return Ok(None);
+			};
+			// entries that were not clustered yet are their own story
+			let cluster_id = cluster_id.unwrap_or(feed_entry_id);
+
+			let story = feed_entry::table
+				.select(feed_entry::id)
+				.filter(dsl::exists(
+					user_feed::table
+						.filter(user_feed::feed_id.eq(feed_entry::feed_id))
+						.filter(user_feed::user_id.eq(user_id)),
+				))
+				.filter(
+					feed_entry::id
+						.eq(cluster_id)
+						.or(feed_entry::cluster_id.eq(cluster_id)),
+				)
+				.load::<FeedEntryId>(conn)?;
+
+			let read = i32::from(read);
+			let updated = dsl::update(
+				user_feed_entry_meta::table
+					.filter(user_feed_entry_meta::user_id.eq(user_id))
+					.filter(user_feed_entry_meta::feed_entry_id.eq_any(&story)),
+			)
+			.set(user_feed_entry_meta::read.eq(read))
+			.returning(user_feed_entry_meta::feed_entry_id)
+			.get_results::<FeedEntryId>(conn)?;
+
+			// entries the user never interacted with have no meta yet
+			let missing = story
+				.iter()
+				.filter(|id| !updated.contains(id))
+				.map(|id| {
+					(
+						user_feed_entry_meta::user_id.eq(user_id),
+						user_feed_entry_meta::feed_entry_id.eq(*id),
+						user_feed_entry_meta::read.eq(read),
+						user_feed_entry_meta::starred.eq(0),
+					)
+				})
+				.collect::<Vec<_>>();
+			// a concurrent request may have created the meta in the meantime
+			dsl::insert_into(user_feed_entry_meta::table)
+				.values(&missing)
+				.on_conflict((
+					user_feed_entry_meta::user_id,
+					user_feed_entry_meta::feed_entry_id,
+				))
+				.do_update()
+				.set(user_feed_entry_meta::read.eq(read))
+				.execute(conn)?;
+
+			Ok(Some(story.len()))
+		})
 	}
 }
 
@@ -167,3 +1058,24 @@ impl UserFeedFolder<'_> {
 		})
 	}
 }
+
+impl User {
+	/// Local part of the newsletter address of a user, generated on first use
+	pub fn newsletter_token(user_id: UserId, conn: &mut PooledConnection) -> QueryResult<String> {
+		use crate::database::schema::*;
+
+		// 96 random bits, in hexadecimal
+		let token = format!("{:024x}", rand::random::<u128>() >> 32);
+
+		// a concurrent request may have set it first
+		dsl::update(user_::table.find(user_id))
+			.filter(user_::newsletter_token.is_null())
+			.set(user_::newsletter_token.eq(token))
+			.execute(conn)?;
+
+		user_::table
+			.find(user_id)
+			.select(user_::newsletter_token.assume_not_null())
+			.get_result(conn)
+	}
+}
@@ -10,6 +10,11 @@ use crate::database::schema::*;
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, DieselNewType, Deserialize, Serialize)]
 pub struct FeedId(i32);
 
+impl FeedId {
+	/// Stands for a feed that is not stored, e.g. while previewing one, identities start at 1
+	pub const UNSAVED: Self = Self(0);
+}
+
 #[derive(Debug, Clone, Queryable, Identifiable, Selectable)]
 #[diesel(table_name = feed)]
 pub struct Feed<'a> {
@@ -18,6 +23,99 @@ pub struct Feed<'a> {
 	pub url: Cow<'a, str>,
 
 	pub status: Cow<'a, str>,
+
+	pub next_fetch_at: Option<OffsetDateTime>,
+
+	pub etag: Option<Cow<'a, str>>,
+	pub last_modified: Option<Cow<'a, str>>,
+	pub content_hash: Option<Cow<'a, str>>,
+
+	pub consecutive_failures: i32,
+	pub last_success_at: Option<OffsetDateTime>,
+	pub publisher_interval_secs: Option<i32>,
+
+	pub last_error: Option<Cow<'a, str>>,
+
+	#[diesel(embed)]
+	pub metadata: FeedMetadata<'a>,
+}
+
+/// Information advertised by the feed itself
+#[derive(Debug, Clone, Default, Queryable, Selectable, AsChangeset, Deserialize, Serialize)]
+#[diesel(table_name = feed)]
+#[diesel(treat_none_as_null = true)]
+pub struct FeedMetadata<'a> {
+	pub title: Option<Cow<'a, str>>,
+	pub description: Option<Cow<'a, str>>,
+	pub site_link: Option<Cow<'a, str>>,
+	pub language: Option<Cow<'a, str>>,
+	pub generator: Option<Cow<'a, str>>,
+	pub icon_url: Option<Cow<'a, str>>,
+	pub logo_url: Option<Cow<'a, str>>,
+}
+
+/// Settings of the requests fetching a feed, only set on feeds owned by a user
+#[derive(Debug, Clone, Default, Queryable, Selectable, AsChangeset)]
+#[diesel(table_name = feed)]
+#[diesel(treat_none_as_null = true)]
+pub struct FeedRequestOptions {
+	/// Object of header names to values
+	pub request_headers: Option<serde_json::Value>,
+	pub user_agent: Option<String>,
+	/// Encrypted with the credentials key of the fetcher
+	pub credentials: Option<Vec<u8>>,
+	pub accept_invalid_certs: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, DieselNewType, Deserialize, Serialize)]
+pub struct FeedFetchLogId(i32);
+
+#[derive(Debug, Clone, Queryable, Identifiable, Selectable, Serialize)]
+#[diesel(table_name = feed_fetch_log)]
+pub struct FeedFetchLog<'a> {
+	pub id: FeedFetchLogId,
+	pub feed_id: FeedId,
+
+	#[serde(with = "time::serde::rfc3339")]
+	pub fetched_at: OffsetDateTime,
+	pub duration_ms: i32,
+
+	pub http_status: Option<i32>,
+	pub bytes: Option<i32>,
+
+	pub error_kind: Option<Cow<'a, str>>,
+	pub error_message: Option<Cow<'a, str>>,
+
+	pub redirects: Option<serde_json::Value>,
+	pub pushed: bool,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = feed_fetch_log)]
+pub struct NewFeedFetchLog<'a> {
+	pub feed_id: FeedId,
+
+	pub fetched_at: OffsetDateTime,
+	pub duration_ms: i32,
+
+	pub http_status: Option<i32>,
+	pub bytes: Option<i32>,
+
+	pub error_kind: Option<&'a str>,
+	pub error_message: Option<Cow<'a, str>>,
+
+	pub redirects: Option<serde_json::Value>,
+	pub pushed: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, DieselNewType, Deserialize, Serialize)]
+pub struct FetchJobId(i32);
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = fetch_job)]
+pub struct NewFetchJob {
+	pub feed_id: FeedId,
+	pub priority: i32,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, DieselNewType, Deserialize, Serialize)]
@@ -29,8 +127,137 @@ pub struct FeedEntry<'a> {
 	pub id: FeedEntryId,
 	pub feed_id: FeedId,
 
+	pub date: OffsetDateTime,
+
 	pub title: Cow<'a, str>,
-	pub content: Cow<'a, str>,
+	pub content: Option<Cow<'a, str>>,
+
+	pub guid: Cow<'a, str>,
+	pub link: Option<Cow<'a, str>>,
+	pub author: Option<Cow<'a, str>>,
+
+	/// Main content of the linked article, for subscriptions in full text mode
+	pub full_content: Option<Cow<'a, str>>,
+	pub full_content_fetched_at: Option<OffsetDateTime>,
+
+	pub last_seen_at: OffsetDateTime,
+	/// Hash of the title and content as published, `None` for entries stored before it existed
+	pub content_hash: Option<Cow<'a, str>>,
+	pub updated_at: Option<OffsetDateTime>,
+
+	/// Link without tracking parameters, matched against the links of other feeds
+	pub canonical_link: Option<Cow<'a, str>>,
+	/// Simhash of the title and text, `None` for short entries
+	pub fingerprint: Option<i64>,
+	/// First entry of the story across feeds, `None` until the entry is clustered
+	pub cluster_id: Option<FeedEntryId>,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = feed_entry)]
+pub struct NewFeedEntry<'a> {
+	pub feed_id: FeedId,
+
+	pub date: OffsetDateTime,
+
+	pub title: Cow<'a, str>,
+	pub content: Option<Cow<'a, str>>,
+
+	pub guid: Cow<'a, str>,
+	pub link: Option<Cow<'a, str>>,
+	pub author: Option<Cow<'a, str>>,
+
+	pub content_hash: Cow<'a, str>,
+
+	pub canonical_link: Option<Cow<'a, str>>,
+	pub fingerprint: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, DieselNewType, Deserialize, Serialize)]
+pub struct FeedEntryRevisionId(i32);
+
+impl fmt::Display for FeedEntryRevisionId {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", self.0)
+	}
+}
+
+/// Version of an entry before the publisher edited it
+#[derive(Debug, Clone, Queryable, Identifiable, Selectable, Serialize)]
+#[diesel(table_name = feed_entry_revision)]
+pub struct FeedEntryRevision<'a> {
+	pub id: FeedEntryRevisionId,
+	pub feed_entry_id: FeedEntryId,
+
+	pub title: Cow<'a, str>,
+	pub content: Option<Cow<'a, str>>,
+
+	#[serde(with = "time::serde::rfc3339")]
+	pub replaced_at: OffsetDateTime,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = feed_entry_revision)]
+pub struct NewFeedEntryRevision<'a> {
+	pub feed_entry_id: FeedEntryId,
+
+	pub title: Cow<'a, str>,
+	pub content: Option<Cow<'a, str>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, DieselNewType, Deserialize, Serialize)]
+pub struct FeedEntryEnclosureId(i32);
+
+/// Media attached to a feed entry
+#[derive(Debug, Clone, Queryable, Identifiable, Selectable, Deserialize, Serialize)]
+#[diesel(table_name = feed_entry_enclosure)]
+pub struct FeedEntryEnclosure<'a> {
+	pub id: FeedEntryEnclosureId,
+	pub feed_entry_id: FeedEntryId,
+
+	pub url: Cow<'a, str>,
+	pub mime: Option<Cow<'a, str>>,
+	pub length: Option<i64>,
+	pub duration_secs: Option<i32>,
+	pub image: Option<Cow<'a, str>>,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = feed_entry_enclosure)]
+pub struct NewFeedEntryEnclosure<'a> {
+	pub feed_entry_id: FeedEntryId,
+
+	pub url: Cow<'a, str>,
+	pub mime: Option<Cow<'a, str>>,
+	pub length: Option<i64>,
+	pub duration_secs: Option<i32>,
+	pub image: Option<Cow<'a, str>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, DieselNewType, Deserialize, Serialize)]
+pub struct IconId(i32);
+
+/// Image representing a feed, deduplicated by content
+#[derive(Debug, Clone, Queryable, Identifiable, Selectable)]
+#[diesel(table_name = icon)]
+pub struct Icon<'a> {
+	pub id: IconId,
+
+	pub hash: Cow<'a, str>,
+	pub mime: Cow<'a, str>,
+	pub content: Vec<u8>,
+
+	pub fetched_at: OffsetDateTime,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = icon)]
+pub struct NewIcon<'a> {
+	pub hash: Cow<'a, str>,
+	pub mime: Cow<'a, str>,
+	pub content: &'a [u8],
+
+	pub fetched_at: OffsetDateTime,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, DieselNewType, Deserialize, Serialize)]
@@ -51,6 +278,9 @@ pub struct User {
 
 	pub basic_secret: Option<String>,
 	pub dauth_secret: Option<String>,
+
+	/// Local part of the address newsletters are received on
+	pub newsletter_token: Option<String>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, DieselNewType, Deserialize, Serialize)]
@@ -76,8 +306,14 @@ pub struct UserFeed<'a> {
 	pub feed_id: FeedId,
 	pub folder_id: Option<UserFeedFolderId>,
 
-	pub title: Cow<'a, str>,
+	/// `None` follows the title of the feed
+	pub title: Option<Cow<'a, str>>,
 	pub description: Option<Cow<'a, str>>,
+
+	/// Whether the linked articles of entries are downloaded
+	pub full_text: bool,
+	/// Whether entries edited by the publisher are shown as unread again
+	pub unread_on_update: bool,
 }
 
 #[derive(Debug, Clone, Insertable)]
@@ -87,8 +323,11 @@ pub struct NewUserFeed<'a> {
 	pub feed_id: FeedId,
 	pub folder_id: Option<UserFeedFolderId>,
 
-	pub title: Cow<'a, str>,
+	pub title: Option<Cow<'a, str>>,
 	pub description: Option<Cow<'a, str>>,
+
+	pub full_text: bool,
+	pub unread_on_update: bool,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, DieselNewType, Deserialize, Serialize)]
@@ -128,3 +367,44 @@ pub struct ApiKey<'a> {
 	pub name: Cow<'a, str>,
 	pub secret: Cow<'a, str>,
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, DieselNewType, Deserialize, Serialize)]
+pub struct WebSubSubscriptionId(i32);
+
+impl fmt::Display for WebSubSubscriptionId {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", self.0)
+	}
+}
+
+#[derive(Debug, Clone, Queryable, Identifiable, Selectable)]
+#[diesel(table_name = websub_subscription)]
+pub struct WebSubSubscription<'a> {
+	pub id: WebSubSubscriptionId,
+	pub feed_id: FeedId,
+
+	pub hub: Cow<'a, str>,
+	pub topic: Cow<'a, str>,
+	pub secret: Cow<'a, str>,
+	pub callback_token: Cow<'a, str>,
+
+	pub status: Cow<'a, str>,
+	pub requested_at: OffsetDateTime,
+
+	pub lease_seconds: Option<i32>,
+	pub lease_expires_at: Option<OffsetDateTime>,
+}
+
+#[derive(Debug, Clone, Insertable, AsChangeset)]
+#[diesel(table_name = websub_subscription)]
+pub struct NewWebSubSubscription<'a> {
+	pub feed_id: FeedId,
+
+	pub hub: Cow<'a, str>,
+	pub topic: Cow<'a, str>,
+	pub secret: Cow<'a, str>,
+	pub callback_token: Cow<'a, str>,
+
+	pub status: &'a str,
+	pub requested_at: OffsetDateTime,
+}
@@ -15,6 +15,32 @@ diesel::table! {
         id -> Int4,
         url -> Text,
         status -> Text,
+        next_fetch_at -> Nullable<Timestamptz>,
+        etag -> Nullable<Text>,
+        last_modified -> Nullable<Text>,
+        content_hash -> Nullable<Text>,
+        consecutive_failures -> Int4,
+        last_success_at -> Nullable<Timestamptz>,
+        publisher_interval_secs -> Nullable<Int4>,
+        last_error -> Nullable<Text>,
+        title -> Nullable<Text>,
+        description -> Nullable<Text>,
+        site_link -> Nullable<Text>,
+        language -> Nullable<Text>,
+        generator -> Nullable<Text>,
+        icon_url -> Nullable<Text>,
+        logo_url -> Nullable<Text>,
+        icon_id -> Nullable<Int4>,
+        icon_checked_at -> Nullable<Timestamptz>,
+        retention_max_age_secs -> Nullable<Int4>,
+        retention_max_entries -> Nullable<Int4>,
+        owner_id -> Nullable<Int4>,
+        request_headers -> Nullable<Jsonb>,
+        user_agent -> Nullable<Text>,
+        credentials -> Nullable<Bytea>,
+        accept_invalid_certs -> Bool,
+        source -> Text,
+        scrape_rules -> Nullable<Jsonb>,
     }
 }
 
@@ -25,6 +51,76 @@ diesel::table! {
         date -> Timestamptz,
         title -> Text,
         content -> Nullable<Text>,
+        guid -> Text,
+        link -> Nullable<Text>,
+        author -> Nullable<Text>,
+        full_content -> Nullable<Text>,
+        full_content_fetched_at -> Nullable<Timestamptz>,
+        last_seen_at -> Timestamptz,
+        content_hash -> Nullable<Text>,
+        updated_at -> Nullable<Timestamptz>,
+        canonical_link -> Nullable<Text>,
+        fingerprint -> Nullable<Int8>,
+        cluster_id -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    feed_entry_enclosure (id) {
+        id -> Int4,
+        feed_entry_id -> Int4,
+        url -> Text,
+        mime -> Nullable<Text>,
+        length -> Nullable<Int8>,
+        duration_secs -> Nullable<Int4>,
+        image -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    feed_entry_revision (id) {
+        id -> Int4,
+        feed_entry_id -> Int4,
+        title -> Text,
+        content -> Nullable<Text>,
+        replaced_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    feed_fetch_log (id) {
+        id -> Int4,
+        feed_id -> Int4,
+        fetched_at -> Timestamptz,
+        duration_ms -> Int4,
+        http_status -> Nullable<Int4>,
+        bytes -> Nullable<Int4>,
+        error_kind -> Nullable<Text>,
+        error_message -> Nullable<Text>,
+        redirects -> Nullable<Jsonb>,
+        pushed -> Bool,
+    }
+}
+
+diesel::table! {
+    fetch_job (id) {
+        id -> Int4,
+        feed_id -> Int4,
+        priority -> Int4,
+        attempts -> Int4,
+        enqueued_at -> Timestamptz,
+        visible_at -> Timestamptz,
+        generation -> Int4,
+    }
+}
+
+diesel::table! {
+    icon (id) {
+        id -> Int4,
+        hash -> Text,
+        mime -> Text,
+        content -> Bytea,
+        fetched_at -> Timestamptz,
     }
 }
 
@@ -42,6 +138,7 @@ diesel::table! {
         username -> Text,
         basic_secret -> Nullable<Text>,
         dauth_secret -> Nullable<Text>,
+        newsletter_token -> Nullable<Text>,
     }
 }
 
@@ -51,8 +148,10 @@ diesel::table! {
         user_id -> Int4,
         feed_id -> Int4,
         folder_id -> Nullable<Int4>,
-        title -> Text,
+        title -> Nullable<Text>,
         description -> Nullable<Text>,
+        full_text -> Bool,
+        unread_on_update -> Bool,
     }
 }
 
@@ -74,22 +173,50 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    websub_subscription (id) {
+        id -> Int4,
+        feed_id -> Int4,
+        hub -> Text,
+        topic -> Text,
+        secret -> Text,
+        status -> Text,
+        requested_at -> Timestamptz,
+        lease_seconds -> Nullable<Int4>,
+        lease_expires_at -> Nullable<Timestamptz>,
+        callback_token -> Text,
+    }
+}
+
 diesel::joinable!(api_key -> user_ (user_id));
+diesel::joinable!(feed -> icon (icon_id));
+diesel::joinable!(feed -> user_ (owner_id));
 diesel::joinable!(feed_entry -> feed (feed_id));
+diesel::joinable!(feed_entry_enclosure -> feed_entry (feed_entry_id));
+diesel::joinable!(feed_entry_revision -> feed_entry (feed_entry_id));
+diesel::joinable!(feed_fetch_log -> feed (feed_id));
+diesel::joinable!(fetch_job -> feed (feed_id));
 diesel::joinable!(user_feed -> feed (feed_id));
 diesel::joinable!(user_feed -> user_ (user_id));
 diesel::joinable!(user_feed -> user_feed_folder (folder_id));
 diesel::joinable!(user_feed_entry_meta -> feed_entry (feed_entry_id));
 diesel::joinable!(user_feed_entry_meta -> user_ (user_id));
 diesel::joinable!(user_feed_folder -> user_ (user_id));
+diesel::joinable!(websub_subscription -> feed (feed_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
     api_key,
     feed,
     feed_entry,
+    feed_entry_enclosure,
+    feed_entry_revision,
+    feed_fetch_log,
+    fetch_job,
+    icon,
     session,
     user_,
     user_feed,
     user_feed_entry_meta,
     user_feed_folder,
+    websub_subscription,
 );
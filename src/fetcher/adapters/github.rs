@@ -0,0 +1,107 @@
+use url::Url;
+
+use crate::fetcher::adapters::{Adapter, Resolution, bare_host, has_extension, segments};
+
+/// Repositories, which serve atom feeds of their releases, tags and commits
+#[derive(Debug)]
+pub struct GitHub;
+
+impl Adapter for GitHub {
+	fn name(&self) -> &'static str {
+		"github"
+	}
+
+	fn resolve(&self, url: &Url) -> Option<Resolution> {
+		if bare_host(url)? != "github.com" {
+			return None;
+		}
+
+		let feed = |path: &str| {
+			let mut feed = Url::parse("https://github.com").expect("url is valid");
+			feed.set_path(path);
+			feed
+		};
+
+		let feeds = match segments(url).as_slice() {
+			[.., last] if has_extension(last, "atom") => return None,
+			[owner, repo, rest @ ..] => {
+				let repo = repo.trim_end_matches(".git");
+				let base = format!("/{owner}/{repo}");
+				match rest {
+					["releases", ..] => vec![feed(&format!("{base}/releases.atom"))],
+					["tags", ..] => vec![feed(&format!("{base}/tags.atom"))],
+					["commits"] => vec![feed(&format!("{base}/commits.atom"))],
+					// branches may contain slashes
+					["commits" | "tree", branch @ ..] => {
+						vec![feed(&format!("{base}/commits/{}.atom", branch.join("/")))]
+					}
+					// users usually follow releases, commits are offered as an alternative
+					_ => vec![
+						feed(&format!("{base}/releases.atom")),
+						feed(&format!("{base}/commits.atom")),
+					],
+				}
+			}
+			_ => return None,
+		};
+
+		Some(Resolution::Feeds(feeds))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn resolve(url: &str) -> Option<Vec<String>> {
+		match GitHub.resolve(&Url::parse(url).expect("fixture url is valid"))? {
+			Resolution::Feeds(feeds) => Some(feeds.into_iter().map(String::from).collect()),
+			Resolution::Page => None,
+		}
+	}
+
+	#[test]
+	fn resolves_urls() {
+		assert_eq!(
+			resolve("https://github.com/rust-lang/rust.git"),
+			Some(vec![
+				"https://github.com/rust-lang/rust/releases.atom".to_owned(),
+				"https://github.com/rust-lang/rust/commits.atom".to_owned(),
+			])
+		);
+		assert_eq!(
+			resolve("https://github.com/rust-lang/rust/releases/tag/1.90.0"),
+			Some(vec![
+				"https://github.com/rust-lang/rust/releases.atom".to_owned()
+			])
+		);
+		assert_eq!(
+			resolve("https://www.github.com/rust-lang/rust/tags"),
+			Some(vec![
+				"https://github.com/rust-lang/rust/tags.atom".to_owned()
+			])
+		);
+		assert_eq!(
+			resolve("https://github.com/rust-lang/rust/tree/release/1.90"),
+			Some(vec![
+				"https://github.com/rust-lang/rust/commits/release/1.90.atom".to_owned()
+			])
+		);
+		assert_eq!(
+			resolve("https://github.com/rust-lang/rust/commits/feature/a/b"),
+			Some(vec![
+				"https://github.com/rust-lang/rust/commits/feature/a/b.atom".to_owned()
+			])
+		);
+	}
+
+	#[test]
+	fn ignores_other_urls() {
+		assert_eq!(
+			resolve("https://github.com/rust-lang/rust/releases.atom"),
+			None
+		);
+		assert_eq!(resolve("https://github.com/rust-lang"), None);
+		assert_eq!(resolve("https://gitlab.com/rust-lang/rust"), None);
+	}
+}
@@ -0,0 +1,77 @@
+use url::Url;
+
+use crate::fetcher::adapters::{Adapter, Resolution, has_extension, segments};
+
+/// Profiles on mastodon instances, which serve the public posts of local accounts as rss
+///
+/// Instances can live on any host, the feed is checked before being offered.
+#[derive(Debug)]
+pub struct Mastodon;
+
+impl Adapter for Mastodon {
+	fn name(&self) -> &'static str {
+		"mastodon"
+	}
+
+	fn resolve(&self, url: &Url) -> Option<Resolution> {
+		let path = match segments(url).as_slice() {
+			// remote accounts, e.g. `/@user@other.host`, are only served by their own instance
+			[handle]
+				if handle.starts_with('@')
+					&& !has_extension(handle, "rss")
+					&& !handle[1..].contains('@') =>
+			{
+				format!("/{handle}.rss")
+			}
+			["users", name] if !has_extension(name, "rss") => format!("/users/{name}.rss"),
+			_ => return None,
+		};
+
+		let mut feed = url.clone();
+		feed.set_path(&path);
+		feed.set_query(None);
+		feed.set_fragment(None);
+
+		Some(Resolution::Feeds(vec![feed]))
+	}
+
+	fn matches_any_host(&self) -> bool {
+		true
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn resolve(url: &str) -> Option<String> {
+		match Mastodon.resolve(&Url::parse(url).expect("fixture url is valid"))? {
+			Resolution::Feeds(feeds) => Some(feeds.iter().map(Url::as_str).collect()),
+			Resolution::Page => None,
+		}
+	}
+
+	#[test]
+	fn resolves_urls() {
+		assert_eq!(
+			resolve("https://mastodon.social/@someone?tab=posts#top").as_deref(),
+			Some("https://mastodon.social/@someone.rss")
+		);
+		assert_eq!(
+			resolve("https://fosstodon.org/users/someone").as_deref(),
+			Some("https://fosstodon.org/users/someone.rss")
+		);
+	}
+
+	#[test]
+	fn ignores_other_urls() {
+		// remote accounts are only served by their own instance
+		assert_eq!(
+			resolve("https://mastodon.social/@someone@fosstodon.org"),
+			None
+		);
+		assert_eq!(resolve("https://mastodon.social/@someone.rss"), None);
+		assert_eq!(resolve("https://mastodon.social/@someone/1234"), None);
+		assert_eq!(resolve("https://mastodon.social/about"), None);
+	}
+}
@@ -0,0 +1,93 @@
+//! Sites whose pages do not advertise their feeds, or only some of them
+//!
+//! Adapters recognize the urls users paste, like a channel page or a repository, and map them to
+//! the native feeds of the site.
+
+use std::fmt;
+
+use url::Url;
+
+mod github;
+mod mastodon;
+mod reddit;
+mod youtube;
+
+/// How an adapter finds the feeds behind a recognized url
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolution {
+	/// Feeds known from the shape of the url alone
+	Feeds(Vec<Url>),
+	/// Feeds that depend on an identifier found in the page, see [`Adapter::page_feeds`]
+	Page,
+}
+
+/// Maps the urls of a site to its feeds
+pub trait Adapter: fmt::Debug + Send + Sync {
+	/// Name shown in logs
+	fn name(&self) -> &'static str;
+
+	/// Feeds behind an url, `None` when the adapter does not recognize it
+	fn resolve(&self, url: &Url) -> Option<Resolution>;
+
+	/// Feeds of a page the adapter asked for with [`Resolution::Page`]
+	fn page_feeds(&self, url: &Url, html: &str) -> Vec<Url> {
+		let _ = (url, html);
+		Vec::new()
+	}
+
+	/// Whether the adapter recognizes urls on any host, it may then be wrong about the site and the
+	/// feeds advertised by the page are offered as well
+	fn matches_any_host(&self) -> bool {
+		false
+	}
+}
+
+/// Adapters tried in order on the urls given to discovery
+#[derive(Debug)]
+pub struct Adapters(Vec<Box<dyn Adapter>>);
+
+impl Default for Adapters {
+	fn default() -> Self {
+		Self(vec![
+			Box::new(youtube::YouTube),
+			Box::new(reddit::Reddit),
+			Box::new(github::GitHub),
+			// matches profiles on any host, checked last
+			Box::new(mastodon::Mastodon),
+		])
+	}
+}
+
+impl Adapters {
+	/// First adapter recognizing the url along with its resolution
+	pub fn find(&self, url: &Url) -> Option<(&dyn Adapter, Resolution)> {
+		self.0
+			.iter()
+			.find_map(|adapter| Some((adapter.as_ref(), adapter.resolve(url)?)))
+	}
+}
+
+/// Host of an url without its usual `www.` or mobile prefixes
+fn bare_host(url: &Url) -> Option<&str> {
+	let host = url.host_str()?;
+	Some(
+		["www.", "m.", "old.", "np."]
+			.iter()
+			.find_map(|prefix| host.strip_prefix(prefix))
+			.unwrap_or(host),
+	)
+}
+
+/// Non-empty path segments of an url
+fn segments(url: &Url) -> Vec<&str> {
+	url.path_segments()
+		.map(|segments| segments.filter(|segment| !segment.is_empty()).collect())
+		.unwrap_or_default()
+}
+
+/// Whether a path segment already points to a feed file, e.g. `releases.atom`
+fn has_extension(segment: &str, extension: &str) -> bool {
+	segment
+		.rsplit_once('.')
+		.is_some_and(|(_, found)| found.eq_ignore_ascii_case(extension))
+}
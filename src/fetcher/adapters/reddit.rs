@@ -0,0 +1,82 @@
+use url::Url;
+
+use crate::fetcher::adapters::{Adapter, Resolution, bare_host, has_extension, segments};
+
+/// Subreddits and user profiles, any listing is served as rss by appending `/.rss`
+#[derive(Debug)]
+pub struct Reddit;
+
+impl Adapter for Reddit {
+	fn name(&self) -> &'static str {
+		"reddit"
+	}
+
+	fn resolve(&self, url: &Url) -> Option<Resolution> {
+		if bare_host(url)? != "reddit.com" {
+			return None;
+		}
+
+		let path = match segments(url).as_slice() {
+			[.., last] if has_extension(last, "rss") => return None,
+			// sorted listings, e.g. `/r/rust/top`
+			["r", subreddit, sort @ ("hot" | "new" | "top" | "rising")] => {
+				format!("/r/{subreddit}/{sort}/.rss")
+			}
+			["r", subreddit, ..] => format!("/r/{subreddit}/.rss"),
+			["u" | "user", name, ..] => format!("/user/{name}/.rss"),
+			_ => return None,
+		};
+
+		let mut feed = Url::parse("https://www.reddit.com").expect("url is valid");
+		feed.set_path(&path);
+		// sorted listings keep their time range
+		if let Some((_, range)) = url.query_pairs().find(|(key, _)| key == "t") {
+			feed.query_pairs_mut().append_pair("t", &range);
+		}
+
+		Some(Resolution::Feeds(vec![feed]))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn resolve(url: &str) -> Option<String> {
+		match Reddit.resolve(&Url::parse(url).expect("fixture url is valid"))? {
+			Resolution::Feeds(feeds) => Some(feeds.iter().map(Url::as_str).collect()),
+			Resolution::Page => None,
+		}
+	}
+
+	#[test]
+	fn resolves_urls() {
+		assert_eq!(
+			resolve("https://www.reddit.com/r/rust/").as_deref(),
+			Some("https://www.reddit.com/r/rust/.rss")
+		);
+		assert_eq!(
+			resolve("https://old.reddit.com/r/rust/comments/abc/title/").as_deref(),
+			Some("https://www.reddit.com/r/rust/.rss")
+		);
+		assert_eq!(
+			resolve("https://www.reddit.com/r/rust/top/?t=week").as_deref(),
+			Some("https://www.reddit.com/r/rust/top/.rss?t=week")
+		);
+		assert_eq!(
+			resolve("https://www.reddit.com/r/rust/new").as_deref(),
+			Some("https://www.reddit.com/r/rust/new/.rss")
+		);
+		assert_eq!(
+			resolve("https://np.reddit.com/u/someone/submitted").as_deref(),
+			Some("https://www.reddit.com/user/someone/.rss")
+		);
+	}
+
+	#[test]
+	fn ignores_other_urls() {
+		assert_eq!(resolve("https://www.reddit.com/r/rust/.rss"), None);
+		assert_eq!(resolve("https://www.reddit.com/"), None);
+		assert_eq!(resolve("https://reddit.example/r/rust"), None);
+	}
+}
@@ -0,0 +1,144 @@
+use scraper::{Html, Selector};
+use url::Url;
+
+use crate::fetcher::adapters::{Adapter, Resolution, bare_host, segments};
+
+const FEEDS_URL: &str = "https://www.youtube.com/feeds/videos.xml";
+
+/// Channels and playlists, served as atom feeds by `/feeds/videos.xml`
+///
+/// Handles and custom names are only known by the channel page, which holds the channel id.
+#[derive(Debug)]
+pub struct YouTube;
+
+impl YouTube {
+	fn feed(param: &str, value: &str) -> Url {
+		let mut url = Url::parse(FEEDS_URL).expect("url is valid");
+		url.query_pairs_mut().append_pair(param, value);
+		url
+	}
+}
+
+impl Adapter for YouTube {
+	fn name(&self) -> &'static str {
+		"youtube"
+	}
+
+	fn resolve(&self, url: &Url) -> Option<Resolution> {
+		if bare_host(url)? != "youtube.com" {
+			return None;
+		}
+
+		let feed = match segments(url).as_slice() {
+			["channel", id, ..] => Self::feed("channel_id", id),
+			["user", name, ..] => Self::feed("user", name),
+			["playlist"] => {
+				let (_, list) = url.query_pairs().find(|(key, _)| key == "list")?;
+				Self::feed("playlist_id", &list)
+			}
+			["feeds", "videos.xml"] => return None,
+			[handle, ..] if handle.starts_with('@') => return Some(Resolution::Page),
+			["c", _, ..] => return Some(Resolution::Page),
+			_ => return None,
+		};
+
+		Some(Resolution::Feeds(vec![feed]))
+	}
+
+	fn page_feeds(&self, _url: &Url, html: &str) -> Vec<Url> {
+		let document = Html::parse_document(html);
+		let selector = Selector::parse(
+			r#"meta[itemprop="identifier"][content], meta[itemprop="channelId"][content]"#,
+		)
+		.expect("selector is valid");
+
+		let id = document
+			.select(&selector)
+			.find_map(|meta| meta.attr("content"))
+			.map(ToOwned::to_owned)
+			// newer pages only hold the id in their embedded initial data
+			.or_else(|| {
+				let (_, rest) = html.split_once(r#""externalId":""#)?;
+				let (id, _) = rest.split_once('"')?;
+				Some(id.to_owned())
+			})
+			.filter(|id| id.starts_with("UC"));
+
+		id.map(|id| Self::feed("channel_id", &id))
+			.into_iter()
+			.collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn resolve(url: &str) -> Option<Resolution> {
+		YouTube.resolve(&Url::parse(url).expect("fixture url is valid"))
+	}
+
+	fn feeds(url: &str) -> Resolution {
+		Resolution::Feeds(vec![Url::parse(url).expect("fixture url is valid")])
+	}
+
+	#[test]
+	fn resolves_urls() {
+		assert_eq!(
+			resolve("https://www.youtube.com/channel/UCaYhcUwRBNscFNUKTjgPFiA/videos"),
+			Some(feeds(
+				"https://www.youtube.com/feeds/videos.xml?channel_id=UCaYhcUwRBNscFNUKTjgPFiA"
+			))
+		);
+		assert_eq!(
+			resolve("https://m.youtube.com/user/rustvideos"),
+			Some(feeds(
+				"https://www.youtube.com/feeds/videos.xml?user=rustvideos"
+			))
+		);
+		assert_eq!(
+			resolve("https://youtube.com/playlist?list=PL85XCvVPmGQgL3lqQD5ivLNLfdAdxbE_u"),
+			Some(feeds(
+				"https://www.youtube.com/feeds/videos.xml?playlist_id=PL85XCvVPmGQgL3lqQD5ivLNLfdAdxbE_u"
+			))
+		);
+		assert_eq!(
+			resolve("https://www.youtube.com/@rustvideos/featured"),
+			Some(Resolution::Page)
+		);
+		assert_eq!(
+			resolve("https://www.youtube.com/c/RustVideos"),
+			Some(Resolution::Page)
+		);
+	}
+
+	#[test]
+	fn ignores_other_urls() {
+		assert_eq!(resolve("https://www.youtube.com/playlist"), None);
+		assert_eq!(
+			resolve("https://www.youtube.com/feeds/videos.xml?channel_id=UCaYhcUwRBNscFNUKTjgPFiA"),
+			None
+		);
+		assert_eq!(resolve("https://www.youtube.com/watch?v=abc"), None);
+		assert_eq!(resolve("https://vimeo.com/channel/abc"), None);
+	}
+
+	#[test]
+	fn finds_channel_of_pages() {
+		let url = Url::parse("https://www.youtube.com/@rustvideos").expect("url is valid");
+		let channel = vec![
+			Url::parse(
+				"https://www.youtube.com/feeds/videos.xml?channel_id=UCaYhcUwRBNscFNUKTjgPFiA",
+			)
+			.expect("url is valid"),
+		];
+
+		let meta = include_str!("fixtures/youtube_channel_meta.html");
+		assert_eq!(YouTube.page_feeds(&url, meta), channel);
+
+		let initial_data = include_str!("fixtures/youtube_channel_initial_data.html");
+		assert_eq!(YouTube.page_feeds(&url, initial_data), channel);
+
+		assert!(YouTube.page_feeds(&url, "<html></html>").is_empty());
+	}
+}
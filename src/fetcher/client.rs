@@ -0,0 +1,140 @@
+use std::{sync::Arc, time::Duration};
+
+use bytes::{Bytes, BytesMut};
+use eyre::WrapErr;
+use reqwest::{Client, RequestBuilder, Response, StatusCode, header};
+use url::Url;
+
+use crate::{
+	config::FetcherConfig,
+	fetcher::{
+		Error, Result,
+		policy::{OutboundPolicy, PolicyResolver},
+		redirect::Redirect,
+	},
+};
+
+/// Http client enforcing the [`OutboundPolicy`], used for every request made on behalf of users
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+	client: Client,
+	/// Skips certificate verification, for feeds hosted behind a self-signed certificate
+	insecure_client: Client,
+	policy: Arc<OutboundPolicy>,
+}
+
+impl HttpClient {
+	pub fn new(config: &FetcherConfig) -> eyre::Result<Self> {
+		let policy = Arc::new(OutboundPolicy::new(config)?);
+
+		let user_agent = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+		// redirects are followed by hand to detect moved feeds and check every hop
+		let client = |accept_invalid_certs| {
+			Client::builder()
+				.user_agent(user_agent)
+				.redirect(reqwest::redirect::Policy::none())
+				// a proxy would resolve hosts itself, out of reach of the policy
+				.no_proxy()
+				.dns_resolver(Arc::new(PolicyResolver(Arc::clone(&policy))))
+				.connect_timeout(Duration::from_secs(config.connect_timeout))
+				.read_timeout(Duration::from_secs(config.read_timeout))
+				.timeout(Duration::from_secs(config.request_timeout))
+				.danger_accept_invalid_certs(accept_invalid_certs)
+				.build()
+				.wrap_err("could not build client")
+		};
+
+		Ok(Self {
+			client: client(false)?,
+			insecure_client: client(true)?,
+			policy,
+		})
+	}
+
+	pub fn request(&self, url: &Url) -> RequestBuilder {
+		self.client.get(url.clone())
+	}
+
+	/// Same as [`Self::request`], without verifying the certificate of the server
+	pub fn insecure_request(&self, url: &Url) -> RequestBuilder {
+		self.insecure_client.get(url.clone())
+	}
+
+	pub fn post(&self, url: &Url) -> RequestBuilder {
+		self.client.post(url.clone())
+	}
+
+	/// Sends a plain `GET` request, see [`Self::send`]
+	pub async fn get(&self, url: &Url) -> Result<(Response, Vec<Redirect>)> {
+		self.send(url, |url| self.request(url)).await
+	}
+
+	/// Sends the request built by `request`, following redirects ourselves to keep track of them
+	///
+	/// The builder is called again for each hop so headers are kept.
+	pub async fn send(
+		&self,
+		url: &Url,
+		request: impl Fn(&Url) -> RequestBuilder,
+	) -> Result<(Response, Vec<Redirect>)> {
+		let mut url = url.clone();
+		let mut redirects = Vec::<Redirect>::new();
+
+		loop {
+			self.policy.check_url(&url)?;
+			let response = request(&url).send().await?;
+
+			let status = response.status();
+			if !status.is_redirection() || status == StatusCode::NOT_MODIFIED {
+				return Ok((response, redirects));
+			}
+
+			let Some(location) = response
+				.headers()
+				.get(header::LOCATION)
+				.and_then(|location| location.to_str().ok())
+			else {
+				return Ok((response, redirects));
+			};
+
+			if redirects.len() >= self.policy.max_redirects {
+				return Err(Error::Redirect("too many redirects"));
+			}
+
+			url = url
+				.join(location)
+				.map_err(|_| Error::Redirect("invalid location"))?;
+			if redirects.iter().any(|redirect| redirect.url == url) {
+				return Err(Error::Redirect("redirect loop"));
+			}
+
+			redirects.push(Redirect {
+				status: status.as_u16(),
+				url: url.clone(),
+			});
+		}
+	}
+
+	/// Reads the response body, giving up once it exceeds the maximum size
+	pub async fn body(&self, mut response: Response) -> Result<Bytes> {
+		let max_size = self.policy.max_body_size;
+
+		if response
+			.content_length()
+			.is_some_and(|length| usize::try_from(length).map_or(true, |length| length > max_size))
+		{
+			return Err(Error::Body("response body is too large"));
+		}
+
+		// chunks are decompressed, which also bounds compression bombs
+		let mut body = BytesMut::new();
+		while let Some(chunk) = response.chunk().await? {
+			if body.len() + chunk.len() > max_size {
+				return Err(Error::Body("response body is too large"));
+			}
+			body.extend_from_slice(&chunk);
+		}
+
+		Ok(body.freeze())
+	}
+}
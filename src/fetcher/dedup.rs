@@ -0,0 +1,176 @@
+//! Fingerprints telling apart entries that carry the same story in several feeds
+//!
+//! Entries are matched by their canonical link, or by the simhash of their text when links
+//! differ, e.g. for syndicated articles. Each story is identified by its first entry, see
+//! [`FeedEntry::cluster`](crate::database::models::FeedEntry::cluster).
+
+use scraper::Html;
+use sha2::{Digest, Sha256};
+use url::Url;
+
+use crate::fetcher::rewrite::without_tracking;
+
+/// Texts shorter than this, in words, give fingerprints too close to be told apart
+const MIN_FINGERPRINT_WORDS: usize = 16;
+
+/// Link of an entry without what varies between feeds linking to the same page: tracking
+/// parameters, fragment, `www.` prefix, scheme and trailing slash
+pub fn canonical_link(link: &str) -> Option<String> {
+	let link = without_tracking(link).unwrap_or_else(|| link.to_owned());
+	let mut url = Url::parse(&link).ok()?;
+	if !matches!(url.scheme(), "http" | "https") {
+		return None;
+	}
+
+	url.set_fragment(None);
+	url.set_scheme("https").ok()?;
+	if let Some(host) = url.host_str().and_then(|host| host.strip_prefix("www.")) {
+		let host = host.to_owned();
+		url.set_host(Some(&host)).ok()?;
+	}
+
+	if url.path().len() > 1 && url.path().ends_with('/') {
+		let path = url.path().trim_end_matches('/').to_owned();
+		url.set_path(&path);
+	}
+
+	Some(url.into())
+}
+
+/// Simhash of the words of the title and content, `None` for short texts
+pub fn fingerprint(title: &str, content: Option<&str>) -> Option<i64> {
+	let text = content.map(|content| {
+		Html::parse_fragment(content)
+			.root_element()
+			.text()
+			.collect::<Vec<_>>()
+			.join(" ")
+	});
+
+	let words = [title, text.as_deref().unwrap_or_default()]
+		.into_iter()
+		.flat_map(|text| text.split(|c: char| !c.is_alphanumeric()))
+		.filter(|word| !word.is_empty())
+		.map(str::to_lowercase)
+		.collect::<Vec<_>>();
+	if words.len() < MIN_FINGERPRINT_WORDS {
+		return None;
+	}
+
+	// each word votes for the bits of its hash
+	let mut weights = [0_i32; 64];
+	for word in &words {
+		let hash = word_hash(word);
+		for (bit, weight) in weights.iter_mut().enumerate() {
+			if hash >> bit & 1 == 1 {
+				*weight += 1;
+			} else {
+				*weight -= 1;
+			}
+		}
+	}
+
+	let hash = weights
+		.iter()
+		.enumerate()
+		.filter(|(_, weight)| **weight > 0)
+		.fold(0_u64, |hash, (bit, _)| hash | 1 << bit);
+
+	// stored as a signed bigint, only the bits matter
+	Some(i64::from_ne_bytes(hash.to_ne_bytes()))
+}
+
+/// Stable across releases unlike the hasher of the standard library, and spreads the bits of
+/// short words better than simpler hashes
+fn word_hash(word: &str) -> u64 {
+	let digest = Sha256::digest(word.as_bytes());
+	u64::from_le_bytes(
+		digest[..8]
+			.try_into()
+			.expect("digest is longer than 8 bytes"),
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::database::CLUSTER_MAX_DISTANCE;
+
+	const ARTICLE: &str = "<p>The Rust project released a new edition today, bringing async closures, \
+		new lints, better diagnostics for borrow errors and a reworked trait solver.</p><p>Generic \
+		code now compiles faster, and most crates can migrate with a single command run by the \
+		maintainers.</p>";
+
+	fn distance(a: Option<i64>, b: Option<i64>) -> i32 {
+		let (a, b) = (
+			a.expect("text is long enough"),
+			b.expect("text is long enough"),
+		);
+		i32::try_from((a ^ b).count_ones()).expect("64 fits in i32")
+	}
+
+	#[test]
+	fn canonicalizes_links() {
+		let canonical = |link| canonical_link(link).expect("link is valid");
+
+		assert_eq!(
+			canonical("http://www.blog.example/posts/rust/?utm_source=rss&fbclid=x#top"),
+			"https://blog.example/posts/rust"
+		);
+		assert_eq!(
+			canonical("https://blog.example/posts/rust"),
+			canonical("https://www.blog.example/posts/rust/")
+		);
+		assert_eq!(
+			canonical("https://blog.example/item?id=3&utm_medium=feed"),
+			"https://blog.example/item?id=3"
+		);
+		assert_eq!(
+			canonical("https://www.blog.example/"),
+			"https://blog.example/"
+		);
+		assert_ne!(
+			canonical("https://blog.example/item?id=3"),
+			canonical("https://blog.example/item?id=4")
+		);
+
+		assert_eq!(canonical_link("mailto:someone@blog.example"), None);
+		assert_eq!(canonical_link("/posts/rust"), None);
+	}
+
+	#[test]
+	fn fingerprints_near_duplicates_together() {
+		let original = fingerprint("Rust edition released", Some(ARTICLE));
+
+		let syndicated = fingerprint(
+			"Rust Edition Released!",
+			Some(&ARTICLE.replace("<p>", "<div class=\"syndicated\">")),
+		);
+		assert!(distance(original, syndicated) <= CLUSTER_MAX_DISTANCE);
+
+		let edited = fingerprint(
+			"Rust edition released",
+			Some(&ARTICLE.replace("today", "this morning")),
+		);
+		assert!(distance(original, edited) <= CLUSTER_MAX_DISTANCE);
+	}
+
+	#[test]
+	fn fingerprints_unrelated_texts_apart() {
+		let original = fingerprint("Rust edition released", Some(ARTICLE));
+		let unrelated = fingerprint(
+			"Local bakery wins award",
+			Some(
+				"<p>The bakery on the corner of the main square won the regional prize for its \
+				sourdough bread, after three years of baking every night before dawn for the \
+				whole neighbourhood.</p>",
+			),
+		);
+		assert!(distance(original, unrelated) > CLUSTER_MAX_DISTANCE);
+
+		assert_eq!(
+			fingerprint("Too short", Some("<p>only a few words</p>")),
+			None
+		);
+	}
+}
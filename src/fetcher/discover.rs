@@ -0,0 +1,197 @@
+use feed_rs::{model, parser};
+use itertools::Itertools;
+use scraper::{Html, Selector};
+use serde::Serialize;
+use url::Url;
+
+use crate::fetcher::{
+	Error, Result,
+	adapters::{Adapter, Adapters, Resolution},
+	client::HttpClient,
+	redirect,
+};
+
+/// Media types of the feeds advertised in html `<link rel="alternate">` tags
+const FEED_TYPES: &[&str] = &[
+	"application/rss+xml",
+	"application/atom+xml",
+	"application/feed+json",
+];
+
+/// Usual feed locations tried when a page does not advertise any feed
+const COMMON_PATHS: &[&str] = &["/feed", "/atom.xml", "/index.xml"];
+
+/// Feed found while looking for the feeds of a page
+#[derive(Debug, Clone, Serialize)]
+pub struct Candidate {
+	pub url: Url,
+	pub title: Option<String>,
+	pub mime: String,
+}
+
+/// Lists the feeds behind an url, which can either point to a feed or to a web page
+///
+/// Urls of well known sites are first mapped to their native feeds by the [`Adapters`].
+pub async fn discover(
+	client: &HttpClient,
+	adapters: &Adapters,
+	url: &Url,
+) -> Result<Vec<Candidate>> {
+	let (any_host, adapted) = adapted_feeds(client, adapters, url)
+		.await
+		.map_or((false, Vec::new()), |(adapter, feeds)| {
+			(adapter.matches_any_host(), feeds)
+		});
+	if !adapted.is_empty() && !any_host {
+		return Ok(adapted);
+	}
+
+	// adapters matching any host may be wrong about the site, its own feeds are kept along
+	match site_feeds(client, url).await {
+		Ok(candidates) => Ok(adapted
+			.into_iter()
+			.chain(candidates)
+			.unique_by(|candidate| candidate.url.clone())
+			.collect()),
+		Err(_) if !adapted.is_empty() => Ok(adapted),
+		Err(err) => Err(err),
+	}
+}
+
+/// Feeds behind a page, the page itself when it is a feed
+async fn site_feeds(client: &HttpClient, url: &Url) -> Result<Vec<Candidate>> {
+	let (response, redirects) = client.get(url).await?;
+
+	let status = response.status();
+	if !status.is_success() {
+		return Err(Error::Status {
+			status,
+			retry_after: None,
+		});
+	}
+
+	// links are relative to the page we landed on
+	let base = response.url().clone();
+	let body = client.body(response).await?;
+
+	if let Ok(feed) = parser::parse(&body[..]) {
+		let url = redirect::permanent_target(&redirects).unwrap_or(url);
+		return Ok(vec![candidate(url.clone(), &feed)]);
+	}
+
+	let candidates = advertised_feeds(&base, &String::from_utf8_lossy(&body));
+	if !candidates.is_empty() {
+		return Ok(candidates);
+	}
+
+	let mut candidates = Vec::new();
+	for path in COMMON_PATHS {
+		let Ok(url) = base.join(path) else { continue };
+		if let Some(feed) = probe(client, &url).await {
+			candidates.push(candidate(url, &feed));
+		}
+	}
+
+	Ok(candidates)
+}
+
+/// Feeds of the adapter recognizing the url, only those that parse are kept
+async fn adapted_feeds<'a>(
+	client: &HttpClient,
+	adapters: &'a Adapters,
+	url: &Url,
+) -> Option<(&'a dyn Adapter, Vec<Candidate>)> {
+	let (adapter, resolution) = adapters.find(url)?;
+
+	let feeds = match resolution {
+		Resolution::Feeds(feeds) => feeds,
+		Resolution::Page => match page(client, url).await {
+			Ok(html) => adapter.page_feeds(url, &html),
+			Err(err) => {
+				tracing::debug!(adapter = adapter.name(), url = %url, err = %err, "could not download page");
+				Vec::new()
+			}
+		},
+	};
+
+	let mut candidates = Vec::new();
+	for feed in feeds {
+		match probe(client, &feed).await {
+			Some(parsed) => candidates.push(candidate(feed, &parsed)),
+			None => {
+				tracing::debug!(adapter = adapter.name(), url = %url, feed = %feed, "adapted feed did not parse");
+			}
+		}
+	}
+
+	Some((adapter, candidates))
+}
+
+async fn page(client: &HttpClient, url: &Url) -> Result<String> {
+	let (response, _) = client.get(url).await?;
+
+	let status = response.status();
+	if !status.is_success() {
+		return Err(Error::Status {
+			status,
+			retry_after: None,
+		});
+	}
+
+	let body = client.body(response).await?;
+	Ok(String::from_utf8_lossy(&body).into_owned())
+}
+
+/// Feeds referenced by the `<link rel="alternate">` tags of a page
+fn advertised_feeds(base: &Url, html: &str) -> Vec<Candidate> {
+	let document = Html::parse_document(html);
+	let selector =
+		Selector::parse(r#"link[rel~="alternate"][href][type]"#).expect("selector is valid");
+
+	document
+		.select(&selector)
+		.filter_map(|link| {
+			let mime = link.attr("type")?.trim().to_ascii_lowercase();
+			if !FEED_TYPES.contains(&mime.as_str()) {
+				return None;
+			}
+
+			Some(Candidate {
+				url: base.join(link.attr("href")?.trim()).ok()?,
+				title: link
+					.attr("title")
+					.map(str::trim)
+					.filter(|title| !title.is_empty())
+					.map(ToOwned::to_owned),
+				mime,
+			})
+		})
+		.unique_by(|candidate| candidate.url.clone())
+		.collect()
+}
+
+async fn probe(client: &HttpClient, url: &Url) -> Option<model::Feed> {
+	let (response, _) = client.get(url).await.ok()?;
+	if !response.status().is_success() {
+		return None;
+	}
+
+	let body = client.body(response).await.ok()?;
+	parser::parse(&body[..]).ok()
+}
+
+fn candidate(url: Url, feed: &model::Feed) -> Candidate {
+	let mime = match feed.feed_type {
+		model::FeedType::Atom => "application/atom+xml",
+		model::FeedType::JSON => "application/feed+json",
+		model::FeedType::RSS0 | model::FeedType::RSS1 | model::FeedType::RSS2 => {
+			"application/rss+xml"
+		}
+	};
+
+	Candidate {
+		url,
+		title: feed.title.as_ref().map(|title| title.content.clone()),
+		mime: mime.to_owned(),
+	}
+}
@@ -1,10 +1,133 @@
+use std::{error::Error as _, iter, time::Duration};
+
+use reqwest::StatusCode;
+
+use crate::fetcher::policy::Blocked;
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
+	#[error("request: {0}")]
+	Request(#[from] reqwest::Error),
+
+	#[error("blocked: {0}")]
+	Blocked(#[from] Blocked),
+
+	#[error("redirect: {0}")]
+	Redirect(&'static str),
+
+	#[error("body: {0}")]
+	Body(&'static str),
+
+	#[error("server returned status {status}")]
+	Status {
+		status: StatusCode,
+		retry_after: Option<Duration>,
+	},
+
+	#[error("parse: {0}")]
+	Parse(#[from] feed_rs::parser::ParseFeedError),
+
+	#[error("scrape: {0}")]
+	Scrape(&'static str),
+
 	#[error("pool: {0}")]
 	DbPool(#[from] diesel::r2d2::PoolError),
 
 	#[error("other: {0}")]
 	Other(#[from] eyre::Report),
 }
+
+/// Why a fetch attempt failed, stored in `feed_fetch_log`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+	Blocked,
+	Dns,
+	Connect,
+	Tls,
+	Timeout,
+	Redirect,
+	ClientError,
+	ServerError,
+	Body,
+	Parse,
+	Other,
+}
+
+impl ErrorKind {
+	pub const fn as_str(self) -> &'static str {
+		match self {
+			Self::Blocked => "blocked",
+			Self::Dns => "dns",
+			Self::Connect => "connect",
+			Self::Tls => "tls",
+			Self::Timeout => "timeout",
+			Self::Redirect => "redirect",
+			Self::ClientError => "http_4xx",
+			Self::ServerError => "http_5xx",
+			Self::Body => "body",
+			Self::Parse => "parse",
+			Self::Other => "other",
+		}
+	}
+
+	fn from_request(err: &reqwest::Error) -> Self {
+		if err.is_timeout() {
+			return Self::Timeout;
+		}
+		if err.is_redirect() {
+			return Self::Redirect;
+		}
+		if err.is_body() || err.is_decode() {
+			return Self::Body;
+		}
+		if !err.is_connect() {
+			return Self::Other;
+		}
+
+		let causes = iter::successors(err.source(), |&err| err.source()).collect::<Vec<_>>();
+
+		// refused addresses are reported by our resolver
+		if causes.iter().any(|cause| cause.is::<Blocked>()) {
+			return Self::Blocked;
+		}
+
+		// connector errors are opaque, look at the messages of the underlying causes
+		let causes = causes
+			.iter()
+			.map(|cause| cause.to_string().to_lowercase())
+			.collect::<Vec<_>>();
+		let mentions = |needle| causes.iter().any(|cause| cause.contains(needle));
+
+		if mentions("dns error") {
+			Self::Dns
+		} else if mentions("certificate") || mentions("tls") || mentions("handshake") {
+			Self::Tls
+		} else {
+			Self::Connect
+		}
+	}
+}
+
+impl Error {
+	pub fn kind(&self) -> ErrorKind {
+		match self {
+			Self::Request(err) => ErrorKind::from_request(err),
+			Self::Blocked(_) => ErrorKind::Blocked,
+			Self::Redirect(_) => ErrorKind::Redirect,
+			Self::Body(_) => ErrorKind::Body,
+			Self::Status { status, .. } if status.is_client_error() => ErrorKind::ClientError,
+			Self::Status { status, .. } if status.is_server_error() => ErrorKind::ServerError,
+			Self::Parse(_) | Self::Scrape(_) => ErrorKind::Parse,
+			Self::Status { .. } | Self::DbPool(_) | Self::Other(_) => ErrorKind::Other,
+		}
+	}
+
+	pub const fn status(&self) -> Option<StatusCode> {
+		match self {
+			Self::Status { status, .. } => Some(*status),
+			_ => None,
+		}
+	}
+}
@@ -0,0 +1,219 @@
+use std::{borrow::Cow, collections::HashMap, time::Duration};
+
+use diesel::{dsl, prelude::*};
+use eyre::WrapErr;
+use reqwest::header;
+use scraper::{ElementRef, Html, Selector};
+use time::OffsetDateTime;
+use url::Url;
+
+use crate::{
+	database::models::{FeedEntryId, FeedId},
+	fetcher::{Error, Fetcher, Result, rewrite::Context},
+};
+
+/// Maximum number of articles downloaded after a single fetch, older entries are left as is
+const BATCH_SIZE: i64 = 10;
+
+/// Class and id fragments of elements likely holding the article
+const POSITIVE_HINTS: &[&str] = &[
+	"article", "body", "content", "entry", "main", "page", "post", "story", "text",
+];
+/// Class and id fragments of elements around the article
+const NEGATIVE_HINTS: &[&str] = &[
+	"banner", "comment", "cookie", "footer", "menu", "nav", "promo", "related", "share", "sidebar",
+	"social", "sponsor", "widget",
+];
+
+/// Paragraphs with less text are ignored, they are usually captions or buttons
+const MIN_PARAGRAPH_LENGTH: usize = 25;
+/// Pages with less text in their best candidate are not considered articles
+const MIN_CONTENT_LENGTH: usize = 250;
+
+impl Fetcher {
+	/// Downloads the articles linked by the latest entries of a feed, when one of its subscribers
+	/// reads it in full text
+	pub(super) async fn fetch_full_texts(&self, feed_id: FeedId) -> Result<()> {
+		let entries = self.pending_full_texts(feed_id)?;
+
+		for (index, (entry_id, link)) in entries.into_iter().enumerate() {
+			// articles usually live on the host of the feed, be as polite as with feeds
+			if index > 0 {
+				tokio::time::sleep(Duration::from_millis(self.config.per_host_delay)).await;
+			}
+
+			let content = match Url::parse(&link) {
+				Ok(url) => self.fetch_article(&url).await.unwrap_or_else(|err| {
+					tracing::debug!(entry_id = ?entry_id, url = %url, err = %err, "could not download article");
+					None
+				}),
+				Err(_) => None,
+			};
+
+			self.store_full_text(entry_id, content.as_deref())?;
+		}
+
+		Ok(())
+	}
+
+	fn pending_full_texts(&self, feed_id: FeedId) -> Result<Vec<(FeedEntryId, String)>> {
+		use crate::database::schema::*;
+
+		let mut conn = self.db_pool.get()?;
+		let entries = feed_entry::table
+			.select((feed_entry::id, feed_entry::link.assume_not_null()))
+			.filter(feed_entry::feed_id.eq(feed_id))
+			.filter(feed_entry::link.is_not_null())
+			.filter(feed_entry::full_content_fetched_at.is_null())
+			.filter(dsl::exists(
+				user_feed::table
+					.filter(user_feed::feed_id.eq(feed_id))
+					.filter(user_feed::full_text),
+			))
+			.order_by(feed_entry::date.desc())
+			.limit(BATCH_SIZE)
+			.load(&mut conn)
+			.wrap_err("could not retrieve entries to download")?;
+
+		Ok(entries)
+	}
+
+	async fn fetch_article(&self, url: &Url) -> Result<Option<String>> {
+		let (response, _) = self.client.get(url).await?;
+
+		let status = response.status();
+		if !status.is_success() {
+			return Err(Error::Status {
+				status,
+				retry_after: None,
+			});
+		}
+
+		let is_html = response
+			.headers()
+			.get(header::CONTENT_TYPE)
+			.and_then(|value| value.to_str().ok())
+			.is_none_or(|mime| mime.contains("html"));
+		if !is_html {
+			return Ok(None);
+		}
+
+		// links are relative to the page we landed on
+		let base = response.url().clone();
+		let body = self.client.body(response).await?;
+
+		let content = main_content(&String::from_utf8_lossy(&body))
+			.map(|content| self.pipeline.run(&content, Context { base: &base }));
+
+		Ok(content)
+	}
+
+	fn store_full_text(&self, entry_id: FeedEntryId, content: Option<&str>) -> Result<()> {
+		use crate::database::schema::*;
+
+		let mut conn = self.db_pool.get()?;
+		dsl::update(feed_entry::table.find(entry_id))
+			.set((
+				feed_entry::full_content.eq(content.map(Cow::Borrowed)),
+				feed_entry::full_content_fetched_at.eq(OffsetDateTime::now_utc()),
+			))
+			.execute(&mut conn)
+			.wrap_err("could not store article content")?;
+
+		Ok(())
+	}
+}
+
+/// Extracts the main content of an article page, it still has to go through the [`Pipeline`]
+///
+/// Follows the readability approach: paragraphs give points to their parent and grand-parent,
+/// weighted by hints in class names and by how much of their text is made of links.
+fn main_content(html: &str) -> Option<String> {
+	let document = Html::parse_document(html);
+	let paragraphs = Selector::parse("p, pre, blockquote, td").expect("selector is valid");
+
+	let mut candidates = HashMap::new();
+	for paragraph in document.select(&paragraphs) {
+		let text = paragraph.text().collect::<String>();
+		let length = text.trim().chars().count();
+		if length < MIN_PARAGRAPH_LENGTH {
+			continue;
+		}
+
+		// longer paragraphs with more clauses are more likely to be prose
+		let score = 1 + text.matches(',').count() + (length / 100).min(3);
+
+		let Some(parent) = paragraph.parent().and_then(ElementRef::wrap) else {
+			continue;
+		};
+		candidates
+			.entry(parent.id())
+			.or_insert_with(|| (parent, hint_weight(parent)))
+			.1 += points(score);
+
+		if let Some(grand_parent) = parent.parent().and_then(ElementRef::wrap) {
+			candidates
+				.entry(grand_parent.id())
+				.or_insert_with(|| (grand_parent, hint_weight(grand_parent)))
+				.1 += points(score) / 2;
+		}
+	}
+
+	let (best, _) = candidates
+		.into_values()
+		.map(|(element, score)| (element, score * link_free_ratio(element) / 100))
+		.max_by_key(|(_, score)| *score)?;
+
+	if best.text().map(str::len).sum::<usize>() < MIN_CONTENT_LENGTH {
+		return None;
+	}
+
+	Some(best.html())
+}
+
+fn points(score: usize) -> i64 {
+	i64::try_from(score).unwrap_or(i64::MAX)
+}
+
+/// Bonus or malus given by the tag, class and id of an element
+fn hint_weight(element: ElementRef) -> i64 {
+	let value = element.value();
+	let hints = format!(
+		"{} {}",
+		value.id().unwrap_or_default(),
+		value.classes().collect::<Vec<_>>().join(" ")
+	)
+	.to_ascii_lowercase();
+
+	let mut weight = match value.name() {
+		"article" | "main" => 10,
+		"div" => 5,
+		"form" | "aside" | "footer" | "header" | "nav" => -10,
+		_ => 0,
+	};
+	if POSITIVE_HINTS.iter().any(|hint| hints.contains(hint)) {
+		weight += 25;
+	}
+	if NEGATIVE_HINTS.iter().any(|hint| hints.contains(hint)) {
+		weight -= 25;
+	}
+
+	weight
+}
+
+/// Percentage of the text of an element that is not part of a link
+fn link_free_ratio(element: ElementRef) -> i64 {
+	let links = Selector::parse("a").expect("selector is valid");
+
+	let length = element.text().map(str::len).sum::<usize>();
+	let link_length = element
+		.select(&links)
+		.flat_map(|link| link.text())
+		.map(str::len)
+		.sum::<usize>();
+
+	if length == 0 {
+		return 0;
+	}
+	points(100 - link_length.min(length) * 100 / length)
+}
@@ -0,0 +1,173 @@
+use std::time::{Duration, SystemTime};
+
+use feed_rs::model;
+use quick_xml::{Reader, events::Event};
+use reqwest::header::{self, HeaderMap};
+use url::Url;
+
+const HOUR: u64 = 60 * 60;
+const DAY: u64 = 24 * HOUR;
+
+/// Freshness lifetime from `Cache-Control: max-age`
+pub fn max_age(headers: &HeaderMap) -> Option<Duration> {
+	headers
+		.get_all(header::CACHE_CONTROL)
+		.iter()
+		.filter_map(|value| value.to_str().ok())
+		.flat_map(|value| value.split(','))
+		.find_map(|directive| {
+			let (name, value) = directive.trim().split_once('=')?;
+			name.eq_ignore_ascii_case("max-age")
+				.then(|| value.trim_matches('"').parse().ok())
+				.flatten()
+		})
+		.map(Duration::from_secs)
+}
+
+/// Delay from `Retry-After`, either in seconds or as an http date
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+	let value = headers.get(header::RETRY_AFTER)?.to_str().ok()?.trim();
+
+	if let Ok(secs) = value.parse() {
+		return Some(Duration::from_secs(secs));
+	}
+
+	httpdate::parse_http_date(value)
+		.ok()?
+		.duration_since(SystemTime::now())
+		.ok()
+}
+
+/// Minimum refresh interval advertised by the publisher through RSS `<ttl>` or the
+/// syndication module `<sy:updatePeriod>` and `<sy:updateFrequency>`
+pub fn publisher_interval(feed: &model::Feed, body: &[u8]) -> Option<Duration> {
+	let ttl = feed
+		.ttl
+		.map(|minutes| Duration::from_secs(u64::from(minutes) * 60));
+	ttl.into_iter().chain(syndication_interval(body)).max()
+}
+
+/// New location declared by a podcast feed through `<itunes:new-feed-url>`
+pub fn new_feed_url(body: &[u8]) -> Option<Url> {
+	let (_, url) = channel_text(body, &[b"new-feed-url"]).into_iter().next()?;
+	Url::parse(&url).ok()
+}
+
+/// Feed-rs does not parse the syndication module, scan the channel header ourselves
+fn syndication_interval(body: &[u8]) -> Option<Duration> {
+	let mut period = None;
+	let mut frequency = None;
+	for (name, text) in channel_text(body, &[b"updatePeriod", b"updateFrequency"]) {
+		match name {
+			b"updatePeriod" => period = Some(text),
+			_ => frequency = text.parse::<u32>().ok(),
+		}
+	}
+
+	let period = match period?.as_str() {
+		"hourly" => HOUR,
+		"daily" => DAY,
+		"weekly" => 7 * DAY,
+		"monthly" => 30 * DAY,
+		"yearly" => 365 * DAY,
+		_ => return None,
+	};
+
+	let frequency = frequency.filter(|freq| *freq > 0).unwrap_or(1);
+	Some(Duration::from_secs(period / u64::from(frequency)))
+}
+
+/// Text of the channel level elements with the given local names, in document order
+fn channel_text<'n>(body: &[u8], names: &[&'n [u8]]) -> Vec<(&'n [u8], String)> {
+	let mut reader = Reader::from_reader(body);
+	let mut buf = Vec::new();
+
+	let mut current = None;
+	let mut found = Vec::new();
+
+	while let Ok(event) = reader.read_event_into(&mut buf) {
+		match event {
+			Event::Start(start) => {
+				let local_name = start.local_name();
+				// only channel level elements are relevant
+				if matches!(local_name.as_ref(), b"item" | b"entry") {
+					break;
+				}
+				current = names
+					.iter()
+					.find(|name| **name == local_name.as_ref())
+					.copied();
+			}
+			Event::Text(text) => {
+				if let (Some(name), Ok(text)) = (current, text.unescape()) {
+					found.push((name, text.trim().to_owned()));
+				}
+			}
+			Event::End(_) => current = None,
+			Event::Eof => break,
+			_ => {}
+		}
+		buf.clear();
+	}
+
+	found
+}
+
+#[cfg(test)]
+mod tests {
+	use reqwest::header::HeaderValue;
+
+	use super::*;
+
+	fn headers(name: &header::HeaderName, values: &[&'static str]) -> HeaderMap {
+		let mut headers = HeaderMap::new();
+		for value in values {
+			headers.append(name.clone(), HeaderValue::from_static(value));
+		}
+		headers
+	}
+
+	#[test]
+	fn reads_max_age() {
+		let parse = |values| max_age(&headers(&header::CACHE_CONTROL, values));
+
+		assert_eq!(parse(&["max-age=300"]), Some(Duration::from_mins(5)));
+		assert_eq!(
+			parse(&["public, Max-Age=\"60\", must-revalidate"]),
+			Some(Duration::from_mins(1))
+		);
+		assert_eq!(
+			parse(&["no-transform", "max-age=120"]),
+			Some(Duration::from_mins(2))
+		);
+		assert_eq!(parse(&["s-maxage=300"]), None);
+		assert_eq!(parse(&["max-age=-1"]), None);
+		assert_eq!(parse(&["no-cache"]), None);
+		assert_eq!(parse(&[]), None);
+	}
+
+	#[test]
+	fn reads_retry_after() {
+		let parse = |values| retry_after(&headers(&header::RETRY_AFTER, values));
+
+		assert_eq!(parse(&["120"]), Some(Duration::from_mins(2)));
+		assert_eq!(parse(&[" 5 "]), Some(Duration::from_secs(5)));
+		assert_eq!(
+			parse(&["18446744073709551615"]),
+			Some(Duration::from_secs(u64::MAX))
+		);
+		assert_eq!(parse(&["Wed, 21 Oct 2015 07:28:00 GMT"]), None);
+		assert_eq!(parse(&["soon"]), None);
+		assert_eq!(parse(&[]), None);
+
+		let later = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(HOUR));
+		let mut headers = HeaderMap::new();
+		headers.insert(
+			header::RETRY_AFTER,
+			HeaderValue::from_str(&later).expect("dates are valid header values"),
+		);
+		let delay = retry_after(&headers).expect("date is in the future");
+		assert!(delay <= Duration::from_secs(HOUR));
+		assert!(delay >= Duration::from_secs(HOUR - 5));
+	}
+}
@@ -0,0 +1,202 @@
+use std::borrow::Cow;
+
+use bytes::Bytes;
+use diesel::{dsl, prelude::*};
+use eyre::WrapErr;
+use itertools::Itertools;
+use reqwest::header;
+use scraper::{Html, Selector};
+use sha2::{Digest, Sha256};
+use time::{Duration, OffsetDateTime};
+use url::Url;
+
+use crate::{
+	database::models::{FeedId, FeedMetadata, Icon, IconId, NewIcon},
+	fetcher::{Error, Fetcher, Result},
+};
+
+/// Delay before looking for the icon of a feed again, sites rarely change them
+const ICON_MAX_AGE: Duration = Duration::days(30);
+/// Larger images are not icons
+const MAX_ICON_SIZE: usize = 1024 * 1024;
+
+/// Icon downloaded while looking for the icon of a feed
+#[derive(Debug)]
+struct FetchedIcon {
+	mime: String,
+	content: Bytes,
+}
+
+impl Fetcher {
+	/// Looks for the icon of a feed when it has none or when it is stale
+	///
+	/// Tries the icon and logo advertised by the feed, then the icons of its site.
+	pub(super) async fn refresh_icon(&self, feed_id: FeedId) -> Result<()> {
+		use crate::database::schema::*;
+
+		let (url, checked_at, metadata) = {
+			let mut conn = self.db_pool.get()?;
+			feed::table
+				.find(feed_id)
+				.select((feed::url, feed::icon_checked_at, FeedMetadata::as_select()))
+				.get_result::<(String, Option<OffsetDateTime>, FeedMetadata)>(&mut conn)
+				.wrap_err("could not retrieve feed metadata")?
+		};
+
+		if checked_at
+			.is_some_and(|checked_at| checked_at + ICON_MAX_AGE > OffsetDateTime::now_utc())
+		{
+			return Ok(());
+		}
+
+		let mut candidates = [&metadata.icon_url, &metadata.logo_url]
+			.into_iter()
+			.flatten()
+			.filter_map(|url| Url::parse(url).ok())
+			.collect::<Vec<_>>();
+
+		let site = metadata
+			.site_link
+			.as_deref()
+			.and_then(|site| Url::parse(site).ok());
+		if let Some(site) = &site {
+			match self.page_icons(site).await {
+				Ok(icons) => candidates.extend(icons),
+				Err(err) => {
+					tracing::debug!(feed_id = ?feed_id, url = %site, err = %err, "could not download site page");
+				}
+			}
+		}
+
+		// feeds without a site link usually live on it
+		let origin = site.or_else(|| Url::parse(&url).ok());
+		if let Some(favicon) = origin.and_then(|origin| origin.join("/favicon.ico").ok()) {
+			candidates.push(favicon);
+		}
+
+		let mut icon = None;
+		for url in candidates.iter().unique() {
+			match self.fetch_icon(url).await {
+				Ok(Some(found)) => {
+					icon = Some(found);
+					break;
+				}
+				Ok(None) => {}
+				Err(err) => {
+					tracing::debug!(feed_id = ?feed_id, url = %url, err = %err, "could not download icon");
+				}
+			}
+		}
+
+		self.store_icon(feed_id, icon.as_ref())
+	}
+
+	/// Icons referenced by the `<link rel="icon">` tags of a page
+	async fn page_icons(&self, url: &Url) -> Result<Vec<Url>> {
+		let (response, _) = self.client.get(url).await?;
+
+		let status = response.status();
+		if !status.is_success() {
+			return Err(Error::Status {
+				status,
+				retry_after: None,
+			});
+		}
+
+		// links are relative to the page we landed on
+		let base = response.url().clone();
+		let body = self.client.body(response).await?;
+
+		let document = Html::parse_document(&String::from_utf8_lossy(&body));
+		let selector =
+			Selector::parse(r#"link[rel~="icon"][href], link[rel="apple-touch-icon"][href]"#)
+				.expect("selector is valid");
+
+		let icons = document
+			.select(&selector)
+			.filter_map(|link| base.join(link.attr("href")?.trim()).ok())
+			.filter(|url| matches!(url.scheme(), "http" | "https"))
+			.collect();
+
+		Ok(icons)
+	}
+
+	async fn fetch_icon(&self, url: &Url) -> Result<Option<FetchedIcon>> {
+		let (response, _) = self.client.get(url).await?;
+
+		let status = response.status();
+		if !status.is_success() {
+			return Err(Error::Status {
+				status,
+				retry_after: None,
+			});
+		}
+
+		// missing favicons are sometimes answered with an html page
+		let Some(mime) = response
+			.headers()
+			.get(header::CONTENT_TYPE)
+			.and_then(|value| value.to_str().ok())
+			.map(|mime| {
+				mime.split(';')
+					.next()
+					.unwrap_or_default()
+					.trim()
+					.to_ascii_lowercase()
+			})
+			.filter(|mime| mime.starts_with("image/"))
+		else {
+			return Ok(None);
+		};
+
+		let content = self.client.body(response).await?;
+		if content.is_empty() || content.len() > MAX_ICON_SIZE {
+			return Ok(None);
+		}
+
+		Ok(Some(FetchedIcon { mime, content }))
+	}
+
+	/// Links the icon to the feed, a feed keeps its previous icon when none was found
+	fn store_icon(&self, feed_id: FeedId, icon: Option<&FetchedIcon>) -> Result<()> {
+		use crate::database::schema::*;
+
+		let mut conn = self.db_pool.get()?;
+		conn.transaction::<_, diesel::result::Error, _>(|conn| {
+			let now = OffsetDateTime::now_utc();
+
+			let Some(icon) = icon else {
+				dsl::update(feed::table.find(feed_id))
+					.set(feed::icon_checked_at.eq(now))
+					.execute(conn)?;
+				return Ok(());
+			};
+
+			let previous = feed::table
+				.find(feed_id)
+				.select(feed::icon_id)
+				.get_result::<Option<IconId>>(conn)?;
+
+			let icon_id = NewIcon {
+				hash: Cow::Owned(format!("{:x}", Sha256::digest(&icon.content))),
+				mime: Cow::Borrowed(&icon.mime),
+				content: &icon.content,
+				fetched_at: now,
+			}
+			.upsert(conn)?;
+
+			dsl::update(feed::table.find(feed_id))
+				.set((feed::icon_id.eq(icon_id), feed::icon_checked_at.eq(now)))
+				.execute(conn)?;
+
+			if let Some(previous) = previous.filter(|previous| *previous != icon_id) {
+				Icon::delete_orphans(&[previous], conn)?;
+			}
+
+			Ok(())
+		})
+		.wrap_err("could not store feed icon")?;
+
+		Ok(())
+	}
+}
@@ -1,151 +1,1270 @@
-use bytes::Buf;
+use std::{
+	borrow::Cow,
+	collections::HashSet,
+	error::Error as _,
+	iter,
+	sync::Arc,
+	time::{Duration, Instant},
+};
+
+use bytes::Bytes;
 use diesel::{dsl, prelude::*};
 use eyre::WrapErr;
-use feed_rs::parser;
-use reqwest::{Client, Response, StatusCode};
+use feed_rs::{model, parser};
+use itertools::Itertools;
+use reqwest::{StatusCode, header};
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
 use tokio::{
-	sync::mpsc::{self, Receiver},
+	sync::{Notify, OwnedSemaphorePermit, Semaphore},
 	task,
+	time::timeout,
 };
 use url::Url;
 
-use crate::database::{PoolConnection, models::FeedId};
+use crate::{
+	config::{Config, FetcherConfig, SchedulerConfig},
+	database::{
+		PoolConnection, PooledConnection,
+		models::{
+			Feed, FeedEntry, FeedEntryId, FeedFetchLog, FeedId, FeedMetadata, FetchJobId,
+			NewFeedEntry, NewFeedEntryEnclosure, NewFeedEntryRevision, NewFeedFetchLog,
+			NewFetchJob, UserId, WebSubSubscriptionId,
+		},
+	},
+	scheduler,
+};
 
+mod adapters;
+mod client;
+mod dedup;
+mod discover;
 mod error;
+mod full_text;
+mod hints;
+mod icon;
+mod newsletter;
+mod options;
+mod policy;
+mod pool;
+mod preview;
+mod proxy;
+mod redirect;
+mod rewrite;
+mod scrape;
+mod websub;
+
+use self::adapters::Adapters;
+use self::client::HttpClient;
+pub use self::discover::Candidate;
+pub use self::error::{Error, ErrorKind, Result};
+pub use self::newsletter::Letter;
+use self::options::{Cipher, RequestOptions};
+pub use self::options::{Credentials, validate_header};
+use self::pool::{HostLimiter, HostSlot};
+pub use self::preview::Preview;
+pub use self::proxy::Media;
+use self::proxy::{MediaCache, Signer};
+use self::redirect::Redirect;
+use self::rewrite::{Context, Pipeline};
+pub use self::scrape::{ScrapeRules, ScrapedItem};
+pub use self::websub::Intent;
+use self::websub::Pushed;
 
-pub use self::error::{Error, Result};
+/// Number of latest entries used to estimate how often a feed is updated
+const POSTING_FREQUENCY_SAMPLE: i64 = 10;
+/// Time a refreshed feed may wait for a worker, on top of the request timeout
+const REFRESH_QUEUE_DELAY: Duration = Duration::from_secs(10);
+const REFRESH_POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// Feeds fetched more recently are not refreshed again, their last attempt stands
+const REFRESH_MIN_INTERVAL: Duration = Duration::from_mins(1);
+/// Minimum time jobs of a busy host are hidden from workers
+const BUSY_HOST_DELAY: Duration = Duration::from_secs(1);
+
+/// Order in which queued feeds are fetched
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+	Background = 0,
+	User = 10,
+	/// Refreshes asked for explicitly, the user may be waiting for them
+	Refresh = 20,
+}
+
+/// Queues the feeds in the `fetch_job` table, to be picked up by any server instance
+///
+/// Call [`FetcherHandle::wake`] once the transaction is committed to start fetching right away.
+pub fn enqueue(
+	feed_ids: impl IntoIterator<Item = FeedId>,
+	priority: Priority,
+	conn: &mut PooledConnection,
+) -> QueryResult<usize> {
+	let jobs = feed_ids
+		.into_iter()
+		.map(|feed_id| NewFetchJob {
+			feed_id,
+			priority: priority as i32,
+		})
+		.collect::<Vec<_>>();
+
+	NewFetchJob::enqueue_all(&jobs, conn)
+}
 
 #[derive(Debug)]
 pub struct Fetcher {
-	client: Client,
-	rx: Receiver<FetchTask>,
+	client: HttpClient,
+	/// Maps urls of well known sites to their feeds during discovery
+	adapters: Adapters,
 	db_pool: PoolConnection,
+	config: FetcherConfig,
+	scheduler_config: SchedulerConfig,
+
+	/// Bounds the number of feeds fetched at the same time
+	workers: Arc<Semaphore>,
+	host_limiter: HostLimiter,
+	/// Signaled when jobs are enqueued by this instance
+	notify: Notify,
+
+	/// Base of the urls hubs push content to, `None` when websub is disabled
+	callback_url: Option<Url>,
+	pipeline: Pipeline,
+	/// Signs the media urls of entry contents, urls signed before are still served when disabled
+	signer: Signer,
+	media_cache: MediaCache,
+	/// Encrypts the credentials of feeds
+	cipher: Cipher,
 }
 
 impl Fetcher {
-	pub fn setup(db_pool: PoolConnection) -> eyre::Result<FetcherHandle> {
-		// TODO: see how to handle large traffic
-		let (tx, rx) = mpsc::channel(100);
+	pub fn setup(db_pool: PoolConnection, config: &Config) -> eyre::Result<FetcherHandle> {
+		let client = HttpClient::new(&config.fetcher)?;
 
-		let user_agent = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
-		let client = Client::builder()
-			.user_agent(user_agent)
-			.build()
-			.wrap_err("could not build client")?;
+		// hubs reach back to the `/websub/{id}` route of the instance
+		let callback_url = config
+			.fetcher
+			.websub
+			.then(|| {
+				let base_url = config.web.base_url.trim_end_matches('/');
+				Url::parse(&format!("{base_url}/websub/")).wrap_err("invalid web base url")
+			})
+			.transpose()?;
+
+		let signer = Signer::new(config)?;
+		let pipeline = Pipeline::from_config(
+			&config.content,
+			config.proxy.enabled.then(|| signer.clone()),
+		);
 
 		let fetcher = Self {
 			client,
-			rx,
+			adapters: Adapters::default(),
 			db_pool,
+			config: config.fetcher.clone(),
+			scheduler_config: config.scheduler.clone(),
+
+			workers: Arc::new(Semaphore::new(config.fetcher.concurrency)),
+			host_limiter: HostLimiter::new(
+				config.fetcher.per_host_concurrency,
+				Duration::from_millis(config.fetcher.per_host_delay),
+			),
+			notify: Notify::new(),
+
+			callback_url,
+			pipeline,
+			signer,
+			media_cache: MediaCache::new(&config.proxy),
+			cipher: Cipher::new(config),
 		};
-		fetcher.spawn();
+		let fetcher = Arc::new(fetcher);
+		Arc::clone(&fetcher).spawn();
 
-		Ok(FetcherHandle { queue: tx })
+		Ok(FetcherHandle { fetcher })
 	}
 
-	fn spawn(self) {
+	fn spawn(self: Arc<Self>) {
 		task::spawn(self.loop_task());
 	}
 
-	async fn loop_task(mut self) {
-		while let Some(task) = self.rx.recv().await {
-			if let Err(err) = self.task(task).await {
-				tracing::error!(err = %err, "error while fetching");
+	async fn loop_task(self: Arc<Self>) {
+		loop {
+			// only claim jobs that can be started right away, others stay available to other instances
+			let permit = Arc::clone(&self.workers)
+				.acquire_owned()
+				.await
+				.expect("worker semaphore is never closed");
+
+			let tasks = self
+				.claim_tasks(1 + self.workers.available_permits())
+				.unwrap_or_else(|err| {
+					tracing::error!(err = %err, "error while claiming fetch jobs");
+					Vec::new()
+				});
+
+			if tasks.is_empty() {
+				drop(permit);
+				// jobs enqueued by other instances are picked up on the next poll
+				let _ = timeout(
+					Duration::from_secs(self.config.poll_interval),
+					self.notify.notified(),
+				)
+				.await;
+				continue;
+			}
+
+			// this loop is the only one acquiring workers, claimed tasks all get a permit
+			let permits = iter::once(permit).chain(iter::from_fn(|| {
+				Arc::clone(&self.workers).try_acquire_owned().ok()
+			}));
+			for (task, permit) in tasks.into_iter().zip(permits) {
+				// workers never wait on a busy host, its jobs are left to be claimed later
+				let host = task.url.host_str().unwrap_or_default();
+				let Some(slot) = self.host_limiter.try_acquire(host) else {
+					if let Err(err) = self.postpone_job(task.job_id) {
+						tracing::error!(err = %err, "error while postponing fetch job");
+					}
+					continue;
+				};
+				task::spawn(Arc::clone(&self).worker(task, permit, slot));
+			}
+		}
+	}
+
+	/// Fetches a task with a slot on its host
+	async fn worker(
+		self: Arc<Self>,
+		task: FetchTask,
+		permit: OwnedSemaphorePermit,
+		slot: HostSlot,
+	) {
+		let (job_id, generation) = (task.job_id, task.generation);
+		match self.task(task).await {
+			Ok(()) => {
+				if let Err(err) = self.complete_job(job_id, generation) {
+					tracing::error!(err = %err, "error while completing fetch job");
+				}
+			}
+			// the job becomes visible again once its visibility timeout expires
+			Err(err) => tracing::error!(err = %err, "error while fetching"),
+		}
+
+		// the worker is freed as soon as the feed is fetched, before the politeness delay
+		drop(permit);
+		self.host_limiter.release(slot).await;
+		// postponed jobs of the host can be claimed again
+		self.notify.notify_one();
+	}
+
+	/// Takes the most urgent visible jobs and hides them from other workers for the visibility
+	/// timeout
+	fn claim_tasks(&self, limit: usize) -> eyre::Result<Vec<FetchTask>> {
+		use crate::database::schema::*;
+
+		let mut conn = self.db_pool.get()?;
+		let jobs = conn
+			.transaction::<_, diesel::result::Error, _>(|conn| {
+				// the scheduler enqueues these feeds again once they are due
+				let dropped = dsl::delete(
+					fetch_job::table
+						.filter(fetch_job::visible_at.le(dsl::now))
+						.filter(fetch_job::attempts.ge(self.config.max_attempts)),
+				)
+				.returning(fetch_job::feed_id)
+				.get_results::<FeedId>(conn)?;
+				for feed_id in dropped {
+					tracing::warn!(feed_id = ?feed_id, "dropping fetch job after too many attempts");
+				}
+
+				let jobs = fetch_job::table
+					.select((fetch_job::id, fetch_job::feed_id, fetch_job::generation))
+					.filter(fetch_job::visible_at.le(dsl::now))
+					.order_by((fetch_job::priority.desc(), fetch_job::visible_at.asc()))
+					.limit(i64::try_from(limit).unwrap_or(i64::MAX))
+					.for_update()
+					.skip_locked()
+					.load::<(FetchJobId, FeedId, i32)>(conn)?;
+
+				let job_ids = jobs
+					.iter()
+					.map(|(job_id, _, _)| *job_id)
+					.collect::<Vec<_>>();
+				let visible_at =
+					OffsetDateTime::now_utc() + Duration::from_secs(self.config.visibility_timeout);
+				dsl::update(fetch_job::table.filter(fetch_job::id.eq_any(&job_ids)))
+					.set((
+						fetch_job::visible_at.eq(visible_at),
+						fetch_job::attempts.eq(fetch_job::attempts + 1),
+					))
+					.execute(conn)?;
+
+				let feed_ids = jobs
+					.iter()
+					.map(|(_, feed_id, _)| *feed_id)
+					.collect::<Vec<_>>();
+				let urls = feed::table
+					.select((feed::id, feed::url, feed::source))
+					.filter(feed::id.eq_any(&feed_ids))
+					.load::<(FeedId, String, String)>(conn)?;
+
+				Ok(jobs
+					.into_iter()
+					.filter_map(|(job_id, feed_id, generation)| {
+						let (_, url, source) = urls.iter().find(|(id, _, _)| *id == feed_id)?;
+						Some((job_id, feed_id, generation, url.clone(), source.clone()))
+					})
+					.collect::<Vec<_>>())
+			})
+			.wrap_err("could not claim fetch jobs")?;
+
+		let mut tasks = Vec::with_capacity(jobs.len());
+		for (job_id, feed_id, generation, url, source) in jobs {
+			// newsletters are received by mail, there is nothing to fetch
+			if source == "newsletter" {
+				self.complete_job(job_id, generation)?;
+				continue;
+			}
+
+			let Ok(url) = Url::parse(&url) else {
+				tracing::warn!(feed_id = ?feed_id, url, "dropping fetch job of feed with invalid url");
+				self.complete_job(job_id, generation)?;
+				continue;
+			};
+
+			tasks.push(FetchTask {
+				job_id,
+				generation,
+				feed_id,
+				url,
+			});
+		}
+
+		Ok(tasks)
+	}
+
+	/// Removes a job from the queue, unless its feed was queued again since it was claimed
+	fn complete_job(&self, job_id: FetchJobId, generation: i32) -> eyre::Result<()> {
+		use crate::database::schema::*;
+
+		let mut conn = self.db_pool.get()?;
+		let deleted = dsl::delete(
+			fetch_job::table
+				.find(job_id)
+				.filter(fetch_job::generation.eq(generation)),
+		)
+		.execute(&mut conn)
+		.wrap_err("could not delete fetch job")?;
+
+		// the feed is fetched again for whoever queued it during this fetch
+		if deleted == 0 {
+			dsl::update(fetch_job::table.find(job_id))
+				.set((
+					fetch_job::visible_at.eq(dsl::now),
+					fetch_job::attempts.eq(0),
+				))
+				.execute(&mut conn)
+				.wrap_err("could not release fetch job")?;
+		}
+
+		Ok(())
+	}
+
+	/// Gives back a job whose host is busy, without counting it as an attempt
+	fn postpone_job(&self, job_id: FetchJobId) -> eyre::Result<()> {
+		use crate::database::schema::*;
+
+		let delay = Duration::from_millis(self.config.per_host_delay).max(BUSY_HOST_DELAY);
+		let mut conn = self.db_pool.get()?;
+		dsl::update(fetch_job::table.find(job_id))
+			.set((
+				fetch_job::visible_at.eq(OffsetDateTime::now_utc() + delay),
+				fetch_job::attempts.eq(fetch_job::attempts - 1),
+			))
+			.execute(&mut conn)
+			.wrap_err("could not postpone fetch job")?;
+
+		Ok(())
+	}
+
+	/// Queues a feed ahead of the others and waits for its fetch when asked to
+	///
+	/// Returns the first attempt logged after the call, `None` when not waited for or when the
+	/// feed was not fetched in time. Feeds fetched less than [`REFRESH_MIN_INTERVAL`] ago are not
+	/// queued, their last attempt is returned instead.
+	async fn refresh(
+		&self,
+		feed_id: FeedId,
+		wait: bool,
+	) -> eyre::Result<Option<FeedFetchLog<'static>>> {
+		use crate::database::schema::*;
+
+		let last_log_id = {
+			let mut conn = self.db_pool.get()?;
+			let last_log = feed_fetch_log::table
+				.filter(feed_fetch_log::feed_id.eq(feed_id))
+				.order_by(feed_fetch_log::id.desc())
+				.select(FeedFetchLog::as_select())
+				.first::<FeedFetchLog<'static>>(&mut conn)
+				.optional()
+				.wrap_err("could not retrieve fetch log")?;
+			if last_log.as_ref().is_some_and(|last_log| {
+				OffsetDateTime::now_utc() - last_log.fetched_at < REFRESH_MIN_INTERVAL
+			}) {
+				return Ok(last_log);
 			}
+
+			enqueue([feed_id], Priority::Refresh, &mut conn)?;
+			last_log.map(|last_log| last_log.id)
+		};
+		self.notify.notify_one();
+
+		if !wait {
+			return Ok(None);
 		}
+
+		// another instance may claim the job, the fetch log tells when it is done
+		let deadline =
+			Instant::now() + Duration::from_secs(self.config.request_timeout) + REFRESH_QUEUE_DELAY;
+		while Instant::now() < deadline {
+			tokio::time::sleep(REFRESH_POLL_INTERVAL).await;
+
+			let mut query = feed_fetch_log::table
+				.filter(feed_fetch_log::feed_id.eq(feed_id))
+				.order_by(feed_fetch_log::id.asc())
+				.select(FeedFetchLog::as_select())
+				.into_boxed();
+			if let Some(last_log_id) = last_log_id {
+				query = query.filter(feed_fetch_log::id.gt(last_log_id));
+			}
+
+			let mut conn = self.db_pool.get()?;
+			let log = query
+				.first::<FeedFetchLog<'static>>(&mut conn)
+				.optional()
+				.wrap_err("could not retrieve fetch log")?;
+			if log.is_some() {
+				return Ok(log);
+			}
+		}
+
+		Ok(None)
 	}
 
 	async fn task(&self, task: FetchTask) -> Result<()> {
-		let FetchTask { feed_id, url } = task;
+		let FetchTask { feed_id, url, .. } = task;
+
+		let validators = self.cache_validators(feed_id)?;
+		let options = self.request_options(feed_id)?;
+		let rules = self.scrape_rules(feed_id)?;
+
+		let fetched_at = OffsetDateTime::now_utc();
+		let timer = Instant::now();
+		let result = self
+			.fetch(&url, &validators, &options, rules.as_ref())
+			.await;
+		let log = fetch_log(feed_id, fetched_at, timer.elapsed(), &result);
+
+		match result {
+			Ok(outcome) => {
+				let feed_id = self.on_fetched(feed_id, &url, &outcome, &log)?;
+
+				// the feed itself was stored, hub errors are retried on the next fetch
+				let url = outcome
+					.moved_to
+					.as_ref()
+					.or(outcome.declared_move.as_ref())
+					.unwrap_or(&url);
+				if let Err(err) = self
+					.maintain_subscription(feed_id, url, outcome.feed.as_deref())
+					.await
+				{
+					tracing::warn!(feed_id = ?feed_id, err = %err, "could not subscribe to websub hub");
+				}
+
+				if let Err(err) = self.refresh_icon(feed_id).await {
+					tracing::warn!(feed_id = ?feed_id, err = %err, "could not refresh icon");
+				}
 
-		// TODO: log errors in the database to notify user
+				if let Err(err) = self.fetch_full_texts(feed_id).await {
+					tracing::warn!(feed_id = ?feed_id, err = %err, "could not download articles");
+				}
 
+				Ok(())
+			}
+			Err(err) => self.on_failed(feed_id, &url, &err, &log),
+		}
+	}
+
+	fn cache_validators(&self, feed_id: FeedId) -> Result<CacheValidators> {
+		use crate::database::schema::*;
+
+		let mut conn = self.db_pool.get()?;
+		let validators = feed::table
+			.find(feed_id)
+			.select(CacheValidators::as_select())
+			.get_result(&mut conn)
+			.wrap_err("could not retrieve feed cache validators")?;
+
+		Ok(validators)
+	}
+
+	async fn fetch(
+		&self,
+		url: &Url,
+		validators: &CacheValidators,
+		options: &RequestOptions,
+		rules: Option<&ScrapeRules>,
+	) -> Result<FetchOutcome> {
 		// url.set_scheme("https")
 
-		let response = self.client.get(url.clone()).send().await;
-		let body = response
-			.wrap_err("could not reach server")?
-			.error_for_status()
-			.wrap_err("server returned an error")?
-			.bytes()
-			.await
-			.wrap_err("could not access request body")?;
+		// invalid certificates are only accepted from the origin of the feed, like credentials
+		let request = |hop: &Url| {
+			let mut request = if options.accept_invalid_certs && hop.origin() == url.origin() {
+				self.client.insecure_request(hop)
+			} else {
+				self.client.request(hop)
+			};
+			request = options.apply(request, hop, url);
+			if let Some(etag) = &validators.etag {
+				request = request.header(header::IF_NONE_MATCH, etag);
+			}
+			if let Some(last_modified) = &validators.last_modified {
+				request = request.header(header::IF_MODIFIED_SINCE, last_modified);
+			}
+			request
+		};
 
-		let parser = parser::Builder::new().sanitize_content(true).build();
+		let (response, redirects) = self.client.send(url, request).await?;
+		let final_url = response.url().clone();
+		let moved_to = redirect::permanent_target(&redirects).cloned();
 
-		let feed = parser
-			.parse(body.reader())
-			.wrap_err("could not parse feed")?;
+		// servers may refresh validators on a `304`, otherwise keep the known ones
+		let header_value = |name| {
+			response
+				.headers()
+				.get(name)
+				.and_then(|value| value.to_str().ok())
+				.map(ToOwned::to_owned)
+		};
+		let mut new_validators = CacheValidators {
+			etag: header_value(header::ETAG).or_else(|| validators.etag.clone()),
+			last_modified: header_value(header::LAST_MODIFIED)
+				.or_else(|| validators.last_modified.clone()),
+			content_hash: validators.content_hash.clone(),
+		};
 
-		tracing::debug!(feed_id = ?feed_id, url = %url, "sucessfully fetched feed");
+		let status = response.status();
+		let max_age = hints::max_age(response.headers());
+		let unchanged = FetchOutcome {
+			http_status: status,
+			bytes: 0,
+			feed: None,
+			publisher_interval: None,
+			max_age,
+			validators: new_validators.clone(),
+			redirects: redirects.clone(),
+			moved_to: moved_to.clone(),
+			declared_move: None,
+		};
 
-		// let new_entries = feed.entries.iter().take_while(|feed| feed.updated)
+		if status == StatusCode::NOT_MODIFIED {
+			return Ok(unchanged);
+		}
 
-		dbg!(feed);
+		if !status.is_success() {
+			return Err(Error::Status {
+				status,
+				retry_after: hints::retry_after(response.headers()),
+			});
+		}
 
-		Ok(())
+		let body = self.client.body(response).await?;
+
+		// some servers do not support conditional requests, avoid parsing the same content
+		let content_hash = format!("{:x}", Sha256::digest(&body));
+		if validators.content_hash.as_ref() == Some(&content_hash) {
+			return Ok(unchanged);
+		}
+		new_validators.content_hash = Some(content_hash);
+
+		// scraped pages go through the same path as feeds once turned into one
+		let body = match rules {
+			Some(rules) => {
+				let html = String::from_utf8_lossy(&body);
+				let items = scrape::scrape(rules, &final_url, &html)?;
+				Bytes::from(scrape::json_feed(&final_url, &html, &items))
+			}
+			None => body,
+		};
+
+		// contents are sanitized by the pipeline once rewritten
+		let feed = parser::Builder::new()
+			.base_uri(Some(&final_url))
+			.sanitize_content(false)
+			.build()
+			.parse(&body[..])?;
+
+		// http redirects are authoritative, locations declared in the content are only followed
+		// once they are confirmed to serve the same feed
+		let declared = hints::new_feed_url(&body)
+			.or_else(|| self_link(&feed))
+			.filter(|declared| !same_location(declared, &final_url));
+		let declared_move = match declared {
+			Some(declared) if moved_to.is_none() => self
+				.serves_same_feed(&declared, url, &feed, options)
+				.await
+				.then_some(declared),
+			_ => None,
+		};
+
+		Ok(FetchOutcome {
+			http_status: status,
+			bytes: body.len(),
+			publisher_interval: hints::publisher_interval(&feed, &body),
+			feed: Some(Box::new(feed)),
+			max_age,
+			validators: new_validators,
+			redirects,
+			moved_to,
+			declared_move,
+		})
 	}
 
-	// async fn process(&self, task: FetchTask, mut conn: &mut PoolConnection) -> Result<(), FetchError> {}
+	/// Whether a location declared by a feed serves the same feed, by its self link or entries
+	async fn serves_same_feed(
+		&self,
+		declared: &Url,
+		url: &Url,
+		feed: &model::Feed,
+		options: &RequestOptions,
+	) -> bool {
+		// credentials only go to the origin of the feed
+		let request = |hop: &Url| options.apply(self.client.request(hop), hop, url);
 
-	fn on_fetched(&self, feed_id: FeedId, url: &Url, response: &Response) -> Result<()> {
+		let body = match self.client.send(declared, request).await {
+			Ok((response, _)) if response.status().is_success() => self.client.body(response).await,
+			Ok((response, _)) => {
+				tracing::debug!(url = %declared, status = %response.status(), "declared feed location is unreachable");
+				return false;
+			}
+			Err(err) => Err(err),
+		};
+		let other = match body.and_then(|body| {
+			let parser = parser::Builder::new().sanitize_content(false).build();
+			Ok(parser.parse(&body[..])?)
+		}) {
+			Ok(other) => other,
+			Err(err) => {
+				tracing::debug!(url = %declared, err = %err, "declared feed location is unreachable");
+				return false;
+			}
+		};
+
+		let same_self_link = self_link(feed)
+			.zip(self_link(&other))
+			.is_some_and(|(link, other_link)| same_location(&link, &other_link));
+		let guids = feed
+			.entries
+			.iter()
+			.map(|entry| entry.id.as_str())
+			.collect::<HashSet<_>>();
+		same_self_link
+			|| other
+				.entries
+				.iter()
+				.any(|entry| guids.contains(entry.id.as_str()))
+	}
+
+	/// Stores a fetched feed, returns its id once relocated
+	fn on_fetched(
+		&self,
+		feed_id: FeedId,
+		url: &Url,
+		outcome: &FetchOutcome,
+		log: &NewFeedFetchLog,
+	) -> Result<FeedId> {
 		use crate::database::schema::*;
 
-		tracing::info!("sucessfully fetched url {url} for feed_id {feed_id:?}: {response:?}",);
+		tracing::debug!(feed_id = ?feed_id, url = %url, changed = outcome.feed.is_some(), "sucessfully fetched feed");
+
+		let metadata = outcome.feed.as_deref().map(feed_metadata);
 
 		let mut conn = self.db_pool.get()?;
-		conn.transaction::<_, eyre::Report, _>(|conn| {
-			dsl::update(feed::table)
-				.set(feed::status.eq("ok"))
+		let (feed_id, upserted, next_fetch_at) =
+			conn.transaction::<_, eyre::Report, _>(|conn| {
+				// the feed may be merged into another one that already uses the new url, but only
+				// on the word of the server, not of the content
+				let feed_id = match (&outcome.moved_to, &outcome.declared_move) {
+					(Some(moved_to), _) => {
+						tracing::info!(feed_id = ?feed_id, from = %url, to = %moved_to, "feed moved permanently");
+						Feed::relocate(feed_id, moved_to, conn)
+							.wrap_err("unable to relocate feed")?
+					}
+					(None, Some(declared)) => {
+						if Feed::rename(feed_id, declared, conn).wrap_err("unable to move feed")? {
+							tracing::info!(feed_id = ?feed_id, from = %url, to = %declared, "feed declared a new location");
+						} else {
+							tracing::info!(feed_id = ?feed_id, to = %declared, "feed declared a location used by another feed");
+						}
+						feed_id
+					}
+					(None, None) => feed_id,
+				};
+
+				NewFeedFetchLog {
+					feed_id,
+					..log.clone()
+				}
+				.insert_into(feed_fetch_log::table)
 				.execute(conn)
-				.wrap_err("unable to update feed status")?;
+				.wrap_err("unable to journal fetch attempt")?;
 
-			// TODO: filter and add feed entries
+				let upserted = match outcome.feed.as_deref() {
+					Some(feed) if !feed.entries.is_empty() => {
+						store_entries(&self.pipeline, feed_id, url, feed, conn)?
+					}
+					_ => 0,
+				};
 
-			Ok(())
-		})?;
+				// an unchanged feed keeps the hint of its last parsed content
+				let publisher_interval = match outcome.feed {
+					Some(_) => outcome.publisher_interval,
+					None => feed::table
+						.find(feed_id)
+						.select(feed::publisher_interval_secs)
+						.get_result::<Option<i32>>(conn)?
+						.and_then(|secs| u64::try_from(secs).ok())
+						.map(Duration::from_secs),
+				};
 
-		Ok(())
+				let entry_dates = feed_entry::table
+					.select(feed_entry::date)
+					.filter(feed_entry::feed_id.eq(feed_id))
+					.order_by(feed_entry::date.desc())
+					.limit(POSTING_FREQUENCY_SAMPLE)
+					.load::<OffsetDateTime>(conn)?;
+
+				let interval = scheduler::success_interval(
+					&self.scheduler_config,
+					&entry_dates,
+					publisher_interval,
+					outcome.max_age,
+				);
+
+				// hubs push new content, polling is kept to renew the lease
+				let lease_expires_at = websub_subscription::table
+					.select(websub_subscription::lease_expires_at)
+					.filter(websub_subscription::feed_id.eq(feed_id))
+					.filter(websub_subscription::status.eq("active"))
+					.get_result::<Option<OffsetDateTime>>(conn)
+					.optional()?
+					.flatten();
+				let interval = lease_expires_at.map_or(interval, |lease_expires_at| {
+					scheduler::push_interval(&self.scheduler_config, interval, lease_expires_at)
+				});
+
+				let next_fetch_at = scheduler::jittered(&self.scheduler_config, interval);
+
+				dsl::update(feed::table.find(feed_id))
+					.set((
+						feed::status.eq("ok"),
+						feed::last_error.eq(None::<String>),
+						&outcome.validators,
+						metadata.as_ref(),
+						feed::consecutive_failures.eq(0),
+						feed::last_success_at.eq(OffsetDateTime::now_utc()),
+						feed::next_fetch_at.eq(next_fetch_at),
+						feed::publisher_interval_secs.eq(publisher_interval
+							.and_then(|interval| i32::try_from(interval.as_secs()).ok())),
+					))
+					.execute(conn)
+					.wrap_err("unable to update feed status")?;
+
+				Ok((feed_id, upserted, next_fetch_at))
+			})?;
+
+		tracing::info!(feed_id = ?feed_id, url = %url, upserted, %next_fetch_at, "stored feed entries");
+
+		Ok(feed_id)
 	}
 
 	fn on_failed(
 		&self,
 		feed_id: FeedId,
 		url: &Url,
-		err: &std::result::Result<StatusCode, reqwest::Error>,
+		err: &Error,
+		log: &NewFeedFetchLog,
 	) -> Result<()> {
 		use crate::database::schema::*;
 
 		tracing::error!("unsucessfully fetched url {url} for feed_id {feed_id:?}: {err:?}");
 
+		let retry_after = match err {
+			Error::Status { retry_after, .. } => *retry_after,
+			_ => None,
+		};
+
 		let mut conn = self.db_pool.get()?;
-		dsl::update(feed::table)
-			.set(feed::status.eq("failed"))
-			.execute(&mut conn)
-			.wrap_err("unable to update feed status")?;
+		conn.transaction::<_, eyre::Report, _>(|conn| {
+			log.insert_into(feed_fetch_log::table)
+				.execute(conn)
+				.wrap_err("unable to journal fetch attempt")?;
+
+			let consecutive_failures = dsl::update(feed::table.find(feed_id))
+				.set((
+					feed::status.eq("failed"),
+					feed::last_error.eq(&log.error_message),
+					feed::consecutive_failures.eq(feed::consecutive_failures + 1),
+				))
+				.returning(feed::consecutive_failures)
+				.get_result::<i32>(conn)
+				.wrap_err("unable to update feed status")?;
 
-		// TODO: add a custom error message in function of why it failed
+			let interval = scheduler::failure_interval(
+				&self.scheduler_config,
+				u32::try_from(consecutive_failures).unwrap_or_default(),
+				retry_after,
+			);
+
+			dsl::update(feed::table.find(feed_id))
+				.set(feed::next_fetch_at.eq(scheduler::jittered(&self.scheduler_config, interval)))
+				.execute(conn)
+				.wrap_err("unable to schedule feed retry")?;
+
+			Ok(())
+		})?;
 
 		Ok(())
 	}
 }
 
+/// Journal entry describing a fetch attempt
+fn fetch_log(
+	feed_id: FeedId,
+	fetched_at: OffsetDateTime,
+	duration: Duration,
+	result: &Result<FetchOutcome>,
+) -> NewFeedFetchLog<'static> {
+	let redirects = match result {
+		Ok(outcome) if !outcome.redirects.is_empty() => {
+			serde_json::to_value(&outcome.redirects).ok()
+		}
+		_ => None,
+	};
+
+	let (http_status, bytes, error_kind, error_message) = match result {
+		Ok(outcome) => (
+			Some(outcome.http_status),
+			i32::try_from(outcome.bytes).ok(),
+			None,
+			None,
+		),
+		Err(err) => {
+			// keep the causes, top-level request errors are too vague to act upon
+			let mut message = err.to_string();
+			for cause in iter::successors(err.source(), |&err| err.source()) {
+				let cause = cause.to_string();
+				// wrapping errors often repeat the message of their source
+				if !message.contains(&cause) {
+					message = format!("{message}: {cause}");
+				}
+			}
+
+			(
+				err.status(),
+				None,
+				Some(err.kind().as_str()),
+				Some(Cow::Owned(message)),
+			)
+		}
+	};
+
+	NewFeedFetchLog {
+		feed_id,
+		fetched_at,
+		duration_ms: i32::try_from(duration.as_millis()).unwrap_or(i32::MAX),
+		http_status: http_status.map(|status| i32::from(status.as_u16())),
+		bytes,
+		error_kind,
+		error_message,
+		redirects,
+		pushed: false,
+	}
+}
+
+/// Whether two urls point to the same location, ignoring the scheme and a trailing slash
+fn same_location(url: &Url, other: &Url) -> bool {
+	dedup::canonical_link(url.as_str()) == dedup::canonical_link(other.as_str())
+}
+
+/// Location a feed advertises for itself with `<link rel="self">`
+fn self_link(feed: &model::Feed) -> Option<Url> {
+	let link = feed
+		.links
+		.iter()
+		.find(|link| link.rel.as_deref() == Some("self"))?;
+
+	Url::parse(&link.href)
+		.ok()
+		.filter(|url| matches!(url.scheme(), "http" | "https"))
+}
+
+/// Information advertised by a parsed feed
+fn feed_metadata(feed: &model::Feed) -> FeedMetadata<'_> {
+	fn text(text: Option<&model::Text>) -> Option<Cow<'_, str>> {
+		text.map(|text| text.content.trim())
+			.filter(|content| !content.is_empty())
+			.map(Cow::Borrowed)
+	}
+
+	// the feed links to itself with `rel="self"`, look for its website
+	let site_link = feed
+		.links
+		.iter()
+		.find(|link| link.rel.as_deref().is_none_or(|rel| rel == "alternate"))
+		.map(|link| Cow::Borrowed(link.href.as_str()));
+
+	let generator = feed.generator.as_ref().map(|generator| {
+		generator
+			.version
+			.as_ref()
+			.map_or(Cow::Borrowed(generator.content.as_str()), |version| {
+				Cow::Owned(format!("{} {version}", generator.content))
+			})
+	});
+
+	FeedMetadata {
+		title: text(feed.title.as_ref()),
+		description: text(feed.description.as_ref()),
+		site_link,
+		language: feed.language.as_deref().map(Cow::Borrowed),
+		generator,
+		icon_url: feed
+			.icon
+			.as_ref()
+			.map(|icon| Cow::Borrowed(icon.uri.as_str())),
+		logo_url: feed
+			.logo
+			.as_ref()
+			.map(|logo| Cow::Borrowed(logo.uri.as_str())),
+	}
+}
+
+/// Stores the entries of a parsed feed along with their enclosures
+fn store_entries(
+	pipeline: &Pipeline,
+	feed_id: FeedId,
+	url: &Url,
+	feed: &model::Feed,
+	conn: &mut PooledConnection,
+) -> eyre::Result<usize> {
+	let entries = feed
+		.entries
+		.iter()
+		.map(|entry| new_feed_entry(pipeline, feed_id, url, entry))
+		.collect::<Vec<_>>();
+
+	let edited = NewFeedEntryRevision::save_edited(feed_id, &entries, conn)
+		.wrap_err("unable to save entry revisions")?;
+	let stored =
+		NewFeedEntry::upsert_all(&entries, conn).wrap_err("unable to store feed entries")?;
+	if !edited.is_empty() {
+		tracing::debug!(feed_id = ?feed_id, count = edited.len(), "entries were edited");
+		FeedEntry::mark_edited(feed_id, &edited, conn).wrap_err("unable to flag edited entries")?;
+	}
+
+	let entry_ids = stored.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+	FeedEntry::cluster(&entry_ids, conn).wrap_err("unable to cluster duplicate entries")?;
+
+	let enclosures = feed
+		.entries
+		.iter()
+		.filter_map(|entry| {
+			let (entry_id, _) = stored.iter().find(|(_, guid)| *guid == entry.id)?;
+			Some(entry_enclosures(*entry_id, &feed.feed_type, entry))
+		})
+		.flatten()
+		.collect::<Vec<_>>();
+	NewFeedEntryEnclosure::replace_all(&entry_ids, &enclosures, conn)
+		.wrap_err("unable to store entry enclosures")?;
+
+	Ok(stored.len())
+}
+
+/// Maps a parsed entry to its database representation
+fn new_feed_entry<'a>(
+	pipeline: &Pipeline,
+	feed_id: FeedId,
+	feed_url: &Url,
+	entry: &'a model::Entry,
+) -> NewFeedEntry<'a> {
+	let link = entry
+		.links
+		.iter()
+		.find(|link| link.rel.as_deref().is_none_or(|rel| rel == "alternate"))
+		.or_else(|| entry.links.first())
+		.map(|link| Cow::Borrowed(link.href.as_str()));
+
+	let title = entry
+		.title
+		.as_ref()
+		.map(|title| Cow::Borrowed(title.content.as_str()))
+		.or_else(|| link.clone())
+		.unwrap_or(Cow::Borrowed("Untitled"));
+
+	let raw_content = entry
+		.content
+		.as_ref()
+		.and_then(|content| content.body.as_deref())
+		.or_else(|| {
+			entry
+				.summary
+				.as_ref()
+				.map(|summary| summary.content.as_str())
+		});
+
+	// hashed as published, so changes of the pipeline are not seen as edits
+	let mut hasher = Sha256::new();
+	hasher.update(title.as_bytes());
+	hasher.update([0]);
+	hasher.update(raw_content.unwrap_or_default().as_bytes());
+	let content_hash = Cow::Owned(format!("{:x}", hasher.finalize()));
+
+	let canonical_link = link
+		.as_deref()
+		.and_then(dedup::canonical_link)
+		.map(Cow::Owned);
+	let fingerprint = dedup::fingerprint(&title, raw_content);
+
+	let content = raw_content.map(|content| {
+		// relative urls of the content refer to the entry page
+		let base = link
+			.as_deref()
+			.and_then(|link| Url::parse(link).ok())
+			.unwrap_or_else(|| feed_url.clone());
+		Cow::Owned(pipeline.run(content, Context { base: &base }))
+	});
+
+	let author = (!entry.authors.is_empty()).then(|| {
+		Cow::Owned(
+			entry
+				.authors
+				.iter()
+				// rss `<author>` only holds an email, parsed as a person with a placeholder name
+				.map(|person| match (&person.email, person.name.as_str()) {
+					(Some(email), "author") => email.as_str(),
+					(_, name) => name,
+				})
+				.join(", "),
+		)
+	});
+
+	// entries without any date are considered published when first seen
+	let date = entry
+		.published
+		.or(entry.updated)
+		.and_then(|date| OffsetDateTime::from_unix_timestamp(date.timestamp()).ok())
+		.unwrap_or_else(OffsetDateTime::now_utc);
+
+	NewFeedEntry {
+		feed_id,
+		date,
+		title,
+		content,
+		guid: Cow::Borrowed(&entry.id),
+		link,
+		author,
+		content_hash,
+		canonical_link,
+		fingerprint,
+	}
+}
+
+/// Media attached to an entry
+///
+/// `feed_rs` maps rss enclosures and itunes tags to media rss objects, atom enclosures and json
+/// feed attachments are kept as links.
+fn entry_enclosures<'a>(
+	feed_entry_id: FeedEntryId,
+	feed_type: &model::FeedType,
+	entry: &'a model::Entry,
+) -> Vec<NewFeedEntryEnclosure<'a>> {
+	let media = entry.media.iter().flat_map(|object| {
+		// itunes episode artworks are parsed as thumbnails
+		let image = object
+			.thumbnails
+			.first()
+			.map(|thumbnail| Cow::Borrowed(thumbnail.image.uri.as_str()));
+
+		object.content.iter().filter_map(move |content| {
+			Some(NewFeedEntryEnclosure {
+				feed_entry_id,
+				url: Cow::Borrowed(content.url.as_ref()?.as_str()),
+				mime: content
+					.content_type
+					.as_ref()
+					.map(|mime| Cow::Owned(mime.to_string())),
+				length: content.size.and_then(|size| i64::try_from(size).ok()),
+				duration_secs: content
+					.duration
+					.or(object.duration)
+					.and_then(|duration| i32::try_from(duration.as_secs()).ok()),
+				image: image.clone(),
+			})
+		})
+	});
+
+	// json feed attachments are the only item links carrying a media type
+	let links = entry
+		.links
+		.iter()
+		.filter(|link| {
+			link.rel.as_deref().map_or_else(
+				|| *feed_type == model::FeedType::JSON && link.media_type.is_some(),
+				|rel| rel == "enclosure",
+			)
+		})
+		.map(|link| NewFeedEntryEnclosure {
+			feed_entry_id,
+			url: Cow::Borrowed(link.href.as_str()),
+			mime: link.media_type.as_deref().map(Cow::Borrowed),
+			length: link.length.and_then(|length| i64::try_from(length).ok()),
+			duration_secs: None,
+			image: None,
+		});
+
+	media.chain(links).collect()
+}
+
+/// Validators of the last successful fetch sent back to the server
+#[derive(Debug, Clone, Default, Queryable, Selectable, AsChangeset)]
+#[diesel(table_name = crate::database::schema::feed)]
+#[diesel(treat_none_as_null = true)]
+struct CacheValidators {
+	etag: Option<String>,
+	last_modified: Option<String>,
+	content_hash: Option<String>,
+}
+
+#[derive(Debug)]
+struct FetchOutcome {
+	http_status: StatusCode,
+	bytes: usize,
+
+	/// Parsed feed, `None` when the content did not change since the last fetch
+	feed: Option<Box<model::Feed>>,
+	/// Minimum refresh interval advertised in the feed content
+	publisher_interval: Option<Duration>,
+	/// Freshness lifetime advertised by the server
+	max_age: Option<Duration>,
+	validators: CacheValidators,
+
+	redirects: Vec<Redirect>,
+	/// Location the feed permanently moved to, per http redirects
+	moved_to: Option<Url>,
+	/// Location declared in the content, once confirmed to serve the same feed
+	declared_move: Option<Url>,
+}
+
 #[derive(Debug, Clone)]
 pub struct FetcherHandle {
-	queue: mpsc::Sender<FetchTask>,
+	fetcher: Arc<Fetcher>,
 }
 
 impl FetcherHandle {
-	pub async fn fetch_feed(&self, task: FetchTask) -> eyre::Result<()> {
-		// TODO: this should not be blocking because of channel size
-		self.queue
-			.send(task)
-			.await
-			.wrap_err("could not send fetch task to executor")
+	/// Lists the feeds behind an url, see [`discover::discover`]
+	pub async fn discover(&self, url: &Url) -> Result<Vec<Candidate>> {
+		discover::discover(&self.fetcher.client, &self.fetcher.adapters, url).await
+	}
+
+	/// Starts fetching jobs enqueued by this instance without waiting for the next poll
+	pub fn wake(&self) {
+		self.fetcher.notify.notify_one();
+	}
+
+	/// Answers a hub verifying a subscription, see [`Fetcher::verify_intent`]
+	pub fn verify_websub_intent(
+		&self,
+		subscription_id: WebSubSubscriptionId,
+		callback_token: &str,
+		intent: &Intent,
+	) -> Result<Option<String>> {
+		self.fetcher
+			.verify_intent(subscription_id, callback_token, intent)
+	}
+
+	/// Encrypts credentials to be stored with a feed
+	pub fn encrypt_credentials(&self, credentials: &Credentials) -> eyre::Result<Vec<u8>> {
+		self.fetcher.cipher.encrypt(credentials)
+	}
+
+	/// Credentials stored with a feed, `None` when they cannot be decrypted
+	pub fn decrypt_credentials(&self, encrypted: &[u8]) -> Option<Credentials> {
+		self.fetcher.cipher.decrypt(encrypted)
+	}
+
+	/// Runs scrape rules on a page without storing anything, see [`Fetcher::preview_scrape`]
+	pub async fn preview_scrape(&self, url: &Url, rules: &ScrapeRules) -> Result<Vec<ScrapedItem>> {
+		self.fetcher.preview_scrape(url, rules).await
+	}
+
+	/// Fetches an url without storing anything, see [`Fetcher::preview`]
+	pub async fn preview(&self, url: &Url, limit: usize) -> Result<Preview> {
+		self.fetcher.preview(url, limit).await
+	}
+
+	/// Queues a feed ahead of the others, see [`Fetcher::refresh`]
+	pub async fn refresh(
+		&self,
+		feed_id: FeedId,
+		wait: bool,
+	) -> eyre::Result<Option<FeedFetchLog<'static>>> {
+		self.fetcher.refresh(feed_id, wait).await
+	}
+
+	/// Stores a mail received for a user, see [`Fetcher::receive_letter`]
+	pub fn receive_letter(&self, user_id: UserId, letter: &Letter<'_>) -> Result<FeedId> {
+		self.fetcher.receive_letter(user_id, letter)
+	}
+
+	/// Downloads media for the proxy, see [`Fetcher::proxy`]
+	pub async fn proxy_media(&self, signed: &str) -> Result<Option<Media>> {
+		self.fetcher.proxy(signed).await
+	}
+
+	/// Stores content pushed by a hub, see [`Fetcher::receive`]
+	///
+	/// Returns `false` when the subscription is unknown.
+	pub fn receive_websub_content(
+		&self,
+		subscription_id: WebSubSubscriptionId,
+		callback_token: &str,
+		signature: Option<&str>,
+		body: &[u8],
+	) -> Result<bool> {
+		match self
+			.fetcher
+			.receive(subscription_id, callback_token, signature, body)?
+		{
+			Pushed::Unknown => Ok(false),
+			Pushed::Dropped => Ok(true),
+			Pushed::Stored(feed_id) => {
+				// hubs expect a quick answer, articles are downloaded afterwards
+				let fetcher = Arc::clone(&self.fetcher);
+				task::spawn(async move {
+					if let Err(err) = fetcher.fetch_full_texts(feed_id).await {
+						tracing::warn!(feed_id = ?feed_id, err = %err, "could not download articles");
+					}
+				});
+
+				Ok(true)
+			}
+		}
 	}
 }
 
-pub struct FetchTask {
-	pub feed_id: FeedId,
-	pub url: Url,
+#[derive(Debug)]
+struct FetchTask {
+	job_id: FetchJobId,
+	/// Generation of the job when claimed, see [`Fetcher::complete_job`]
+	generation: i32,
+	feed_id: FeedId,
+	url: Url,
 }
@@ -0,0 +1,147 @@
+use std::time::Instant;
+
+use diesel::{dsl, prelude::*, upsert::DecoratableTarget};
+use eyre::WrapErr;
+use feed_rs::parser;
+use reqwest::StatusCode;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+use url::Url;
+
+use crate::{
+	database::models::{FeedId, NewFeedFetchLog, NewUserFeed, UserId},
+	fetcher::{CacheValidators, FetchOutcome, Fetcher, Result, fetch_log},
+};
+
+/// Mail received for a user, stored as an entry of the feed gathering the mails of its sender
+#[derive(Debug)]
+pub struct Letter<'a> {
+	/// Address of the sender, in lowercase
+	pub sender: &'a str,
+	pub sender_name: Option<&'a str>,
+	pub subject: Option<&'a str>,
+	pub message_id: Option<&'a str>,
+	pub date: Option<OffsetDateTime>,
+	/// Html content, plain text mails are converted beforehand
+	pub content: &'a str,
+}
+
+impl Letter<'_> {
+	/// Json feed document holding the mail, parsed like any other feed
+	fn json_feed(&self) -> Vec<u8> {
+		// mails without identifier are told apart by their content
+		let id = self.message_id.map_or_else(
+			|| {
+				let mut hasher = Sha256::new();
+				hasher.update(self.subject.unwrap_or_default().as_bytes());
+				hasher.update([0]);
+				hasher.update(self.content.as_bytes());
+				format!("urn:sha256:{:x}", hasher.finalize())
+			},
+			|message_id| format!("mid:{message_id}"),
+		);
+
+		let feed = json!({
+			"version": "https://jsonfeed.org/version/1.1",
+			"title": self.sender_name.unwrap_or(self.sender),
+			"items": [{
+				"id": id,
+				"title": self.subject,
+				"content_html": self.content,
+				"date_published": self.date.and_then(|date| date.format(&Rfc3339).ok()),
+			}],
+		});
+
+		serde_json::to_vec(&feed).expect("json values always serialize")
+	}
+}
+
+impl Fetcher {
+	/// Stores a mail received for a user, returns the id of the feed of its sender
+	pub(super) fn receive_letter(&self, user_id: UserId, letter: &Letter<'_>) -> Result<FeedId> {
+		let url =
+			Url::parse(&format!("mailto:{}", letter.sender)).wrap_err("invalid sender address")?;
+		let feed_id = self.newsletter_feed(user_id, &url)?;
+
+		let body = letter.json_feed();
+
+		let fetched_at = OffsetDateTime::now_utc();
+		let timer = Instant::now();
+		let result = parser::Builder::new()
+			.sanitize_content(false)
+			.build()
+			.parse(&body[..])
+			.map_err(Into::into)
+			.map(|feed| FetchOutcome {
+				http_status: StatusCode::OK,
+				bytes: body.len(),
+				publisher_interval: None,
+				feed: Some(Box::new(feed)),
+				max_age: None,
+				validators: CacheValidators::default(),
+				redirects: Vec::new(),
+				moved_to: None,
+				declared_move: None,
+			});
+		let log = NewFeedFetchLog {
+			pushed: true,
+			..fetch_log(feed_id, fetched_at, timer.elapsed(), &result)
+		};
+
+		let outcome = result?;
+		self.on_fetched(feed_id, &url, &outcome, &log)
+	}
+
+	/// Feed of a sender for a user, created along with its subscription on the first mail
+	///
+	/// Mails keep being stored once the user unsubscribed, until the retention job purges the
+	/// feed.
+	fn newsletter_feed(&self, user_id: UserId, url: &Url) -> Result<FeedId> {
+		use crate::database::schema::*;
+
+		let mut conn = self.db_pool.get()?;
+		let feed_id = conn
+			.transaction::<_, diesel::result::Error, _>(|conn| {
+				// mails of the same sender may arrive at the same time, the first one creates
+				// the feed and its subscription
+				let created = dsl::insert_into(feed::table)
+					.values((
+						feed::url.eq(url.as_str()),
+						feed::status.eq("ok"),
+						feed::owner_id.eq(user_id),
+						feed::source.eq("newsletter"),
+					))
+					.on_conflict((feed::owner_id, feed::url))
+					.filter_target(feed::owner_id.is_not_null())
+					.do_nothing()
+					.returning(feed::id)
+					.get_result::<FeedId>(conn)
+					.optional()?;
+				let Some(feed_id) = created else {
+					return feed::table
+						.select(feed::id)
+						.filter(feed::owner_id.eq(user_id))
+						.filter(feed::url.eq(url.as_str()))
+						.get_result::<FeedId>(conn);
+				};
+
+				NewUserFeed {
+					user_id,
+					feed_id,
+					folder_id: None,
+					title: None,
+					description: None,
+					full_text: false,
+					unread_on_update: false,
+				}
+				.insert_into(user_feed::table)
+				.execute(conn)?;
+
+				Ok(feed_id)
+			})
+			.wrap_err("could not create newsletter feed")?;
+
+		Ok(feed_id)
+	}
+}
@@ -0,0 +1,221 @@
+use std::fmt;
+
+use chacha20poly1305::{KeyInit, XChaCha20Poly1305, XNonce, aead::Aead};
+use diesel::prelude::*;
+use eyre::WrapErr;
+use reqwest::{
+	RequestBuilder,
+	header::{self, HeaderName, HeaderValue},
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use url::Url;
+
+use crate::{
+	config::Config,
+	database::models::{FeedId, FeedRequestOptions},
+	fetcher::{Fetcher, Result},
+};
+
+/// Keys are bound to credentials, the same secret may be used for other things
+const KEY_CONTEXT: &[u8] = b"feedr-credentials:";
+/// Length of the random nonce prepended to encrypted credentials
+const NONCE_LENGTH: usize = 24;
+
+/// Headers handled by the client, or that must go through [`Credentials`] to be encrypted
+const RESERVED_HEADERS: &[HeaderName] = &[
+	header::HOST,
+	header::CONNECTION,
+	header::CONTENT_LENGTH,
+	header::TRANSFER_ENCODING,
+	header::IF_NONE_MATCH,
+	header::IF_MODIFIED_SINCE,
+	header::AUTHORIZATION,
+	header::COOKIE,
+	header::USER_AGENT,
+];
+
+/// Secrets sent along the requests of a feed, stored encrypted
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Credentials {
+	Basic {
+		username: String,
+		password: Option<String>,
+	},
+	Bearer {
+		token: String,
+	},
+	Cookie {
+		cookie: String,
+	},
+}
+
+impl Credentials {
+	pub const fn kind(&self) -> &'static str {
+		match self {
+			Self::Basic { .. } => "basic",
+			Self::Bearer { .. } => "bearer",
+			Self::Cookie { .. } => "cookie",
+		}
+	}
+
+	fn apply(&self, request: RequestBuilder) -> RequestBuilder {
+		match self {
+			Self::Basic { username, password } => request.basic_auth(username, password.as_ref()),
+			Self::Bearer { token } => request.bearer_auth(token),
+			Self::Cookie { cookie } => request.header(header::COOKIE, cookie),
+		}
+	}
+}
+
+// secrets must not end up in logs
+impl fmt::Debug for Credentials {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("Credentials")
+			.field("kind", &self.kind())
+			.finish_non_exhaustive()
+	}
+}
+
+/// Checks a custom header of a feed, returns the reason it is refused
+pub fn validate_header(
+	name: &str,
+	value: &str,
+) -> std::result::Result<(HeaderName, HeaderValue), &'static str> {
+	let name = HeaderName::try_from(name).map_err(|_| "invalid header name")?;
+	if RESERVED_HEADERS.contains(&name) {
+		return Err("header is reserved, use the user agent or credentials settings instead");
+	}
+	let value = HeaderValue::try_from(value).map_err(|_| "invalid header value")?;
+
+	Ok((name, value))
+}
+
+/// Encrypts feed credentials at rest
+pub struct Cipher(XChaCha20Poly1305);
+
+impl Cipher {
+	pub fn new(config: &Config) -> Self {
+		let secret = config
+			.fetcher
+			.credentials_key
+			.as_ref()
+			.unwrap_or(&config.server.session_secret);
+
+		let mut hasher = Sha256::new();
+		hasher.update(KEY_CONTEXT);
+		hasher.update(secret.as_bytes());
+
+		Self(XChaCha20Poly1305::new(&hasher.finalize()))
+	}
+
+	/// Serialized credentials, prefixed by their nonce
+	pub fn encrypt(&self, credentials: &Credentials) -> eyre::Result<Vec<u8>> {
+		let plaintext = serde_json::to_vec(credentials)?;
+
+		let nonce = rand::random::<[u8; NONCE_LENGTH]>();
+		let ciphertext = self
+			.0
+			.encrypt(XNonce::from_slice(&nonce), plaintext.as_slice())
+			.map_err(|_| eyre::eyre!("could not encrypt credentials"))?;
+
+		Ok([nonce.as_slice(), &ciphertext].concat())
+	}
+
+	/// Credentials encrypted by [`Self::encrypt`], `None` once the key changed
+	pub fn decrypt(&self, encrypted: &[u8]) -> Option<Credentials> {
+		if encrypted.len() < NONCE_LENGTH {
+			return None;
+		}
+		let (nonce, ciphertext) = encrypted.split_at(NONCE_LENGTH);
+
+		let plaintext = self.0.decrypt(XNonce::from_slice(nonce), ciphertext).ok()?;
+		serde_json::from_slice(&plaintext).ok()
+	}
+}
+
+impl fmt::Debug for Cipher {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("Cipher").finish_non_exhaustive()
+	}
+}
+
+/// Settings of the requests fetching a feed, see [`FeedRequestOptions`]
+#[derive(Debug, Default)]
+pub struct RequestOptions {
+	pub headers: Vec<(HeaderName, HeaderValue)>,
+	pub user_agent: Option<HeaderValue>,
+	pub credentials: Option<Credentials>,
+	pub accept_invalid_certs: bool,
+}
+
+impl RequestOptions {
+	/// Adds the settings to a request made to `url` while fetching the feed at `feed_url`
+	///
+	/// Custom headers and credentials are only sent to the origin of the feed, not to the hosts
+	/// it redirects to.
+	pub fn apply(&self, mut request: RequestBuilder, url: &Url, feed_url: &Url) -> RequestBuilder {
+		if let Some(user_agent) = &self.user_agent {
+			request = request.header(header::USER_AGENT, user_agent);
+		}
+
+		if url.origin() != feed_url.origin() {
+			return request;
+		}
+
+		for (name, value) in &self.headers {
+			request = request.header(name, value);
+		}
+		if let Some(credentials) = &self.credentials {
+			request = credentials.apply(request);
+		}
+
+		request
+	}
+}
+
+impl Fetcher {
+	/// Decrypts the request settings of a feed, settings that cannot be read are left out
+	pub(super) fn request_options(&self, feed_id: FeedId) -> Result<RequestOptions> {
+		use crate::database::schema::*;
+
+		let stored = {
+			let mut conn = self.db_pool.get()?;
+			feed::table
+				.find(feed_id)
+				.select(FeedRequestOptions::as_select())
+				.get_result::<FeedRequestOptions>(&mut conn)
+				.wrap_err("could not retrieve feed request options")?
+		};
+
+		let headers = stored
+			.request_headers
+			.as_ref()
+			.and_then(|headers| headers.as_object())
+			.into_iter()
+			.flatten()
+			.filter_map(|(name, value)| validate_header(name, value.as_str()?).ok())
+			.collect();
+
+		let user_agent = stored
+			.user_agent
+			.as_deref()
+			.and_then(|user_agent| HeaderValue::try_from(user_agent).ok());
+
+		let credentials = stored.credentials.as_deref().and_then(|encrypted| {
+			let credentials = self.cipher.decrypt(encrypted);
+			if credentials.is_none() {
+				tracing::warn!(feed_id = ?feed_id, "could not decrypt feed credentials, was the key changed?");
+			}
+			credentials
+		});
+
+		Ok(RequestOptions {
+			headers,
+			user_agent,
+			credentials,
+			accept_invalid_certs: stored.accept_invalid_certs,
+		})
+	}
+}
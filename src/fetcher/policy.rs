@@ -0,0 +1,220 @@
+use std::{
+	net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+	sync::Arc,
+};
+
+use eyre::WrapErr;
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use url::{Host, Url};
+
+use crate::config::FetcherConfig;
+
+/// Reason a request was refused by the [`OutboundPolicy`]
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+pub struct Blocked(pub &'static str);
+
+/// Rules applied to every outgoing request, urls are user provided and must not reach into the
+/// network of the instance
+#[derive(Debug)]
+pub struct OutboundPolicy {
+	/// Networks reachable even though they are not public
+	allowed_networks: Vec<IpNet>,
+
+	pub max_body_size: usize,
+	pub max_redirects: usize,
+}
+
+impl OutboundPolicy {
+	pub fn new(config: &FetcherConfig) -> eyre::Result<Self> {
+		let allowed_networks = config
+			.allowed_networks
+			.iter()
+			.map(|network| {
+				network
+					.parse::<IpNet>()
+					.wrap_err_with(|| format!("invalid allowed network `{network}`"))
+			})
+			.collect::<eyre::Result<_>>()?;
+
+		Ok(Self {
+			allowed_networks,
+			max_body_size: config.max_body_size,
+			max_redirects: config.max_redirects,
+		})
+	}
+
+	/// Checks an url before sending a request, domains are checked once resolved
+	pub fn check_url(&self, url: &Url) -> Result<(), Blocked> {
+		if !matches!(url.scheme(), "http" | "https") {
+			return Err(Blocked("only http and https urls can be fetched"));
+		}
+
+		let ip = match url.host() {
+			Some(Host::Domain(_)) => return Ok(()),
+			Some(Host::Ipv4(ip)) => IpAddr::V4(ip),
+			Some(Host::Ipv6(ip)) => IpAddr::V6(ip),
+			None => return Err(Blocked("url has no host")),
+		};
+
+		if self.is_allowed(ip) {
+			Ok(())
+		} else {
+			Err(Blocked("url points to a non-public address"))
+		}
+	}
+
+	pub fn is_allowed(&self, ip: IpAddr) -> bool {
+		is_public(ip)
+			|| self
+				.allowed_networks
+				.iter()
+				.any(|network| network.contains(&ip))
+	}
+}
+
+/// Whether the address is routable on the internet
+///
+/// Ipv6 addresses embedding an ipv4 address are judged by the embedded address.
+fn is_public(ip: IpAddr) -> bool {
+	// `Ipv4Addr::is_shared`, `is_benchmarking` and `is_reserved` are unstable
+	const SHARED: Ipv4Net = Ipv4Net::new_assert(Ipv4Addr::new(100, 64, 0, 0), 10);
+	const BENCHMARKING: Ipv4Net = Ipv4Net::new_assert(Ipv4Addr::new(198, 18, 0, 0), 15);
+	const RESERVED: Ipv4Net = Ipv4Net::new_assert(Ipv4Addr::new(240, 0, 0, 0), 4);
+
+	// deprecated ipv4-compatible addresses, e.g. `::127.0.0.1`, are routed by some stacks to the
+	// embedded address, they are refused rather than unwrapped
+	const IPV4_COMPATIBLE: Ipv6Net = Ipv6Net::new_assert(Ipv6Addr::UNSPECIFIED, 96);
+	const NAT64: Ipv6Net = Ipv6Net::new_assert(Ipv6Addr::new(0x64, 0xff9b, 0, 0, 0, 0, 0, 0), 96);
+	// the embedded address depends on the prefix length chosen by the network, it is not
+	// routable on the internet anyway
+	const LOCAL_NAT64: Ipv6Net =
+		Ipv6Net::new_assert(Ipv6Addr::new(0x64, 0xff9b, 1, 0, 0, 0, 0, 0), 48);
+	const SIX_TO_FOUR: Ipv6Net =
+		Ipv6Net::new_assert(Ipv6Addr::new(0x2002, 0, 0, 0, 0, 0, 0, 0), 16);
+	// the client address of teredo is obfuscated, tunnels are refused altogether
+	const TEREDO: Ipv6Net = Ipv6Net::new_assert(Ipv6Addr::new(0x2001, 0, 0, 0, 0, 0, 0, 0), 32);
+	const DOCUMENTATION: Ipv6Net =
+		Ipv6Net::new_assert(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0), 32);
+
+	let ip = match ip {
+		IpAddr::V4(ip) => {
+			return !(ip.is_unspecified()
+				|| ip.is_loopback()
+				|| ip.is_private()
+				|| ip.is_link_local()
+				|| ip.is_broadcast()
+				|| ip.is_multicast()
+				|| ip.is_documentation()
+				|| ip.octets()[0] == 0
+				|| SHARED.contains(&ip)
+				|| BENCHMARKING.contains(&ip)
+				|| RESERVED.contains(&ip));
+		}
+		IpAddr::V6(ip) => ip,
+	};
+
+	let octets = ip.octets();
+	let ipv4_at = |start: usize| {
+		let [a, b, c, d] = octets[start..start + 4]
+			.try_into()
+			.expect("ipv6 addresses hold 16 octets");
+		Ipv4Addr::new(a, b, c, d)
+	};
+	let embedded = ip
+		.to_ipv4_mapped()
+		.or_else(|| NAT64.contains(&ip).then(|| ipv4_at(12)))
+		.or_else(|| SIX_TO_FOUR.contains(&ip).then(|| ipv4_at(2)));
+	if let Some(ip) = embedded {
+		return is_public(IpAddr::V4(ip));
+	}
+
+	!(ip.is_unspecified()
+		|| ip.is_loopback()
+		|| ip.is_multicast()
+		|| ip.is_unique_local()
+		|| ip.is_unicast_link_local()
+		|| IPV4_COMPATIBLE.contains(&ip)
+		|| LOCAL_NAT64.contains(&ip)
+		|| TEREDO.contains(&ip)
+		|| DOCUMENTATION.contains(&ip))
+}
+
+/// Resolves hosts with the system resolver, dropping the addresses refused by the policy
+///
+/// Checking resolved addresses rather than urls ensures a host cannot be rebound to an internal
+/// address between the check and the connection.
+#[derive(Debug)]
+pub struct PolicyResolver(pub Arc<OutboundPolicy>);
+
+impl Resolve for PolicyResolver {
+	fn resolve(&self, name: Name) -> Resolving {
+		let policy = Arc::clone(&self.0);
+		Box::pin(async move {
+			let addrs = tokio::net::lookup_host((name.as_str(), 0))
+				.await?
+				.filter(|addr| policy.is_allowed(addr.ip()))
+				.collect::<Vec<SocketAddr>>();
+
+			if addrs.is_empty() {
+				return Err(Blocked("host resolves to a non-public address").into());
+			}
+
+			Ok(Box::new(addrs.into_iter()) as Addrs)
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn is_public_str(ip: &str) -> bool {
+		is_public(ip.parse().expect("fixture address is valid"))
+	}
+
+	#[test]
+	fn accepts_public_addresses() {
+		for ip in [
+			"93.184.215.14",
+			"2606:2800:21f:cb07:6820:80da:af6b:8b2c",
+			"::ffff:93.184.215.14",
+			"64:ff9b::93.184.215.14",
+			"2002:5db8:d70e::1",
+		] {
+			assert!(is_public_str(ip), "{ip} is public");
+		}
+	}
+
+	#[test]
+	fn refuses_internal_addresses() {
+		for ip in [
+			"127.0.0.1",
+			"10.1.2.3",
+			"169.254.169.254",
+			"100.64.0.1",
+			"198.18.0.1",
+			"198.19.255.255",
+			"240.0.0.1",
+			"255.255.255.255",
+			"0.1.2.3",
+			"::1",
+			"fd00::1",
+			"fe80::1",
+			"::ffff:127.0.0.1",
+			"::127.0.0.1",
+			"::a9fe:a9fe",
+			"::93.184.215.14",
+			"64:ff9b::127.0.0.1",
+			"64:ff9b::a9fe:a9fe",
+			"64:ff9b:1::5db8:d70e",
+			"2002:7f00:1::1",
+			"2002:a00:1::1",
+			"2001::5db8:d70e",
+			"2001:db8::1",
+		] {
+			assert!(!is_public_str(ip), "{ip} is not public");
+		}
+	}
+}
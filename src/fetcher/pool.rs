@@ -0,0 +1,88 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use parking_lot::Mutex;
+use tokio::{
+	sync::{OwnedSemaphorePermit, Semaphore},
+	time,
+};
+
+/// Limits the number of concurrent requests made to the same host
+#[derive(Debug)]
+pub struct HostLimiter {
+	per_host: usize,
+	delay: Duration,
+	hosts: Mutex<HashMap<String, Arc<Semaphore>>>,
+}
+
+/// Slot taken on a host, see [`HostLimiter::try_acquire`]
+#[derive(Debug)]
+pub struct HostSlot {
+	host: String,
+	permit: OwnedSemaphorePermit,
+}
+
+impl HostLimiter {
+	pub fn new(per_host: usize, delay: Duration) -> Self {
+		Self {
+			per_host,
+			delay,
+			hosts: Mutex::default(),
+		}
+	}
+
+	/// Takes a slot on the host, `None` when all of them are in use
+	pub fn try_acquire(&self, host: &str) -> Option<HostSlot> {
+		let semaphore = Arc::clone(
+			self.hosts
+				.lock()
+				.entry(host.to_owned())
+				.or_insert_with(|| Arc::new(Semaphore::new(self.per_host))),
+		);
+
+		let permit = semaphore.try_acquire_owned().ok()?;
+		Some(HostSlot {
+			host: host.to_owned(),
+			permit,
+		})
+	}
+
+	/// Frees the slot once the politeness delay has passed
+	///
+	/// Hosts are forgotten once no slot is taken, so that the map does not grow with every host
+	/// ever fetched.
+	pub async fn release(&self, slot: HostSlot) {
+		time::sleep(self.delay).await;
+		drop(slot.permit);
+
+		let mut hosts = self.hosts.lock();
+		// slots and pending acquisitions hold the semaphore as well
+		if hosts
+			.get(&slot.host)
+			.is_some_and(|semaphore| Arc::strong_count(semaphore) == 1)
+		{
+			hosts.remove(&slot.host);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	async fn hold(limiter: &HostLimiter, host: &str) -> bool {
+		let Some(slot) = limiter.try_acquire(host) else {
+			return false;
+		};
+		let busy = limiter.try_acquire(host).is_none() && limiter.hosts.lock().contains_key(host);
+		limiter.release(slot).await;
+		busy
+	}
+
+	#[tokio::test]
+	async fn limits_and_forgets_hosts() {
+		let limiter = HostLimiter::new(1, Duration::ZERO);
+
+		assert!(hold(&limiter, "a.example").await);
+		assert!(limiter.hosts.lock().is_empty());
+	}
+}
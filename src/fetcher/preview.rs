@@ -0,0 +1,144 @@
+use std::{borrow::Cow, collections::HashSet};
+
+use serde::Serialize;
+use time::OffsetDateTime;
+use url::Url;
+
+use crate::{
+	database::models::{FeedId, FeedMetadata},
+	fetcher::{
+		CacheValidators, Error, Fetcher, Result, feed_metadata, new_feed_entry,
+		options::RequestOptions,
+	},
+};
+
+/// What subscribing to an url would store, see [`Fetcher::preview`]
+#[derive(Debug, Clone, Serialize)]
+pub struct Preview {
+	/// Where the feed was found once redirects are followed
+	pub url: Url,
+	pub metadata: FeedMetadata<'static>,
+	/// Number of entries of the document, only the first ones are listed
+	pub entry_count: usize,
+	pub entries: Vec<PreviewEntry>,
+	/// Issues the feed would have once subscribed to
+	pub warnings: Vec<String>,
+}
+
+/// Entry as it would be stored, with its content rewritten
+#[derive(Debug, Clone, Serialize)]
+pub struct PreviewEntry {
+	pub guid: String,
+	pub title: String,
+	pub link: Option<String>,
+	pub author: Option<String>,
+	#[serde(with = "time::serde::rfc3339")]
+	pub date: OffsetDateTime,
+	pub content: Option<String>,
+}
+
+impl Fetcher {
+	/// Fetches and parses an url like a feed refresh would, nothing is stored
+	pub(super) async fn preview(&self, url: &Url, limit: usize) -> Result<Preview> {
+		let outcome = self
+			.fetch(
+				url,
+				&CacheValidators::default(),
+				&RequestOptions::default(),
+				None,
+			)
+			.await?;
+		// without validators, the content is always parsed
+		let feed = outcome
+			.feed
+			.ok_or(Error::Body("server answered with an empty response"))?;
+
+		let final_url = outcome
+			.redirects
+			.last()
+			.map_or_else(|| url.clone(), |redirect| redirect.url.clone());
+
+		let mut warnings = Vec::new();
+		if let Some(moved_to) = outcome.moved_to.as_ref().or(outcome.declared_move.as_ref()) {
+			warnings.push(format!(
+				"the feed moved to {moved_to}, subscriptions will follow it"
+			));
+		}
+		if final_url.scheme() == "http" {
+			warnings.push("the feed is not served over https".to_owned());
+		}
+		if feed.title.is_none() {
+			warnings.push("the feed has no title".to_owned());
+		}
+		if feed.entries.is_empty() {
+			warnings.push("the feed has no entries".to_owned());
+		}
+
+		let undated = feed
+			.entries
+			.iter()
+			.filter(|entry| entry.published.is_none() && entry.updated.is_none())
+			.count();
+		if undated > 0 {
+			warnings.push(format!(
+				"{undated} entries have no date, they are dated when first seen"
+			));
+		}
+		let unlinked = feed
+			.entries
+			.iter()
+			.filter(|entry| entry.links.is_empty())
+			.count();
+		if unlinked > 0 {
+			warnings.push(format!("{unlinked} entries have no link"));
+		}
+		let mut guids = HashSet::new();
+		let repeated = feed
+			.entries
+			.iter()
+			.filter(|entry| !guids.insert(entry.id.as_str()))
+			.count();
+		if repeated > 0 {
+			warnings.push(format!(
+				"{repeated} entries repeat the guid of another one, only the first is kept"
+			));
+		}
+
+		let owned = |text: Option<Cow<'_, str>>| text.map(|text| Cow::Owned(text.into_owned()));
+		let metadata = feed_metadata(&feed);
+		let metadata = FeedMetadata {
+			title: owned(metadata.title),
+			description: owned(metadata.description),
+			site_link: owned(metadata.site_link),
+			language: owned(metadata.language),
+			generator: owned(metadata.generator),
+			icon_url: owned(metadata.icon_url),
+			logo_url: owned(metadata.logo_url),
+		};
+
+		let entries = feed
+			.entries
+			.iter()
+			.take(limit)
+			.map(|entry| {
+				let entry = new_feed_entry(&self.pipeline, FeedId::UNSAVED, &final_url, entry);
+				PreviewEntry {
+					guid: entry.guid.into_owned(),
+					title: entry.title.into_owned(),
+					link: entry.link.map(Cow::into_owned),
+					author: entry.author.map(Cow::into_owned),
+					date: entry.date,
+					content: entry.content.map(Cow::into_owned),
+				}
+			})
+			.collect();
+
+		Ok(Preview {
+			url: final_url,
+			metadata,
+			entry_count: feed.entries.len(),
+			entries,
+			warnings,
+		})
+	}
+}
@@ -0,0 +1,250 @@
+use std::{
+	path::PathBuf,
+	time::{Duration, SystemTime},
+};
+
+use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
+use bytes::Bytes;
+use eyre::WrapErr;
+use hmac::{Hmac, Mac};
+use reqwest::header;
+use sha2::{Digest, Sha256};
+use tokio::fs;
+use url::Url;
+
+use crate::{
+	config::{Config, ProxyConfig},
+	fetcher::{Error, Fetcher, Result},
+};
+
+/// Signed urls are bound to the proxy, the same key may sign other things
+const SIGNATURE_CONTEXT: &[u8] = b"feedr-proxy:";
+
+/// Extension of the files holding the mime type of cached media
+const MIME_EXTENSION: &str = "mime";
+
+/// Media downloaded through the proxy
+#[derive(Debug, Clone)]
+pub struct Media {
+	pub mime: String,
+	pub body: Bytes,
+	/// Time before the media is downloaded again
+	pub max_age: Duration,
+}
+
+/// Signs remote urls so the proxy only downloads media that were linked by entries
+#[derive(Debug, Clone)]
+pub struct Signer {
+	key: Vec<u8>,
+	/// Route of the proxy, signed urls are appended to it
+	base_url: Url,
+}
+
+impl Signer {
+	pub fn new(config: &Config) -> eyre::Result<Self> {
+		let key = config
+			.proxy
+			.key
+			.as_ref()
+			.unwrap_or(&config.server.session_secret);
+
+		let base_url = config.web.base_url.trim_end_matches('/');
+		let base_url =
+			Url::parse(&format!("{base_url}/proxy/")).wrap_err("invalid web base url")?;
+
+		Ok(Self {
+			key: key.as_bytes().to_vec(),
+			base_url,
+		})
+	}
+
+	fn mac(&self) -> Hmac<Sha256> {
+		let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("hmac accepts any key");
+		mac.update(SIGNATURE_CONTEXT);
+		mac
+	}
+
+	/// Url of the proxy serving `url`, formatted as `{base}/proxy/{signature}.{url}`
+	pub fn sign(&self, url: &Url) -> Url {
+		let mut mac = self.mac();
+		mac.update(url.as_str().as_bytes());
+		let signed = format!(
+			"{}.{}",
+			BASE64_URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes()),
+			BASE64_URL_SAFE_NO_PAD.encode(url.as_str()),
+		);
+
+		self.base_url
+			.join(&signed)
+			.expect("signed urls only contain url-safe characters")
+	}
+
+	/// Whether an url already goes through the proxy
+	pub fn is_signed(&self, url: &Url) -> bool {
+		url.as_str().starts_with(self.base_url.as_str())
+	}
+
+	/// Remote url of a signed path segment, `None` when the signature does not match
+	pub fn verify(&self, signed: &str) -> Option<Url> {
+		let (signature, url) = signed.split_once('.')?;
+		let signature = BASE64_URL_SAFE_NO_PAD.decode(signature).ok()?;
+		let url = BASE64_URL_SAFE_NO_PAD.decode(url).ok()?;
+
+		let mut mac = self.mac();
+		mac.update(&url);
+		mac.verify_slice(&signature).ok()?;
+
+		Url::parse(std::str::from_utf8(&url).ok()?).ok()
+	}
+}
+
+/// Media cached on disk, by hash of their remote url
+///
+/// Entries are evicted once stale or when the cache grows over its maximum size, oldest first.
+#[derive(Debug)]
+pub struct MediaCache {
+	dir: PathBuf,
+	max_size: u64,
+	max_age: Duration,
+}
+
+impl MediaCache {
+	pub fn new(config: &ProxyConfig) -> Self {
+		Self {
+			dir: config.cache_dir.clone(),
+			max_size: config.max_cache_size,
+			max_age: Duration::from_secs(config.max_age),
+		}
+	}
+
+	fn path(&self, url: &Url) -> PathBuf {
+		self.dir
+			.join(format!("{:x}", Sha256::digest(url.as_str().as_bytes())))
+	}
+
+	/// Cached media, `None` when missing or stale
+	async fn get(&self, url: &Url) -> eyre::Result<Option<Media>> {
+		let path = self.path(url);
+
+		let Ok(metadata) = fs::metadata(&path).await else {
+			return Ok(None);
+		};
+		let age = metadata.modified()?.elapsed().unwrap_or(Duration::ZERO);
+		if age >= self.max_age {
+			return Ok(None);
+		}
+
+		let (Ok(body), Ok(mime)) = (
+			fs::read(&path).await,
+			fs::read_to_string(path.with_extension(MIME_EXTENSION)).await,
+		) else {
+			return Ok(None);
+		};
+
+		Ok(Some(Media {
+			mime,
+			body: Bytes::from(body),
+			max_age: self.max_age.saturating_sub(age),
+		}))
+	}
+
+	async fn put(&self, url: &Url, media: &Media) -> eyre::Result<()> {
+		fs::create_dir_all(&self.dir)
+			.await
+			.wrap_err("could not create cache directory")?;
+
+		let path = self.path(url);
+		fs::write(path.with_extension(MIME_EXTENSION), &media.mime).await?;
+		fs::write(&path, &media.body).await?;
+
+		self.evict().await
+	}
+
+	/// Removes the oldest media until the cache fits in its maximum size
+	async fn evict(&self) -> eyre::Result<()> {
+		let mut files = Vec::new();
+		let mut size = 0;
+
+		let mut entries = fs::read_dir(&self.dir).await?;
+		while let Some(entry) = entries.next_entry().await? {
+			let metadata = entry.metadata().await?;
+			if !metadata.is_file() {
+				continue;
+			}
+			size += metadata.len();
+
+			let path = entry.path();
+			if path.extension().is_none() {
+				let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+				files.push((modified, path, metadata.len()));
+			}
+		}
+
+		files.sort_unstable_by_key(|(modified, _, _)| *modified);
+		for (_, path, length) in files {
+			if size <= self.max_size {
+				break;
+			}
+
+			fs::remove_file(&path).await?;
+			let _ = fs::remove_file(path.with_extension(MIME_EXTENSION)).await;
+			size = size.saturating_sub(length);
+		}
+
+		Ok(())
+	}
+}
+
+impl Fetcher {
+	/// Serves the media behind a signed url, from the cache when fresh
+	///
+	/// Returns `None` when the signature does not match or the url is not a media.
+	pub(super) async fn proxy(&self, signed: &str) -> Result<Option<Media>> {
+		let Some(url) = self.signer.verify(signed) else {
+			return Ok(None);
+		};
+
+		match self.media_cache.get(&url).await {
+			Ok(Some(media)) => return Ok(Some(media)),
+			Ok(None) => {}
+			Err(err) => tracing::warn!(url = %url, err = %err, "could not read cached media"),
+		}
+
+		let (response, _) = self.client.get(&url).await?;
+
+		let status = response.status();
+		if !status.is_success() {
+			return Err(Error::Status {
+				status,
+				retry_after: None,
+			});
+		}
+
+		// only media can be proxied, the route must not serve arbitrary pages from our origin
+		let Some(mime) = response
+			.headers()
+			.get(header::CONTENT_TYPE)
+			.and_then(|value| value.to_str().ok())
+			.map(|mime| mime.split(';').next().unwrap_or_default().trim().to_owned())
+			.filter(|mime| {
+				["image/", "video/", "audio/"]
+					.iter()
+					.any(|kind| mime.starts_with(kind))
+			})
+		else {
+			return Ok(None);
+		};
+
+		let media = Media {
+			mime,
+			body: self.client.body(response).await?,
+			max_age: self.media_cache.max_age,
+		};
+
+		if let Err(err) = self.media_cache.put(&url, &media).await {
+			tracing::warn!(url = %url, err = %err, "could not cache media");
+		}
+
+		Ok(Some(media))
+	}
+}
@@ -0,0 +1,26 @@
+use serde::Serialize;
+use url::Url;
+
+/// A redirect followed while fetching an url, stored in `feed_fetch_log`
+#[derive(Debug, Clone, Serialize)]
+pub struct Redirect {
+	pub status: u16,
+	/// Location the server redirected to
+	pub url: Url,
+}
+
+impl Redirect {
+	const fn is_permanent(&self) -> bool {
+		matches!(self.status, 301 | 308)
+	}
+}
+
+/// Location the resource permanently moved to, temporary redirects break the chain as the
+/// original url must keep being used
+pub fn permanent_target(redirects: &[Redirect]) -> Option<&Url> {
+	redirects
+		.iter()
+		.take_while(|redirect| redirect.is_permanent())
+		.last()
+		.map(|redirect| &redirect.url)
+}
@@ -0,0 +1,516 @@
+use std::{borrow::Cow, fmt};
+
+use eyre::WrapErr;
+use lol_html::{
+	ElementContentHandlers, RewriteStrSettings, Selector, element, html_content::Element,
+};
+use url::Url;
+
+use crate::{
+	config::{ContentConfig, RewriteStep, SanitizerConfig},
+	fetcher::proxy::Signer,
+};
+
+/// Query parameters only used to track readers, along with every `utm_*` parameter
+const TRACKING_PARAMETERS: &[&str] = &[
+	"fbclid", "gclid", "igshid", "mc_cid", "mc_eid", "yclid", "_hsenc", "_hsmi",
+];
+/// Hosts serving tracking pixels
+const TRACKING_HOSTS: &[&str] = &[
+	"feeds.feedburner.com",
+	"pixel.quantserve.com",
+	"pixel.wp.com",
+	"stats.wordpress.com",
+	"www.google-analytics.com",
+];
+/// Hosts of the youtube player, embeds are moved to its privacy-enhanced domain
+const YOUTUBE_HOSTS: &[&str] = &["youtube.com", "www.youtube.com", "m.youtube.com"];
+
+/// Media tags kept on top of the `ammonia` defaults
+const MEDIA_TAGS: &[&str] = &["audio", "iframe", "picture", "source", "video"];
+const MEDIA_TAG_ATTRIBUTES: &[(&str, &[&str])] = &[
+	("img", &["decoding", "loading", "sizes", "srcset", "title"]),
+	(
+		"iframe",
+		&[
+			"allowfullscreen",
+			"height",
+			"loading",
+			"src",
+			"title",
+			"width",
+		],
+	),
+	("video", &["controls", "height", "poster", "src", "width"]),
+	("audio", &["controls", "src"]),
+	("source", &["media", "sizes", "src", "srcset", "type"]),
+];
+
+/// Information about the content being rewritten
+#[derive(Debug, Clone, Copy)]
+pub struct Context<'a> {
+	/// Url relative links are resolved against, the entry link or the feed url
+	pub base: &'a Url,
+}
+
+/// A step of the content [`Pipeline`]
+///
+/// Steps receive the output of the previous one, the sanitizer always runs last so steps do not
+/// need to care about unsafe markup.
+pub trait Rewrite: fmt::Debug + Send + Sync {
+	fn rewrite(&self, html: &str, context: Context<'_>) -> eyre::Result<String>;
+}
+
+/// Rewrites entry contents before they are stored
+#[derive(Debug)]
+pub struct Pipeline {
+	steps: Vec<Box<dyn Rewrite>>,
+	sanitizer: Sanitizer,
+}
+
+impl Pipeline {
+	pub fn new(steps: Vec<Box<dyn Rewrite>>, sanitizer: Sanitizer) -> Self {
+		Self { steps, sanitizer }
+	}
+
+	/// Steps of the config, followed by the media proxy when a signer is given
+	pub fn from_config(config: &ContentConfig, proxy: Option<Signer>) -> Self {
+		let mut steps = config
+			.rewrites
+			.iter()
+			.map(|step| -> Box<dyn Rewrite> {
+				match step {
+					RewriteStep::ResolveUrls => Box::new(ResolveUrls),
+					RewriteStep::StripTracking => Box::new(StripTracking),
+					RewriteStep::LazyImages => Box::new(LazyImages),
+					RewriteStep::PrivacyEmbeds => Box::new(PrivacyEmbeds),
+				}
+			})
+			.collect::<Vec<_>>();
+
+		// proxied urls are opaque, other steps have to see the original ones
+		if let Some(signer) = proxy {
+			steps.push(Box::new(ProxyMedia { signer }));
+		}
+
+		Self::new(steps, Sanitizer::new(config.sanitizer.clone()))
+	}
+
+	/// Runs every step then sanitizes the result, failing steps are skipped
+	pub fn run(&self, html: &str, context: Context<'_>) -> String {
+		let mut html = Cow::Borrowed(html);
+		for step in &self.steps {
+			match step.rewrite(&html, context) {
+				Ok(rewritten) => html = Cow::Owned(rewritten),
+				Err(err) => tracing::warn!(step = ?step, err = %err, "could not rewrite content"),
+			}
+		}
+
+		self.sanitizer.clean(&html)
+	}
+}
+
+/// Resolves relative urls against the base of the content
+#[derive(Debug)]
+pub struct ResolveUrls;
+
+impl Rewrite for ResolveUrls {
+	fn rewrite(&self, html: &str, context: Context<'_>) -> eyre::Result<String> {
+		// in-page anchors stay relative
+		let resolve = |value: &str| {
+			let value = value.trim();
+			(!value.starts_with('#'))
+				.then(|| context.base.join(value).ok())
+				.flatten()
+				.map(String::from)
+		};
+		let resolve_attribute = |element: &mut Element, attribute: &str| {
+			if let Some(url) = element
+				.get_attribute(attribute)
+				.and_then(|value| resolve(&value))
+			{
+				element.set_attribute(attribute, &url)?;
+			}
+			Ok(())
+		};
+
+		rewrite_elements(
+			html,
+			vec![
+				element!("a[href], area[href]", |element| {
+					resolve_attribute(element, "href")
+				}),
+				element!("[src]", |element| resolve_attribute(element, "src")),
+				element!("video[poster]", |element| {
+					resolve_attribute(element, "poster")
+				}),
+				element!("[srcset]", |element| {
+					if let Some(srcset) = element.get_attribute("srcset") {
+						element.set_attribute("srcset", &map_srcset(&srcset, resolve))?;
+					}
+					Ok(())
+				}),
+			],
+		)
+	}
+}
+
+/// Removes tracking pixels and tracking parameters of links
+#[derive(Debug)]
+pub struct StripTracking;
+
+impl Rewrite for StripTracking {
+	fn rewrite(&self, html: &str, _context: Context<'_>) -> eyre::Result<String> {
+		rewrite_elements(
+			html,
+			vec![
+				element!("img", |element| {
+					let is_pixel = ["width", "height"].iter().all(|dimension| {
+						element
+							.get_attribute(dimension)
+							.and_then(|value| value.trim().parse::<u32>().ok())
+							.is_some_and(|value| value <= 1)
+					});
+					let is_tracker = element
+						.get_attribute("src")
+						.and_then(|src| Url::parse(&src).ok())
+						.is_some_and(|src| {
+							src.host_str()
+								.is_some_and(|host| TRACKING_HOSTS.contains(&host))
+						});
+
+					if is_pixel || is_tracker {
+						element.remove();
+					}
+					Ok(())
+				}),
+				element!("a[href]", |element| {
+					if let Some(href) = element
+						.get_attribute("href")
+						.and_then(|href| without_tracking(&href))
+					{
+						element.set_attribute("href", &href)?;
+					}
+					Ok(())
+				}),
+			],
+		)
+	}
+}
+
+/// Defers the loading of images and embeds until they are scrolled to
+#[derive(Debug)]
+pub struct LazyImages;
+
+impl Rewrite for LazyImages {
+	fn rewrite(&self, html: &str, _context: Context<'_>) -> eyre::Result<String> {
+		rewrite_elements(
+			html,
+			vec![
+				element!("img:not([loading]), iframe:not([loading])", |element| {
+					element.set_attribute("loading", "lazy")?;
+					Ok(())
+				}),
+				element!("img:not([decoding])", |element| {
+					element.set_attribute("decoding", "async")?;
+					Ok(())
+				}),
+			],
+		)
+	}
+}
+
+/// Moves video embeds to the privacy-enhanced domains of their provider
+#[derive(Debug)]
+pub struct PrivacyEmbeds;
+
+impl Rewrite for PrivacyEmbeds {
+	fn rewrite(&self, html: &str, _context: Context<'_>) -> eyre::Result<String> {
+		rewrite_elements(
+			html,
+			vec![element!("iframe[src]", |element| {
+				let Some(mut src) = element
+					.get_attribute("src")
+					.and_then(|src| Url::parse(&src).ok())
+				else {
+					return Ok(());
+				};
+
+				let is_youtube = src
+					.host_str()
+					.is_some_and(|host| YOUTUBE_HOSTS.contains(&host));
+				if is_youtube && src.path().starts_with("/embed/") {
+					src.set_host(Some("www.youtube-nocookie.com"))?;
+					element.set_attribute("src", src.as_str())?;
+				}
+				Ok(())
+			})],
+		)
+	}
+}
+
+/// Moves images to the media proxy, so readers do not load them from third-party hosts
+#[derive(Debug)]
+pub struct ProxyMedia {
+	signer: Signer,
+}
+
+impl Rewrite for ProxyMedia {
+	fn rewrite(&self, html: &str, context: Context<'_>) -> eyre::Result<String> {
+		let proxy = |value: &str| {
+			let url = context.base.join(value.trim()).ok()?;
+			(matches!(url.scheme(), "http" | "https") && !self.signer.is_signed(&url))
+				.then(|| String::from(self.signer.sign(&url)))
+		};
+
+		rewrite_elements(
+			html,
+			vec![
+				element!("img[src]", |element| {
+					if let Some(src) = element.get_attribute("src").and_then(|src| proxy(&src)) {
+						element.set_attribute("src", &src)?;
+					}
+					Ok(())
+				}),
+				element!("img[srcset], picture source[srcset]", |element| {
+					if let Some(srcset) = element.get_attribute("srcset") {
+						element.set_attribute("srcset", &map_srcset(&srcset, proxy))?;
+					}
+					Ok(())
+				}),
+			],
+		)
+	}
+}
+
+/// Allowlist based html sanitizer, its policy comes from the config
+#[derive(Debug)]
+pub struct Sanitizer {
+	builder: ammonia::Builder<'static>,
+}
+
+impl Sanitizer {
+	/// Sanitizers live as long as the process, the builder borrows the policy for as long
+	pub fn new(config: SanitizerConfig) -> Self {
+		let config: &'static SanitizerConfig = Box::leak(Box::new(config));
+
+		let mut builder = ammonia::Builder::default();
+		builder
+			.add_tags(MEDIA_TAGS)
+			.add_tags(config.extra_tags.iter().map(String::as_str))
+			.rm_tags(config.removed_tags.iter().map(String::as_str));
+		for (tag, attributes) in MEDIA_TAG_ATTRIBUTES {
+			builder.add_tag_attributes(tag, attributes.iter());
+		}
+		for (tag, attributes) in &config.extra_tag_attributes {
+			builder.add_tag_attributes(tag, attributes.iter().map(String::as_str));
+		}
+
+		// embeds can run anything, only keep the ones from trusted hosts
+		let iframe_hosts = config.iframe_hosts.clone();
+		builder.attribute_filter(move |element, attribute, value| {
+			if element == "iframe" && attribute == "src" {
+				let host = Url::parse(value).ok()?.host_str()?.to_owned();
+				if !iframe_hosts.contains(&host) {
+					return None;
+				}
+			}
+			Some(Cow::Borrowed(value))
+		});
+
+		Self { builder }
+	}
+
+	pub fn clean(&self, html: &str) -> String {
+		self.builder.clean(html).to_string()
+	}
+}
+
+/// Runs element handlers over an html fragment
+fn rewrite_elements(
+	html: &str,
+	handlers: Vec<(Cow<'_, Selector>, ElementContentHandlers<'_>)>,
+) -> eyre::Result<String> {
+	lol_html::rewrite_str(
+		html,
+		RewriteStrSettings {
+			element_content_handlers: handlers,
+			..RewriteStrSettings::new()
+		},
+	)
+	.wrap_err("could not rewrite html")
+}
+
+/// Applies `map` to each url of a `srcset` attribute, keeping their descriptors
+fn map_srcset(srcset: &str, map: impl Fn(&str) -> Option<String>) -> String {
+	srcset
+		.split(',')
+		.map(str::trim)
+		.filter(|candidate| !candidate.is_empty())
+		.map(|candidate| {
+			let (url, descriptor) = candidate
+				.split_once(char::is_whitespace)
+				.unwrap_or((candidate, ""));
+			let url = map(url).unwrap_or_else(|| url.to_owned());
+
+			if descriptor.is_empty() {
+				url
+			} else {
+				format!("{url} {}", descriptor.trim())
+			}
+		})
+		.collect::<Vec<_>>()
+		.join(", ")
+}
+
+/// Url without its tracking parameters, `None` when there was nothing to remove
+pub(super) fn without_tracking(url: &str) -> Option<String> {
+	fn is_tracking(name: &str) -> bool {
+		name.starts_with("utm_") || TRACKING_PARAMETERS.contains(&name)
+	}
+
+	let mut url = Url::parse(url).ok()?;
+	if !url.query_pairs().any(|(name, _)| is_tracking(&name)) {
+		return None;
+	}
+
+	let pairs = url
+		.query_pairs()
+		.filter(|(name, _)| !is_tracking(name))
+		.map(|(name, value)| (name.into_owned(), value.into_owned()))
+		.collect::<Vec<_>>();
+	if pairs.is_empty() {
+		url.set_query(None);
+	} else {
+		url.query_pairs_mut().clear().extend_pairs(pairs);
+	}
+
+	Some(url.into())
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::config::Config;
+
+	use super::*;
+
+	fn rewrite(step: &dyn Rewrite, html: &str) -> String {
+		let base = Url::parse("https://blog.example/posts/first/").expect("base is valid");
+		step.rewrite(html, Context { base: &base })
+			.expect("fixture is rewritable")
+	}
+
+	fn signer() -> Signer {
+		let config = toml::from_str::<Config>(
+			r#"
+			[server]
+			port = 3000
+			database_url = "postgres://localhost/feedr"
+			session_secret = "secret"
+			[web]
+			base_url = "https://feeds.example/"
+			[scheduler]
+			auto-refresh = false
+			refresh-interval = 3600
+			jitter = 0
+			tick-interval = 60
+			"#,
+		)
+		.expect("config fixture is valid");
+		Signer::new(&config).expect("base url is valid")
+	}
+
+	#[test]
+	fn resolves_urls() {
+		let html = r##"<a href="../second/">next</a><a href="#notes">notes</a><img src="cover.png" srcset="small.png 1x, /large.png 2x"><video poster="poster.jpg"></video>"##;
+		assert_eq!(
+			rewrite(&ResolveUrls, html),
+			r##"<a href="https://blog.example/posts/second/">next</a><a href="#notes">notes</a><img src="https://blog.example/posts/first/cover.png" srcset="https://blog.example/posts/first/small.png 1x, https://blog.example/large.png 2x"><video poster="https://blog.example/posts/first/poster.jpg"></video>"##
+		);
+	}
+
+	#[test]
+	fn strips_tracking() {
+		let html = r#"<p>text</p><img src="https://cdn.example/pixel.gif" width="1" height="1"><img src="https://pixel.wp.com/g.gif"><img src="https://cdn.example/photo.jpg" width="640" height="1"><a href="https://shop.example/item?id=3&utm_source=feed&fbclid=abc">item</a><a href="https://shop.example/?utm_medium=rss">home</a>"#;
+		assert_eq!(
+			rewrite(&StripTracking, html),
+			r#"<p>text</p><img src="https://cdn.example/photo.jpg" width="640" height="1"><a href="https://shop.example/item?id=3">item</a><a href="https://shop.example/">home</a>"#
+		);
+	}
+
+	#[test]
+	fn defers_images() {
+		let html = r#"<img src="a.png"><img src="b.png" loading="eager" decoding="sync"><iframe src="https://player.vimeo.com/video/1"></iframe>"#;
+		assert_eq!(
+			rewrite(&LazyImages, html),
+			r#"<img src="a.png" loading="lazy" decoding="async"><img src="b.png" loading="eager" decoding="sync"><iframe src="https://player.vimeo.com/video/1" loading="lazy"></iframe>"#
+		);
+	}
+
+	#[test]
+	fn moves_embeds_to_privacy_domains() {
+		let html = r#"<iframe src="https://www.youtube.com/embed/abc?start=3"></iframe><iframe src="https://www.youtube.com/watch?v=abc"></iframe><iframe src="https://player.vimeo.com/video/1"></iframe>"#;
+		assert_eq!(
+			rewrite(&PrivacyEmbeds, html),
+			r#"<iframe src="https://www.youtube-nocookie.com/embed/abc?start=3"></iframe><iframe src="https://www.youtube.com/watch?v=abc"></iframe><iframe src="https://player.vimeo.com/video/1"></iframe>"#
+		);
+	}
+
+	#[test]
+	fn proxies_media() {
+		let signer = signer();
+		let photo = Url::parse("https://blog.example/posts/first/photo.jpg").expect("url is valid");
+		let large = Url::parse("https://cdn.example/large.jpg").expect("url is valid");
+		let proxied = signer.sign(&photo);
+
+		let html = format!(
+			r#"<img src="photo.jpg" srcset="https://cdn.example/large.jpg 2x"><img src="{proxied}"><img src="data:image/png;base64,AAAA">"#
+		);
+		assert_eq!(
+			rewrite(
+				&ProxyMedia {
+					signer: signer.clone()
+				},
+				&html
+			),
+			format!(
+				r#"<img src="{proxied}" srcset="{} 2x"><img src="{proxied}"><img src="data:image/png;base64,AAAA">"#,
+				signer.sign(&large)
+			)
+		);
+	}
+
+	#[test]
+	fn sanitizes_with_policy() {
+		let sanitizer = Sanitizer::new(SanitizerConfig {
+			extra_tags: vec!["mark".to_owned()],
+			removed_tags: vec!["img".to_owned()],
+			..SanitizerConfig::default()
+		});
+
+		let html = r#"<script>alert(1)</script><p onclick="steal()">kept <mark>marked</mark></p><img src="a.png"><iframe src="https://www.youtube-nocookie.com/embed/abc"></iframe><iframe src="https://evil.example/"></iframe>"#;
+		assert_eq!(
+			sanitizer.clean(html),
+			r#"<p>kept <mark>marked</mark></p><iframe src="https://www.youtube-nocookie.com/embed/abc"></iframe><iframe></iframe>"#
+		);
+	}
+
+	#[test]
+	fn removes_tracking_parameters() {
+		assert_eq!(
+			without_tracking("https://shop.example/item?utm_source=a&id=3&gclid=b#top").as_deref(),
+			Some("https://shop.example/item?id=3#top")
+		);
+		assert_eq!(without_tracking("https://shop.example/item?id=3"), None);
+		assert_eq!(without_tracking("not a url"), None);
+	}
+
+	#[test]
+	fn maps_srcset_candidates() {
+		let upper = |url: &str| (!url.starts_with('k')).then(|| url.to_uppercase());
+		assert_eq!(
+			map_srcset(" a.png 1x,b.png   2x , keep.png,, ", upper),
+			"A.PNG 1x, B.PNG 2x, keep.png"
+		);
+	}
+}
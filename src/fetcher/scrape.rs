@@ -0,0 +1,233 @@
+use diesel::prelude::*;
+use eyre::WrapErr;
+use itertools::Itertools;
+use scraper::{ElementRef, Html, Selector};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use time::{
+	OffsetDateTime,
+	format_description::well_known::{Rfc2822, Rfc3339},
+};
+use url::Url;
+
+use crate::{
+	database::models::FeedId,
+	fetcher::{Error, Fetcher, Result},
+};
+
+/// Css selectors turning a web page into a feed, all but `item` are relative to each item
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScrapeRules {
+	/// Container of each entry
+	pub item: String,
+	pub title: String,
+	/// Defaults to the first link of the item
+	pub link: Option<String>,
+	/// Read from the `datetime` attribute when present, entries are dated when first seen
+	/// otherwise
+	pub date: Option<String>,
+	/// Html content of the entry
+	pub content: Option<String>,
+}
+
+struct Selectors {
+	item: Selector,
+	title: Selector,
+	link: Selector,
+	date: Option<Selector>,
+	content: Option<Selector>,
+}
+
+impl ScrapeRules {
+	fn selectors(&self) -> std::result::Result<Selectors, &'static str> {
+		let parse =
+			|selector: &str| Selector::parse(selector.trim()).map_err(|_| "invalid css selector");
+		let optional = |selector: &Option<String>| {
+			selector
+				.as_deref()
+				.filter(|selector| !selector.trim().is_empty())
+				.map(parse)
+				.transpose()
+		};
+
+		Ok(Selectors {
+			item: parse(&self.item)?,
+			title: parse(&self.title)?,
+			link: optional(&self.link)?
+				.unwrap_or_else(|| parse("a[href]").expect("selector is valid")),
+			date: optional(&self.date)?,
+			content: optional(&self.content)?,
+		})
+	}
+
+	/// Checks the selectors, returns the reason they are refused
+	pub fn validate(&self) -> std::result::Result<(), &'static str> {
+		self.selectors().map(|_| ())
+	}
+}
+
+/// Entry extracted from a page
+#[derive(Debug, Clone, Serialize)]
+pub struct ScrapedItem {
+	pub id: String,
+	pub title: String,
+	pub link: Option<Url>,
+	#[serde(with = "time::serde::rfc3339::option")]
+	pub date: Option<OffsetDateTime>,
+	pub content: Option<String>,
+}
+
+/// Applies the rules to a page, items without a title are left out
+pub fn scrape(rules: &ScrapeRules, base: &Url, html: &str) -> Result<Vec<ScrapedItem>> {
+	let selectors = rules.selectors().map_err(Error::Scrape)?;
+	let document = Html::parse_document(html);
+
+	let items = document
+		.select(&selectors.item)
+		.filter_map(|item| scrape_item(&selectors, base, item))
+		// the same entry is sometimes listed twice, e.g. in a featured section
+		.unique_by(|item| item.id.clone())
+		.collect::<Vec<_>>();
+
+	if items.is_empty() {
+		return Err(Error::Scrape("no item matched the selectors"));
+	}
+
+	Ok(items)
+}
+
+fn scrape_item(selectors: &Selectors, base: &Url, item: ElementRef<'_>) -> Option<ScrapedItem> {
+	let text = |element: ElementRef<'_>| element.text().collect::<String>().trim().to_owned();
+
+	let title = item
+		.select(&selectors.title)
+		.next()
+		.map(text)
+		.filter(|title| !title.is_empty())?;
+
+	// the item itself may be the link
+	let link = item
+		.select(&selectors.link)
+		.next()
+		.or_else(|| (item.value().name() == "a").then_some(item))
+		.and_then(|link| link.attr("href"))
+		.and_then(|href| base.join(href.trim()).ok())
+		.filter(|link| matches!(link.scheme(), "http" | "https"));
+
+	let date = selectors
+		.date
+		.as_ref()
+		.and_then(|selector| item.select(selector).next())
+		.and_then(|date| {
+			parse_date(
+				date.attr("datetime")
+					.map_or_else(|| text(date), ToOwned::to_owned)
+					.trim(),
+			)
+		});
+
+	let content = selectors
+		.content
+		.as_ref()
+		.and_then(|selector| item.select(selector).next())
+		.map(|content| content.inner_html().trim().to_owned())
+		.filter(|content| !content.is_empty());
+
+	// pages have no identifiers, entries without links are told apart by their title
+	let id = link.as_ref().map_or_else(
+		|| format!("urn:sha256:{:x}", Sha256::digest(title.as_bytes())),
+		ToString::to_string,
+	);
+
+	Some(ScrapedItem {
+		id,
+		title,
+		link,
+		date,
+		content,
+	})
+}
+
+/// Parses the usual machine readable dates, days alone are taken at midnight utc
+fn parse_date(date: &str) -> Option<OffsetDateTime> {
+	OffsetDateTime::parse(date, &Rfc3339)
+		.or_else(|_| OffsetDateTime::parse(date, &Rfc2822))
+		.or_else(|_| OffsetDateTime::parse(&format!("{date}T00:00:00Z"), &Rfc3339))
+		.ok()
+}
+
+/// Json feed document listing scraped items, parsed like any other feed
+pub fn json_feed(base: &Url, html: &str, items: &[ScrapedItem]) -> Vec<u8> {
+	let document = Html::parse_document(html);
+	let title = document
+		.select(&Selector::parse("title").expect("selector is valid"))
+		.next()
+		.map(|title| title.text().collect::<String>().trim().to_owned())
+		.filter(|title| !title.is_empty());
+
+	let items = items
+		.iter()
+		.map(|item| {
+			json!({
+				"id": item.id,
+				"url": item.link,
+				"title": item.title,
+				"content_html": item.content,
+				"date_published": item.date.and_then(|date| date.format(&Rfc3339).ok()),
+			})
+		})
+		.collect::<Vec<_>>();
+
+	let feed = json!({
+		"version": "https://jsonfeed.org/version/1.1",
+		"title": title,
+		"home_page_url": base,
+		"items": items,
+	});
+
+	serde_json::to_vec(&feed).expect("json values always serialize")
+}
+
+impl Fetcher {
+	/// Selectors of a scraped feed, `None` for feeds fetched from their url
+	pub(super) fn scrape_rules(&self, feed_id: FeedId) -> Result<Option<ScrapeRules>> {
+		use crate::database::schema::*;
+
+		let mut conn = self.db_pool.get()?;
+		let rules = feed::table
+			.find(feed_id)
+			.select(feed::scrape_rules)
+			.get_result::<Option<serde_json::Value>>(&mut conn)
+			.wrap_err("could not retrieve feed scrape rules")?;
+
+		let rules = rules
+			.map(serde_json::from_value)
+			.transpose()
+			.wrap_err("invalid stored scrape rules")?;
+
+		Ok(rules)
+	}
+
+	/// Downloads a page and applies the rules once, nothing is stored
+	pub(super) async fn preview_scrape(
+		&self,
+		url: &Url,
+		rules: &ScrapeRules,
+	) -> Result<Vec<ScrapedItem>> {
+		let (response, _) = self.client.get(url).await?;
+
+		let status = response.status();
+		if !status.is_success() {
+			return Err(Error::Status {
+				status,
+				retry_after: None,
+			});
+		}
+
+		let base = response.url().clone();
+		let body = self.client.body(response).await?;
+
+		scrape(rules, &base, &String::from_utf8_lossy(&body))
+	}
+}
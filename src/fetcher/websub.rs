@@ -0,0 +1,515 @@
+use std::{borrow::Cow, time::Instant};
+
+use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
+use diesel::{dsl, prelude::*};
+use eyre::WrapErr;
+use feed_rs::{model, parser};
+use hmac::{Hmac, Mac};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use sha1::Sha1;
+use sha2::{Sha256, Sha384, Sha512};
+use subtle::ConstantTimeEq;
+use time::{Duration, OffsetDateTime};
+use url::Url;
+
+use crate::{
+	database::models::{
+		FeedId, NewFeedFetchLog, NewWebSubSubscription, WebSubSubscription, WebSubSubscriptionId,
+	},
+	fetcher::{Error, FetchOutcome, Fetcher, Result, fetch_log, hints, self_link},
+};
+
+/// Time given to a hub to verify a subscription request before sending it again
+const PENDING_TIMEOUT: Duration = Duration::DAY;
+/// Delay before asking again a hub that denied a subscription
+const DENIED_RETRY: Duration = Duration::WEEK;
+
+/// What became of content pushed by a hub
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Pushed {
+	/// No subscription matches the callback
+	Unknown,
+	/// Content was acknowledged but not stored
+	Dropped,
+	Stored(FeedId),
+}
+
+/// Query of the requests a hub sends to the callback to verify the intent of a subscription
+#[derive(Debug, Deserialize)]
+pub struct Intent {
+	#[serde(rename = "hub.mode")]
+	pub mode: String,
+	#[serde(rename = "hub.topic")]
+	pub topic: String,
+	#[serde(rename = "hub.challenge")]
+	pub challenge: Option<String>,
+	#[serde(rename = "hub.lease_seconds")]
+	pub lease_seconds: Option<u32>,
+	#[serde(rename = "hub.reason")]
+	pub reason: Option<String>,
+}
+
+impl Fetcher {
+	/// Subscribes to the hub of a fetched feed, or renews the lease of the current subscription
+	///
+	/// Unchanged feeds are not parsed, their known subscription is renewed as is.
+	pub(super) async fn maintain_subscription(
+		&self,
+		feed_id: FeedId,
+		url: &Url,
+		feed: Option<&model::Feed>,
+	) -> Result<()> {
+		let Some(callback_base) = &self.callback_url else {
+			return Ok(());
+		};
+
+		let subscription = self.subscription(feed_id)?;
+		let (hub, topic) = match feed {
+			Some(feed) => match advertised_hub(feed, url) {
+				Some(advertised) => advertised,
+				// a hub that is not advertised anymore lets the lease expire
+				None => return Ok(()),
+			},
+			None => match &subscription {
+				Some(subscription) => (
+					Url::parse(&subscription.hub).wrap_err("invalid stored hub url")?,
+					Url::parse(&subscription.topic).wrap_err("invalid stored topic url")?,
+				),
+				None => return Ok(()),
+			},
+		};
+
+		let current = subscription.filter(|subscription| {
+			subscription.hub == hub.as_str() && subscription.topic == topic.as_str()
+		});
+		if current
+			.as_ref()
+			.is_some_and(|subscription| is_settled(subscription, OffsetDateTime::now_utc()))
+		{
+			return Ok(());
+		}
+
+		// content may be pushed with the current secret until the renewal is verified
+		let (secret, callback_token) = current.as_ref().map_or_else(
+			|| (random_token(), random_token()),
+			|subscription| {
+				(
+					subscription.secret.clone(),
+					subscription.callback_token.clone(),
+				)
+			},
+		);
+		let status = match &current {
+			Some(subscription) if subscription.status == "active" => "active",
+			_ => "pending",
+		};
+
+		let new_subscription = NewWebSubSubscription {
+			feed_id,
+			hub: Cow::Borrowed(hub.as_str()),
+			topic: Cow::Borrowed(topic.as_str()),
+			secret,
+			callback_token,
+			status,
+			requested_at: OffsetDateTime::now_utc(),
+		};
+		let subscription_id = self.save_subscription(&new_subscription)?;
+
+		let callback = callback_base
+			.join(&format!(
+				"{subscription_id}/{}",
+				new_subscription.callback_token
+			))
+			.wrap_err("invalid callback url")?;
+		let lease_seconds = self.config.websub_lease.to_string();
+		let form = [
+			("hub.mode", "subscribe"),
+			("hub.topic", topic.as_str()),
+			("hub.callback", callback.as_str()),
+			("hub.secret", &new_subscription.secret),
+			("hub.lease_seconds", &lease_seconds),
+		];
+
+		// the hub verifies the intent on the callback, possibly before answering
+		let (response, _) = self
+			.client
+			.send(&hub, |url| self.client.post(url).form(&form))
+			.await?;
+		if !response.status().is_success() {
+			return Err(Error::Status {
+				status: response.status(),
+				retry_after: None,
+			});
+		}
+
+		tracing::info!(feed_id = ?feed_id, hub = %hub, topic = %topic, "requested websub subscription");
+
+		Ok(())
+	}
+
+	/// Answers a hub verifying the intent of a subscription
+	///
+	/// Returns the body to answer with, `None` when the intent does not match a subscription.
+	pub(super) fn verify_intent(
+		&self,
+		subscription_id: WebSubSubscriptionId,
+		callback_token: &str,
+		intent: &Intent,
+	) -> Result<Option<String>> {
+		use crate::database::schema::*;
+
+		let mut conn = self.db_pool.get()?;
+		let Some(subscription) = websub_subscription::table
+			.find(subscription_id)
+			.select(WebSubSubscription::as_select())
+			.get_result(&mut conn)
+			.optional()
+			.wrap_err("could not retrieve websub subscription")?
+		else {
+			return Ok(None);
+		};
+
+		if !has_token(&subscription, callback_token) || subscription.topic != intent.topic {
+			return Ok(None);
+		}
+
+		match intent.mode.as_str() {
+			// only confirm requests we recently sent
+			"subscribe"
+				if OffsetDateTime::now_utc() - subscription.requested_at < PENDING_TIMEOUT =>
+			{
+				let Some(challenge) = &intent.challenge else {
+					return Ok(None);
+				};
+
+				let lease_seconds = intent
+					.lease_seconds
+					.map_or(self.config.websub_lease, u64::from);
+				let lease_seconds = i32::try_from(lease_seconds).unwrap_or(i32::MAX);
+
+				dsl::update(websub_subscription::table.find(subscription_id))
+					.set((
+						websub_subscription::status.eq("active"),
+						websub_subscription::lease_seconds.eq(lease_seconds),
+						websub_subscription::lease_expires_at
+							.eq(OffsetDateTime::now_utc()
+								+ Duration::seconds(i64::from(lease_seconds))),
+					))
+					.execute(&mut conn)
+					.wrap_err("could not activate websub subscription")?;
+
+				tracing::info!(feed_id = ?subscription.feed_id, lease_seconds, "websub subscription verified");
+
+				Ok(Some(challenge.clone()))
+			}
+			"denied" => {
+				dsl::update(websub_subscription::table.find(subscription_id))
+					.set(websub_subscription::status.eq("denied"))
+					.execute(&mut conn)
+					.wrap_err("could not deny websub subscription")?;
+
+				tracing::warn!(feed_id = ?subscription.feed_id, reason = intent.reason, "websub subscription denied");
+
+				Ok(Some(String::new()))
+			}
+			// subscriptions are never cancelled, let other requests fail
+			_ => Ok(None),
+		}
+	}
+
+	/// Stores content pushed by a hub like a regular fetch
+	///
+	/// Content with an invalid signature is acknowledged but dropped, as hubs must not learn
+	/// whether the signature matched.
+	pub(super) fn receive(
+		&self,
+		subscription_id: WebSubSubscriptionId,
+		callback_token: &str,
+		signature: Option<&str>,
+		body: &[u8],
+	) -> Result<Pushed> {
+		use crate::database::schema::*;
+
+		let mut conn = self.db_pool.get()?;
+		let Some(subscription) = websub_subscription::table
+			.find(subscription_id)
+			.select(WebSubSubscription::as_select())
+			.get_result(&mut conn)
+			.optional()
+			.wrap_err("could not retrieve websub subscription")?
+		else {
+			return Ok(Pushed::Unknown);
+		};
+		drop(conn);
+
+		if !has_token(&subscription, callback_token) {
+			return Ok(Pushed::Unknown);
+		}
+
+		let feed_id = subscription.feed_id;
+		if !signature
+			.is_some_and(|signature| is_signed(subscription.secret.as_bytes(), signature, body))
+		{
+			tracing::warn!(feed_id = ?feed_id, "dropping pushed content with an invalid signature");
+			return Ok(Pushed::Dropped);
+		}
+
+		let topic = Url::parse(&subscription.topic).wrap_err("invalid stored topic url")?;
+
+		let fetched_at = OffsetDateTime::now_utc();
+		let timer = Instant::now();
+		let result = self.parse_pushed(feed_id, &topic, body);
+		let log = NewFeedFetchLog {
+			pushed: true,
+			..fetch_log(feed_id, fetched_at, timer.elapsed(), &result)
+		};
+
+		match result {
+			Ok(outcome) => {
+				let feed_id = self.on_fetched(feed_id, &topic, &outcome, &log)?;
+				Ok(Pushed::Stored(feed_id))
+			}
+			// a broken push does not make the feed itself fail
+			Err(err) => {
+				tracing::warn!(feed_id = ?feed_id, err = %err, "could not parse pushed content");
+
+				let mut conn = self.db_pool.get()?;
+				log.insert_into(feed_fetch_log::table)
+					.execute(&mut conn)
+					.wrap_err("unable to journal pushed content")?;
+
+				Ok(Pushed::Dropped)
+			}
+		}
+	}
+
+	fn parse_pushed(&self, feed_id: FeedId, topic: &Url, body: &[u8]) -> Result<FetchOutcome> {
+		let parser = parser::Builder::new()
+			.base_uri(Some(topic))
+			.sanitize_content(false)
+			.build();
+		let feed = parser.parse(body)?;
+
+		Ok(FetchOutcome {
+			http_status: StatusCode::OK,
+			bytes: body.len(),
+			publisher_interval: hints::publisher_interval(&feed, body),
+			feed: Some(Box::new(feed)),
+			max_age: None,
+			// pushed content does not replace what conditional requests rely on
+			validators: self.cache_validators(feed_id)?,
+			redirects: Vec::new(),
+			moved_to: None,
+			declared_move: None,
+		})
+	}
+
+	fn subscription(&self, feed_id: FeedId) -> Result<Option<WebSubSubscription<'static>>> {
+		use crate::database::schema::*;
+
+		let mut conn = self.db_pool.get()?;
+		let subscription = websub_subscription::table
+			.filter(websub_subscription::feed_id.eq(feed_id))
+			.select(WebSubSubscription::as_select())
+			.get_result(&mut conn)
+			.optional()
+			.wrap_err("could not retrieve websub subscription")?;
+
+		Ok(subscription)
+	}
+
+	fn save_subscription(
+		&self,
+		subscription: &NewWebSubSubscription,
+	) -> Result<WebSubSubscriptionId> {
+		use crate::database::schema::*;
+
+		let mut conn = self.db_pool.get()?;
+		let subscription_id = subscription
+			.insert_into(websub_subscription::table)
+			.on_conflict(websub_subscription::feed_id)
+			.do_update()
+			.set(subscription)
+			.returning(websub_subscription::id)
+			.get_result(&mut conn)
+			.wrap_err("could not store websub subscription")?;
+
+		Ok(subscription_id)
+	}
+}
+
+/// Hub a feed advertises with `<link rel="hub">`, along with the topic to subscribe to
+///
+/// The topic is the `rel="self"` link of the feed, falling back to its url.
+fn advertised_hub(feed: &model::Feed, url: &Url) -> Option<(Url, Url)> {
+	let hub = feed
+		.links
+		.iter()
+		.find(|link| link.rel.as_deref() == Some("hub"))?;
+	let hub = Url::parse(&hub.href)
+		.ok()
+		.filter(|hub| matches!(hub.scheme(), "http" | "https"))?;
+
+	Some((hub, self_link(feed).unwrap_or_else(|| url.clone())))
+}
+
+/// Whether a subscription needs no request to the hub for now
+///
+/// Active leases are renewed once half of them elapsed, other requests are retried after a while.
+fn is_settled(subscription: &WebSubSubscription, now: OffsetDateTime) -> bool {
+	let since_request = now - subscription.requested_at;
+
+	match subscription.status.as_ref() {
+		"active" => {
+			since_request < PENDING_TIMEOUT
+				|| subscription
+					.lease_expires_at
+					.zip(subscription.lease_seconds)
+					.is_some_and(|(expires_at, lease_seconds)| {
+						expires_at - now > Duration::seconds(i64::from(lease_seconds / 2))
+					})
+		}
+		"pending" => since_request < PENDING_TIMEOUT,
+		_ => since_request < DENIED_RETRY,
+	}
+}
+
+fn random_token() -> Cow<'static, str> {
+	Cow::Owned(BASE64_URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>()))
+}
+
+/// Compares the token of a callback url in constant time, it is the only proof a request comes
+/// from the hub
+fn has_token(subscription: &WebSubSubscription, callback_token: &str) -> bool {
+	subscription
+		.callback_token
+		.as_bytes()
+		.ct_eq(callback_token.as_bytes())
+		.into()
+}
+
+/// Checks a `X-Hub-Signature` header, formatted as `method=hex-digest`
+fn is_signed(secret: &[u8], signature: &str, body: &[u8]) -> bool {
+	let Some((method, digest)) = signature.split_once('=') else {
+		return false;
+	};
+	let Some(digest) = decode_hex(digest) else {
+		return false;
+	};
+
+	let verified = match method {
+		"sha1" => Hmac::<Sha1>::new_from_slice(secret)
+			.map(|mac| mac.chain_update(body).verify_slice(&digest).is_ok()),
+		"sha256" => Hmac::<Sha256>::new_from_slice(secret)
+			.map(|mac| mac.chain_update(body).verify_slice(&digest).is_ok()),
+		"sha384" => Hmac::<Sha384>::new_from_slice(secret)
+			.map(|mac| mac.chain_update(body).verify_slice(&digest).is_ok()),
+		"sha512" => Hmac::<Sha512>::new_from_slice(secret)
+			.map(|mac| mac.chain_update(body).verify_slice(&digest).is_ok()),
+		_ => return false,
+	};
+
+	verified.unwrap_or(false)
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+	// `from_str_radix` would also accept a leading `+`
+	if !hex.len().is_multiple_of(2) || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+		return None;
+	}
+
+	(0..hex.len())
+		.step_by(2)
+		.map(|index| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok())
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// RFC 2202 and RFC 4231 test case 2
+	const SECRET: &[u8] = b"Jefe";
+	const BODY: &[u8] = b"what do ya want for nothing?";
+	const SHA1: &str = "effcdf6ae5eb2fa2d27416d5f184df9c259a7c79";
+	const SHA256: &str = "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843";
+	const SHA384: &str = "af45d2e376484031617f78d2b58a6b1b9c7ef464f5a01b47e42ec3736322445e\
+		8e2240ca5e69e2c78b3239ecfab21649";
+	const SHA512: &str = "164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea250554\
+		9758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737";
+
+	#[test]
+	fn accepts_valid_signatures() {
+		for (method, digest) in [
+			("sha1", SHA1),
+			("sha256", SHA256),
+			("sha384", SHA384),
+			("sha512", SHA512),
+		] {
+			assert!(
+				is_signed(SECRET, &format!("{method}={digest}"), BODY),
+				"{method}"
+			);
+			let upper = digest.to_ascii_uppercase();
+			assert!(
+				is_signed(SECRET, &format!("{method}={upper}"), BODY),
+				"{method}"
+			);
+		}
+	}
+
+	#[test]
+	fn rejects_wrong_signatures() {
+		assert!(!is_signed(b"secret", &format!("sha256={SHA256}"), BODY));
+		assert!(!is_signed(
+			SECRET,
+			&format!("sha256={SHA256}"),
+			b"what do ya want?"
+		));
+		// digests of another method or truncated digests never match
+		assert!(!is_signed(SECRET, &format!("sha1={SHA256}"), BODY));
+		assert!(!is_signed(
+			SECRET,
+			&format!("sha256={}", &SHA256[..32]),
+			BODY
+		));
+		assert!(!is_signed(SECRET, "sha256=", BODY));
+	}
+
+	#[test]
+	fn rejects_unknown_methods() {
+		assert!(!is_signed(SECRET, &format!("md5={SHA1}"), BODY));
+		assert!(!is_signed(SECRET, &format!("SHA256={SHA256}"), BODY));
+		assert!(!is_signed(SECRET, SHA256, BODY));
+		assert!(!is_signed(SECRET, "", BODY));
+	}
+
+	#[test]
+	fn rejects_malformed_digests() {
+		assert!(!is_signed(
+			SECRET,
+			&format!("sha256={}", &SHA256[1..]),
+			BODY
+		));
+		assert!(!is_signed(SECRET, &format!("sha256={SHA256}0"), BODY));
+		assert!(!is_signed(
+			SECRET,
+			&format!("sha256={}zz", &SHA256[2..]),
+			BODY
+		));
+		assert!(!is_signed(
+			SECRET,
+			&format!("sha256=+{}", &SHA256[1..]),
+			BODY
+		));
+
+		assert_eq!(decode_hex("00ff7F"), Some(vec![0x00, 0xff, 0x7f]));
+		assert_eq!(decode_hex(""), Some(Vec::new()));
+		assert_eq!(decode_hex("abc"), None);
+		assert_eq!(decode_hex("+f"), None);
+		assert_eq!(decode_hex("-1"), None);
+		assert_eq!(decode_hex("0g"), None);
+		assert_eq!(decode_hex("é0"), None);
+	}
+}
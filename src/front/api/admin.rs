@@ -0,0 +1,122 @@
+use axum::{
+	Form, Json, Router,
+	extract::Path,
+	http::StatusCode,
+	routing::{get, put},
+};
+use diesel::{dsl, prelude::*};
+use eyre::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+	config::RessourcesRef,
+	database::models::{FeedId, UserId},
+	front::{
+		auth::ApiSession,
+		error::{AuthError, RouteError, RouteResult},
+	},
+	retention::RetentionReport,
+};
+
+/// Instance management, restricted to the users listed in `server.admins`
+pub fn router() -> Router<RessourcesRef> {
+	Router::new()
+		.route(
+			"/retention",
+			get(retention_get_handler).post(retention_post_handler),
+		)
+		.route("/feeds/{id}/retention", put(feed_retention_put_handler))
+}
+
+fn admin_id(auth: &ApiSession, ressources: &RessourcesRef) -> RouteResult<UserId> {
+	use crate::database::schema::*;
+
+	let user_id = auth.user_id()?;
+
+	let mut conn = ressources.database_handle.get()?;
+	let username = user_::table
+		.find(user_id)
+		.select(user_::username)
+		.get_result::<String>(&mut conn)
+		.wrap_err("could not retrieve user")?;
+
+	if ressources.admins.contains(&username) {
+		Ok(user_id)
+	} else {
+		Err(AuthError::NotAdmin.into())
+	}
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct RetentionGetResponse {
+	last_report: Option<RetentionReport>,
+}
+
+// Outcome of the latest retention run of this instance
+async fn retention_get_handler(
+	auth: ApiSession,
+	ressources: RessourcesRef,
+) -> RouteResult<Json<RetentionGetResponse>> {
+	admin_id(&auth, &ressources)?;
+
+	Ok(Json(RetentionGetResponse {
+		last_report: ressources.retention_handle.last_report(),
+	}))
+}
+
+// Purges old entries right away
+async fn retention_post_handler(
+	auth: ApiSession,
+	ressources: RessourcesRef,
+) -> RouteResult<Json<RetentionReport>> {
+	admin_id(&auth, &ressources)?;
+
+	let report = ressources
+		.retention_handle
+		.run()
+		.await
+		.wrap_err("could not purge entries")?;
+
+	Ok(Json(report))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct FeedRetentionPutRequest {
+	/// In seconds, missing follows the global limit and `0` keeps entries forever
+	max_age_secs: Option<i32>,
+	/// Missing follows the global limit and `0` keeps every entry
+	max_entries: Option<i32>,
+}
+
+// Overrides the retention limits of a feed
+async fn feed_retention_put_handler(
+	auth: ApiSession,
+	ressources: RessourcesRef,
+	Path(id): Path<FeedId>,
+	Form(query): Form<FeedRetentionPutRequest>,
+) -> RouteResult<StatusCode> {
+	use crate::database::schema::*;
+
+	admin_id(&auth, &ressources)?;
+
+	if query.max_age_secs.is_some_and(|max_age| max_age < 0)
+		|| query.max_entries.is_some_and(|max_entries| max_entries < 0)
+	{
+		return Err(RouteError::User("retention limits cannot be negative"));
+	}
+
+	let mut conn = ressources.database_handle.get()?;
+	let updated = dsl::update(feed::table.find(id))
+		.set((
+			feed::retention_max_age_secs.eq(query.max_age_secs),
+			feed::retention_max_entries.eq(query.max_entries),
+		))
+		.execute(&mut conn)
+		.wrap_err("could not update feed retention")?;
+
+	if updated == 0 {
+		return Err(RouteError::NotFound("no such feed"));
+	}
+
+	Ok(StatusCode::OK)
+}
@@ -1,19 +1,45 @@
-use axum::{Json, Router, routing::get};
+use axum::{
+	Json, Router,
+	extract::{Path, Query},
+	http::StatusCode,
+	routing::{get, put},
+};
 use eyre::Context;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use similar::TextDiff;
 
 use crate::{
 	config::RessourcesRef,
-	database::ResolvedUserEntry,
-	front::{auth::ApiSession, error::RouteResult},
+	database::{
+		ResolvedEntryRevisions, ResolvedUserEntry,
+		models::{FeedEntryId, FeedEntryRevisionId, UserFeedEntryMeta},
+	},
+	front::{
+		auth::ApiSession,
+		error::{RouteError, RouteResult},
+	},
 };
 
 pub fn router() -> Router<RessourcesRef> {
-	Router::new().route(
-		"/",
-		get(entries_get_handler), // .post(entries_post_handler)
-		                          // .delete(entries_post_handler),
-	)
+	Router::new()
+		.route(
+			"/",
+			get(entries_get_handler), // .post(entries_post_handler)
+			                          // .delete(entries_post_handler),
+		)
+		.route(
+			"/{id}/read",
+			put(entry_read_put_handler).delete(entry_read_delete_handler),
+		)
+		.route("/{id}/revisions", get(entry_revisions_get_handler))
+		.route("/{id}/diff", get(entry_diff_get_handler))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct EntriesGetQuery {
+	/// Lists each story once, duplicates from other feeds are only referenced
+	#[serde(default)]
+	collapse: bool,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -25,12 +51,133 @@ struct EntriesGetResponse<'a> {
 async fn entries_get_handler<'a>(
 	auth: ApiSession,
 	ressources: RessourcesRef,
+	Query(query): Query<EntriesGetQuery>,
 ) -> RouteResult<Json<EntriesGetResponse<'a>>> {
 	let user_id = auth.user_id()?;
 
 	let mut conn = ressources.database_handle.get()?;
-	let user_feed_entries = ResolvedUserEntry::resolve_all(user_id, &mut conn)
+	let mut user_feed_entries = ResolvedUserEntry::resolve_all(user_id, &mut conn)
 		.wrap_err("could not retrieve user feed entries")?;
+	if query.collapse {
+		user_feed_entries = ResolvedUserEntry::collapse(user_feed_entries);
+	}
 
 	Ok(Json(EntriesGetResponse { user_feed_entries }))
 }
+
+// Mark an entry as read, along with its duplicates in other feeds
+async fn entry_read_put_handler(
+	auth: ApiSession,
+	ressources: RessourcesRef,
+	Path(id): Path<FeedEntryId>,
+) -> RouteResult<StatusCode> {
+	mark_read(&auth, &ressources, id, true)
+}
+
+// Mark an entry as unread, along with its duplicates in other feeds
+async fn entry_read_delete_handler(
+	auth: ApiSession,
+	ressources: RessourcesRef,
+	Path(id): Path<FeedEntryId>,
+) -> RouteResult<StatusCode> {
+	mark_read(&auth, &ressources, id, false)
+}
+
+fn mark_read(
+	auth: &ApiSession,
+	ressources: &RessourcesRef,
+	id: FeedEntryId,
+	read: bool,
+) -> RouteResult<StatusCode> {
+	let user_id = auth.user_id()?;
+
+	let mut conn = ressources.database_handle.get()?;
+	UserFeedEntryMeta::mark_story_read(user_id, id, read, &mut conn)
+		.wrap_err("could not update entry read state")?
+		.ok_or(RouteError::NotFound("the current user has no such entry"))?;
+
+	Ok(StatusCode::OK)
+}
+
+// Previous versions of an entry edited by its publisher
+async fn entry_revisions_get_handler<'a>(
+	auth: ApiSession,
+	ressources: RessourcesRef,
+	Path(id): Path<FeedEntryId>,
+) -> RouteResult<Json<ResolvedEntryRevisions<'a>>> {
+	let user_id = auth.user_id()?;
+
+	let mut conn = ressources.database_handle.get()?;
+	let revisions = ResolvedEntryRevisions::resolve(user_id, id, &mut conn)
+		.wrap_err("could not retrieve entry revisions")?
+		.ok_or(RouteError::NotFound("the current user has no such entry"))?;
+
+	Ok(Json(revisions))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct EntryDiffQuery {
+	/// Defaults to the latest revision
+	from: Option<FeedEntryRevisionId>,
+	/// Defaults to the current version
+	to: Option<FeedEntryRevisionId>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct EntryDiffResponse {
+	/// Unified diff of the title and content
+	diff: String,
+}
+
+// Changes between two versions of an entry
+async fn entry_diff_get_handler(
+	auth: ApiSession,
+	ressources: RessourcesRef,
+	Path(id): Path<FeedEntryId>,
+	Query(query): Query<EntryDiffQuery>,
+) -> RouteResult<Json<EntryDiffResponse>> {
+	let user_id = auth.user_id()?;
+
+	let mut conn = ressources.database_handle.get()?;
+	let entry = ResolvedEntryRevisions::resolve(user_id, id, &mut conn)
+		.wrap_err("could not retrieve entry revisions")?
+		.ok_or(RouteError::NotFound("the current user has no such entry"))?;
+
+	let version = |revision_id: Option<FeedEntryRevisionId>| match revision_id {
+		Some(revision_id) => entry
+			.revisions
+			.iter()
+			.find(|revision| revision.id == revision_id)
+			.map(|revision| {
+				(
+					format!("revision {revision_id}"),
+					version_text(&revision.title, revision.content.as_deref()),
+				)
+			})
+			.ok_or(RouteError::NotFound("the entry has no such revision")),
+		None => Ok((
+			"current".to_owned(),
+			version_text(&entry.title, entry.content.as_deref()),
+		)),
+	};
+
+	let from = query
+		.from
+		.or_else(|| entry.revisions.last().map(|revision| revision.id))
+		.ok_or(RouteError::NotFound("the entry has no revision"))?;
+	let (from_name, from_text) = version(Some(from))?;
+	let (to_name, to_text) = version(query.to)?;
+
+	let diff = TextDiff::from_lines(&from_text, &to_text)
+		.unified_diff()
+		.header(&from_name, &to_name)
+		.to_string();
+
+	Ok(Json(EntryDiffResponse { diff }))
+}
+
+/// Title and content of a version, with one tag per line so html changes diff by line
+fn version_text(title: &str, content: Option<&str>) -> String {
+	let content = content.unwrap_or_default().replace("><", ">\n<");
+	format!("{title}\n\n{content}\n")
+}
@@ -1,10 +1,11 @@
-use std::{borrow::Cow, io};
+use std::{borrow::Cow, collections::BTreeMap, io};
 
 use axum::{
 	Form, Json, Router,
-	extract::Multipart,
-	http::StatusCode,
-	routing::{get, post},
+	extract::{Multipart, Path, Query},
+	http::{HeaderValue, StatusCode},
+	response::{IntoResponse, Response},
+	routing::{get, patch, post, put},
 };
 use diesel::{
 	dsl,
@@ -18,9 +19,12 @@ use url::Url;
 use crate::{
 	config::RessourcesRef,
 	database::{
-		ResolvedUserFeed,
-		models::{self, Feed, NewUserFeed, UserFeedFolder, UserFeedId},
+		ResolvedFeedHealth, ResolvedUserFeed,
+		models::{
+			self, Feed, FeedFetchLog, FeedRequestOptions, NewUserFeed, UserFeedFolder, UserFeedId,
+		},
 	},
+	fetcher::{self, Candidate, Credentials, ErrorKind, Priority, ScrapeRules},
 	front::{
 		auth::ApiSession,
 		error::{RouteError, RouteResult},
@@ -37,6 +41,15 @@ pub fn router() -> Router<RessourcesRef> {
 				.delete(feeds_delete_handler),
 		)
 		.route("/import", post(import_post_handler))
+		.route("/scraped", post(scraped_post_handler))
+		.route("/{id}", patch(feed_patch_handler))
+		.route("/{id}/health", get(feed_health_get_handler))
+		.route("/{id}/refresh", post(feed_refresh_post_handler))
+		.route(
+			"/{id}/request",
+			get(feed_request_get_handler).put(feed_request_put_handler),
+		)
+		.route("/{id}/scrape", put(feed_scrape_put_handler))
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -58,30 +71,349 @@ async fn feeds_get_handler(
 	Ok(Json(FeedsGetResponse { user_feeds }))
 }
 
+// Retrieve fetch state and latest fetch attempts of a feed
+async fn feed_health_get_handler(
+	auth: ApiSession,
+	ressources: RessourcesRef,
+	Path(id): Path<UserFeedId>,
+) -> RouteResult<Json<ResolvedFeedHealth<'static>>> {
+	let user_id = auth.user_id()?;
+
+	let mut conn = ressources.database_handle.get()?;
+	let health = ResolvedFeedHealth::resolve(user_id, id, &mut conn)
+		.wrap_err("could not retrieve feed health")?
+		.ok_or(RouteError::NotFound("the current user has no such feed"))?;
+
+	Ok(Json(health))
+}
+
+#[derive(Debug, Deserialize)]
+struct FeedRefreshQuery {
+	/// Answers once the feed is fetched, bounded by the request timeout of the fetcher
+	#[serde(default)]
+	wait: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct FeedRefreshResponse<'a> {
+	/// Attempt made for the refresh, `None` when not waited for or not done in time
+	fetch_log: Option<FeedFetchLog<'a>>,
+}
+
+// Fetch a feed ahead of its schedule
+//
+// Answers `202 Accepted` once the feed is queued, or `200 OK` along with the fetch attempt when
+// waiting for it. Feeds fetched in the last minute are not queued, their last attempt is answered.
+async fn feed_refresh_post_handler(
+	auth: ApiSession,
+	ressources: RessourcesRef,
+	Path(id): Path<UserFeedId>,
+	Query(query): Query<FeedRefreshQuery>,
+) -> RouteResult<Response> {
+	use crate::database::schema::*;
+
+	let user_id = auth.user_id()?;
+
+	let feed = {
+		let mut conn = ressources.database_handle.get()?;
+		user_feed::table
+			.inner_join(feed::table)
+			.filter(user_feed::id.eq(id))
+			.filter(user_feed::user_id.eq(user_id))
+			.select((feed::id, feed::source))
+			.get_result::<(models::FeedId, String)>(&mut conn)
+			.optional()
+			.wrap_err("could not retrieve feed")?
+	};
+	let (feed_id, source) =
+		feed.ok_or(RouteError::NotFound("the current user has no such feed"))?;
+	if source == "newsletter" {
+		return Err(RouteError::User("newsletters are received by mail"));
+	}
+
+	let fetch_log = ressources
+		.fetcher_handle
+		.refresh(feed_id, query.wait)
+		.await
+		.wrap_err("could not refresh feed")?;
+
+	let status = if fetch_log.is_some() {
+		StatusCode::OK
+	} else {
+		StatusCode::ACCEPTED
+	};
+	Ok((status, Json(FeedRefreshResponse { fetch_log })).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+struct FeedPatchRequest {
+	full_text: Option<bool>,
+	unread_on_update: Option<bool>,
+}
+
+// Update the settings of a subscription
+async fn feed_patch_handler(
+	auth: ApiSession,
+	ressources: RessourcesRef,
+	Path(id): Path<UserFeedId>,
+	Form(query): Form<FeedPatchRequest>,
+) -> RouteResult<StatusCode> {
+	use crate::database::schema::*;
+
+	let user_id = auth.user_id()?;
+
+	let mut conn = ressources.database_handle.get()?;
+	let transaction = conn.transaction::<_, diesel::result::Error, _>(|conn| {
+		let user_feed = user_feed::table
+			.filter(user_feed::id.eq(id))
+			.filter(user_feed::user_id.eq(user_id));
+
+		let Some(feed_id) = user_feed
+			.select(user_feed::feed_id)
+			.get_result::<models::FeedId>(conn)
+			.optional()?
+		else {
+			return Ok(None);
+		};
+
+		if let Some(full_text) = query.full_text {
+			dsl::update(user_feed)
+				.set(user_feed::full_text.eq(full_text))
+				.execute(conn)?;
+
+			// articles of existing entries are downloaded after the next fetch
+			if full_text {
+				fetcher::enqueue([feed_id], Priority::User, conn)?;
+			}
+		}
+
+		if let Some(unread_on_update) = query.unread_on_update {
+			dsl::update(user_feed)
+				.set(user_feed::unread_on_update.eq(unread_on_update))
+				.execute(conn)?;
+		}
+
+		Ok(Some(feed_id))
+	});
+
+	transaction
+		.wrap_err("could not update feed")?
+		.ok_or(RouteError::NotFound("the current user has no such feed"))?;
+	ressources.fetcher_handle.wake();
+
+	Ok(StatusCode::OK)
+}
+
+#[derive(Debug, Serialize)]
+struct FeedRequestGetResponse {
+	headers: serde_json::Value,
+	user_agent: Option<String>,
+	/// Kind of the stored credentials, secrets are never sent back
+	credentials: Option<&'static str>,
+	accept_invalid_certs: bool,
+	/// Whether the feed is fetched for the current user alone
+	private: bool,
+}
+
+// Retrieve the settings of the requests fetching a feed
+async fn feed_request_get_handler(
+	auth: ApiSession,
+	ressources: RessourcesRef,
+	Path(id): Path<UserFeedId>,
+) -> RouteResult<Json<FeedRequestGetResponse>> {
+	use crate::database::schema::*;
+
+	let user_id = auth.user_id()?;
+
+	let mut conn = ressources.database_handle.get()?;
+	let (options, owner_id) = user_feed::table
+		.inner_join(feed::table)
+		.filter(user_feed::id.eq(id))
+		.filter(user_feed::user_id.eq(user_id))
+		.select((FeedRequestOptions::as_select(), feed::owner_id))
+		.get_result::<(FeedRequestOptions, Option<models::UserId>)>(&mut conn)
+		.optional()
+		.wrap_err("could not retrieve feed request settings")?
+		.ok_or(RouteError::NotFound("the current user has no such feed"))?;
+
+	let credentials = options.credentials.as_deref().map(|encrypted| {
+		ressources
+			.fetcher_handle
+			.decrypt_credentials(encrypted)
+			.map_or("unreadable", |credentials| credentials.kind())
+	});
+
+	Ok(Json(FeedRequestGetResponse {
+		headers: options
+			.request_headers
+			.unwrap_or_else(|| serde_json::Value::Object(serde_json::Map::new())),
+		user_agent: options.user_agent,
+		credentials,
+		accept_invalid_certs: options.accept_invalid_certs,
+		private: owner_id.is_some(),
+	}))
+}
+
+#[derive(Debug, Deserialize)]
+struct FeedRequestPutRequest {
+	#[serde(default)]
+	headers: BTreeMap<String, String>,
+	user_agent: Option<String>,
+	credentials: Option<Credentials>,
+	/// Only for hosts serving a self-signed certificate
+	#[serde(default)]
+	accept_invalid_certs: bool,
+}
+
+// Replace the settings of the requests fetching a feed
+//
+// Customized feeds are not shared with other subscribers anymore, the user gets their own copy of
+// the feed. It stays private once the settings are cleared.
+async fn feed_request_put_handler(
+	auth: ApiSession,
+	ressources: RessourcesRef,
+	Path(id): Path<UserFeedId>,
+	Json(query): Json<FeedRequestPutRequest>,
+) -> RouteResult<StatusCode> {
+	use crate::database::schema::*;
+
+	let user_id = auth.user_id()?;
+
+	for (name, value) in &query.headers {
+		fetcher::validate_header(name, value).map_err(RouteError::User)?;
+	}
+	let user_agent = query
+		.user_agent
+		.filter(|user_agent| !user_agent.trim().is_empty());
+	if user_agent
+		.as_deref()
+		.is_some_and(|user_agent| HeaderValue::try_from(user_agent).is_err())
+	{
+		return Err(RouteError::User("invalid user agent"));
+	}
+
+	let credentials = query
+		.credentials
+		.as_ref()
+		.map(|credentials| ressources.fetcher_handle.encrypt_credentials(credentials))
+		.transpose()?;
+
+	let options = FeedRequestOptions {
+		request_headers: (!query.headers.is_empty())
+			.then(|| serde_json::to_value(&query.headers))
+			.transpose()
+			.wrap_err("could not serialize headers")?,
+		user_agent,
+		credentials,
+		accept_invalid_certs: query.accept_invalid_certs,
+	};
+	let customized = options.request_headers.is_some()
+		|| options.user_agent.is_some()
+		|| options.credentials.is_some()
+		|| options.accept_invalid_certs;
+
+	let mut conn = ressources.database_handle.get()?;
+	let transaction = conn.transaction::<_, diesel::result::Error, _>(|conn| {
+		let Some((feed_id, owner_id)) = user_feed::table
+			.inner_join(feed::table)
+			.filter(user_feed::id.eq(id))
+			.filter(user_feed::user_id.eq(user_id))
+			.select((feed::id, feed::owner_id))
+			.get_result::<(models::FeedId, Option<models::UserId>)>(conn)
+			.optional()?
+		else {
+			return Ok(None);
+		};
+
+		// shared feeds only ever use the default settings
+		if owner_id.is_none() && !customized {
+			return Ok(Some(()));
+		}
+
+		let feed_id = Feed::make_private(user_id, id, conn)?.unwrap_or(feed_id);
+		dsl::update(feed::table.find(feed_id))
+			.set(&options)
+			.execute(conn)?;
+		fetcher::enqueue([feed_id], Priority::User, conn)?;
+
+		Ok(Some(()))
+	});
+
+	transaction
+		.wrap_err("could not update feed request settings")?
+		.ok_or(RouteError::NotFound("the current user has no such feed"))?;
+	ressources.fetcher_handle.wake();
+
+	Ok(StatusCode::OK)
+}
+
 #[derive(Debug, Deserialize)]
 struct FeedsPostRequest<'a> {
-	title: Cow<'a, str>,
+	/// Defaults to the title of the feed
+	title: Option<Cow<'a, str>>,
 	description: Option<Cow<'a, str>>,
 	url: Cow<'a, str>,
+	/// Download the articles linked by entries
+	#[serde(default)]
+	full_text: bool,
+	/// Show entries edited by the publisher as unread again
+	#[serde(default)]
+	unread_on_update: bool,
+}
+
+/// Blank titles and descriptions fall back to the ones of the feed
+fn non_blank(value: Option<Cow<'_, str>>) -> Option<Cow<'_, str>> {
+	value.filter(|value| !value.trim().is_empty())
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct FeedsPostCandidatesResponse {
+	candidates: Vec<Candidate>,
 }
 
 // Create new feed entries
+//
+// The url can point to a web page, its feed is subscribed to if there is only one. Otherwise the
+// candidates are returned with `300 Multiple Choices` for the user to pick.
 async fn feeds_post_handler(
 	auth: ApiSession,
 	ressources: RessourcesRef,
 	Form(query): Form<FeedsPostRequest<'_>>,
-) -> RouteResult<StatusCode> {
+) -> RouteResult<Response> {
 	let user_id = auth.user_id()?;
 
 	let FeedsPostRequest {
 		title,
 		description,
 		url,
+		full_text,
+		unread_on_update,
 	} = query;
+	let title = non_blank(title);
+	let description = non_blank(description);
 
 	let url = Url::parse(&url).map_err(|_| RouteError::User("url is not valid"))?;
 
-	// TODO: assert url scheme is https (allow http?)
+	let url = match ressources.fetcher_handle.discover(&url).await {
+		Ok(candidates) => match <[_; 1]>::try_from(candidates) {
+			Ok([candidate]) => candidate.url,
+			Err(candidates) if candidates.is_empty() => {
+				return Err(RouteError::User("no feed was found at this url"));
+			}
+			Err(candidates) => {
+				let response = FeedsPostCandidatesResponse { candidates };
+				return Ok((StatusCode::MULTIPLE_CHOICES, Json(response)).into_response());
+			}
+		},
+		Err(err) if err.kind() == ErrorKind::Blocked => {
+			return Err(RouteError::User("url is not allowed"));
+		}
+		// the feed may be temporarily down, failures are reported through its health
+		Err(err) => {
+			tracing::debug!(err = %err, url = %url, "could not discover feeds, subscribing as is");
+			url
+		}
+	};
 
 	let mut conn = ressources.database_handle.get()?;
 
@@ -94,32 +426,159 @@ async fn feeds_post_handler(
 			folder_id: None,
 			title,
 			description,
+			full_text,
+			unread_on_update,
 		}
 		.insert_into(crate::database::schema::user_feed::table)
 		.returning(crate::database::schema::user_feed::id);
 
 		let user_feed_id = match stmt.get_result::<UserFeedId>(conn) {
-			Ok(id) => Some(id),
-			Err(Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => None,
+			Ok(id) => id,
+			Err(Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => return Ok(None),
 			Err(err) => return Err(err),
 		};
 
-		Ok((feed_id, user_feed_id))
-	});
+		fetcher::enqueue([feed_id], Priority::User, conn)?;
 
-	let (feed_id, user_feed_id) = transaction.unwrap();
+		Ok(Some(user_feed_id))
+	});
 
-	ressources.fetch_url(feed_id, url).await.unwrap();
+	let user_feed_id = transaction.wrap_err("could not subscribe to feed")?;
+	ressources.fetcher_handle.wake();
 
 	user_feed_id.map_or(
 		Err(RouteError::User("the current user already has such a feed")),
 		|_id| {
 			// TODO: return id?
-			Ok(StatusCode::OK)
+			Ok(StatusCode::OK.into_response())
 		},
 	)
 }
 
+#[derive(Debug, Deserialize)]
+struct ScrapedPostRequest<'a> {
+	/// Defaults to the title of the page
+	title: Option<Cow<'a, str>>,
+	description: Option<Cow<'a, str>>,
+	url: Cow<'a, str>,
+	rules: ScrapeRules,
+	#[serde(default)]
+	full_text: bool,
+	#[serde(default)]
+	unread_on_update: bool,
+}
+
+// Subscribe to a web page without feed, its entries are found with css selectors
+//
+// Selectors can be tried beforehand with `POST /api/v0/feeds/scrape`.
+async fn scraped_post_handler(
+	auth: ApiSession,
+	ressources: RessourcesRef,
+	Json(query): Json<ScrapedPostRequest<'_>>,
+) -> RouteResult<StatusCode> {
+	let user_id = auth.user_id()?;
+
+	let ScrapedPostRequest {
+		title,
+		description,
+		url,
+		rules,
+		full_text,
+		unread_on_update,
+	} = query;
+	let title = non_blank(title);
+	let description = non_blank(description);
+
+	let url = Url::parse(&url).map_err(|_| RouteError::User("url is not valid"))?;
+	rules.validate().map_err(RouteError::User)?;
+	let rules = serde_json::to_value(&rules).wrap_err("could not serialize scrape rules")?;
+
+	let mut conn = ressources.database_handle.get()?;
+	let transaction = conn.transaction::<_, diesel::result::Error, _>(|conn| {
+		let feed_id = match Feed::create_scraped(user_id, &url, &rules, conn) {
+			Ok(feed_id) => feed_id,
+			Err(Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => return Ok(false),
+			Err(err) => return Err(err),
+		};
+
+		NewUserFeed {
+			user_id,
+			feed_id,
+			folder_id: None,
+			title,
+			description,
+			full_text,
+			unread_on_update,
+		}
+		.insert_into(crate::database::schema::user_feed::table)
+		.execute(conn)?;
+
+		fetcher::enqueue([feed_id], Priority::User, conn)?;
+
+		Ok(true)
+	});
+
+	let created = transaction.wrap_err("could not subscribe to scraped feed")?;
+	if !created {
+		return Err(RouteError::User("the current user already has such a feed"));
+	}
+	ressources.fetcher_handle.wake();
+
+	Ok(StatusCode::OK)
+}
+
+// Replace the css selectors of a scraped feed
+async fn feed_scrape_put_handler(
+	auth: ApiSession,
+	ressources: RessourcesRef,
+	Path(id): Path<UserFeedId>,
+	Json(rules): Json<ScrapeRules>,
+) -> RouteResult<StatusCode> {
+	use crate::database::schema::*;
+
+	let user_id = auth.user_id()?;
+
+	rules.validate().map_err(RouteError::User)?;
+	let rules = serde_json::to_value(&rules).wrap_err("could not serialize scrape rules")?;
+
+	let mut conn = ressources.database_handle.get()?;
+	let transaction = conn.transaction::<_, diesel::result::Error, _>(|conn| {
+		let Some(feed_id) = user_feed::table
+			.inner_join(feed::table)
+			.filter(user_feed::id.eq(id))
+			.filter(user_feed::user_id.eq(user_id))
+			.filter(feed::source.eq("scraped"))
+			.select(feed::id)
+			.get_result::<models::FeedId>(conn)
+			.optional()?
+		else {
+			return Ok(None);
+		};
+
+		// the page did not change, it has to be scraped again all the same
+		dsl::update(feed::table.find(feed_id))
+			.set((
+				feed::scrape_rules.eq(&rules),
+				feed::etag.eq(None::<String>),
+				feed::last_modified.eq(None::<String>),
+				feed::content_hash.eq(None::<String>),
+			))
+			.execute(conn)?;
+		fetcher::enqueue([feed_id], Priority::User, conn)?;
+
+		Ok(Some(()))
+	});
+
+	transaction
+		.wrap_err("could not update scrape rules")?
+		.ok_or(RouteError::NotFound(
+			"the current user has no such scraped feed",
+		))?;
+	ressources.fetcher_handle.wake();
+
+	Ok(StatusCode::OK)
+}
+
 #[derive(Debug, Deserialize)]
 struct FeedsDeleteRequest {
 	id: UserFeedId,
@@ -160,10 +619,10 @@ async fn import_post_handler(
 		folders.extend(file_folders);
 	}
 
-	let mut to_fetch = Vec::new();
-
 	let mut conn = ressources.database_handle.get()?;
 	conn.transaction::<(), diesel::result::Error, _>(|conn| {
+		let mut to_fetch = Vec::new();
+
 		for (folder_name, feeds) in folders {
 			let folder_id = UserFeedFolder::resolve_or_create(user_id, &folder_name, conn)?;
 
@@ -173,12 +632,14 @@ async fn import_post_handler(
 						user_id,
 						feed_id,
 						folder_id: Some(folder_id),
-						title: feed.title.into(),
+						title: non_blank(Some(feed.title.into())),
 						description: None,
+						full_text: false,
+						unread_on_update: false,
 					}
 				})?;
 
-				to_fetch.push((new_feed.feed_id, feed.url));
+				to_fetch.push(new_feed.feed_id);
 
 				// TODO: do not crash on unique violation
 				dsl::insert_into(crate::database::schema::user_feed::table)
@@ -186,16 +647,15 @@ async fn import_post_handler(
 					.execute(conn)?;
 			}
 		}
+
+		// large imports are queued with background polls to not delay other subscriptions
+		fetcher::enqueue(to_fetch, Priority::Background, conn)?;
+
 		Ok(())
 	})
 	.wrap_err("failed to register bulk feeds from opml file")?;
 
-	for (feed_id, url) in to_fetch {
-		ressources
-			.fetch_url(feed_id, url)
-			.await
-			.wrap_err("failed to put feed in fetcher queue")?;
-	}
+	ressources.fetcher_handle.wake();
 
 	Ok(StatusCode::OK)
 }
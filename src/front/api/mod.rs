@@ -5,8 +5,10 @@ use crate::{
 	front::auth::ApiAuthnLayer,
 };
 
+mod admin;
 mod entries;
 mod feeds;
+mod remote_feeds;
 
 pub fn router(ressources: &Ressources) -> Router<RessourcesRef> {
 	let api_auth_layer = ApiAuthnLayer::new(ressources);
@@ -20,4 +22,6 @@ pub fn nightly_api_router() -> Router<RessourcesRef> {
 	Router::new()
 		.nest("/user/feeds", feeds::router())
 		.nest("/user/entries", entries::router())
+		.nest("/feeds", remote_feeds::router())
+		.nest("/admin", admin::router())
 }
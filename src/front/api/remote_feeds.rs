@@ -0,0 +1,129 @@
+use axum::{
+	Form, Json, Router,
+	extract::Query,
+	routing::{get, post},
+};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{
+	config::RessourcesRef,
+	fetcher::{self, Candidate, ErrorKind, Preview, ScrapeRules, ScrapedItem},
+	front::{
+		auth::ApiSession,
+		error::{RouteError, RouteResult},
+	},
+};
+
+pub fn router() -> Router<RessourcesRef> {
+	Router::new()
+		.route("/discover", get(discover_get_handler))
+		.route("/scrape", post(scrape_post_handler))
+		.route("/preview", post(preview_post_handler))
+}
+
+/// Entries listed by a preview when not specified
+const PREVIEW_ENTRIES: usize = 10;
+const PREVIEW_MAX_ENTRIES: usize = 50;
+
+#[derive(Debug, Deserialize)]
+struct DiscoverGetRequest {
+	url: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct DiscoverGetResponse {
+	candidates: Vec<Candidate>,
+}
+
+// List the feeds found behind an url, either a feed itself or a web page
+async fn discover_get_handler(
+	auth: ApiSession,
+	ressources: RessourcesRef,
+	Query(query): Query<DiscoverGetRequest>,
+) -> RouteResult<Json<DiscoverGetResponse>> {
+	auth.user_id()?;
+
+	let url = Url::parse(&query.url).map_err(|_| RouteError::User("url is not valid"))?;
+
+	let candidates = ressources
+		.fetcher_handle
+		.discover(&url)
+		.await
+		.map_err(|err| RouteError::UserOpaque("could not reach url", err.into()))?;
+
+	Ok(Json(DiscoverGetResponse { candidates }))
+}
+
+#[derive(Debug, Deserialize)]
+struct ScrapePostRequest {
+	url: String,
+	rules: ScrapeRules,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct ScrapePostResponse {
+	entries: Vec<ScrapedItem>,
+}
+
+// Run css selectors on a page once and return the entries they would create, nothing is stored
+async fn scrape_post_handler(
+	auth: ApiSession,
+	ressources: RessourcesRef,
+	Json(query): Json<ScrapePostRequest>,
+) -> RouteResult<Json<ScrapePostResponse>> {
+	auth.user_id()?;
+
+	let url = Url::parse(&query.url).map_err(|_| RouteError::User("url is not valid"))?;
+	query.rules.validate().map_err(RouteError::User)?;
+
+	let entries = match ressources
+		.fetcher_handle
+		.preview_scrape(&url, &query.rules)
+		.await
+	{
+		Ok(entries) => entries,
+		// selectors are being tuned, tell what went wrong with them
+		Err(fetcher::Error::Scrape(reason)) => return Err(RouteError::User(reason)),
+		Err(err) => return Err(RouteError::UserOpaque("could not scrape url", err.into())),
+	};
+
+	Ok(Json(ScrapePostResponse { entries }))
+}
+
+#[derive(Debug, Deserialize)]
+struct PreviewPostRequest {
+	url: String,
+	/// Number of entries listed
+	limit: Option<usize>,
+}
+
+// Fetch and parse a feed like a subscription would and return what would be stored, nothing is
+// stored
+async fn preview_post_handler(
+	auth: ApiSession,
+	ressources: RessourcesRef,
+	Form(query): Form<PreviewPostRequest>,
+) -> RouteResult<Json<Preview>> {
+	auth.user_id()?;
+
+	let url = Url::parse(&query.url).map_err(|_| RouteError::User("url is not valid"))?;
+	let limit = query
+		.limit
+		.unwrap_or(PREVIEW_ENTRIES)
+		.min(PREVIEW_MAX_ENTRIES);
+
+	let preview = match ressources.fetcher_handle.preview(&url, limit).await {
+		Ok(preview) => preview,
+		Err(err) if err.kind() == ErrorKind::Blocked => {
+			return Err(RouteError::User("url is not allowed"));
+		}
+		// pages link to their feeds, `/discover` finds them
+		Err(err) if err.kind() == ErrorKind::Parse => {
+			return Err(RouteError::UserOpaque("url is not a feed", err.into()));
+		}
+		Err(err) => return Err(RouteError::UserOpaque("could not reach url", err.into())),
+	};
+
+	Ok(Json(preview))
+}
@@ -26,6 +26,9 @@ pub enum RouteError {
 	#[error("{0}")]
 	User(&'static str),
 
+	#[error("not found: {0}")]
+	NotFound(&'static str),
+
 	#[error("user opaque {0}: {1}")]
 	UserOpaque(&'static str, eyre::Report),
 }
@@ -48,6 +51,7 @@ impl IntoResponse for RouteError {
 			}
 			Self::Auth(err) => err.into_response(),
 			Self::User(msg) => (StatusCode::BAD_REQUEST, msg).into_response(),
+			Self::NotFound(msg) => (StatusCode::NOT_FOUND, msg).into_response(),
 			Self::UserOpaque(msg, err) => {
 				tracing::error!(
 					err = %err,
@@ -64,6 +68,9 @@ pub enum AuthError {
 	#[error("user is not authenticated")]
 	NotAuthenticated,
 
+	#[error("user is not an admin")]
+	NotAdmin,
+
 	#[error("session: {0}")]
 	Session(#[from] tower_sessions_core::session::Error),
 
@@ -80,6 +87,7 @@ impl IntoResponse for AuthError {
 			err @ Self::NotAuthenticated => {
 				(StatusCode::UNAUTHORIZED, err.to_string()).into_response()
 			}
+			err @ Self::NotAdmin => (StatusCode::FORBIDDEN, err.to_string()).into_response(),
 			err @ (Self::DbPool(_) | Self::Session(_) | Self::Other(_)) => {
 				tracing::error!(err = %err, "error at auth boundary");
 				StatusCode::INTERNAL_SERVER_ERROR.into_response()
@@ -0,0 +1,44 @@
+use axum::{
+	Router,
+	extract::Path,
+	http::header,
+	response::{IntoResponse, Response},
+	routing::get,
+};
+
+use crate::{
+	config::RessourcesRef,
+	database::models::Icon,
+	front::error::{RouteError, RouteResult},
+};
+
+/// Icons of feeds, named after their content so they can be cached forever
+pub fn router() -> Router<RessourcesRef> {
+	Router::new().route("/{hash}", get(icon_get_handler))
+}
+
+async fn icon_get_handler(
+	ressources: RessourcesRef,
+	Path(hash): Path<String>,
+) -> RouteResult<Response> {
+	let mut conn = ressources.database_handle.get()?;
+	let icon = Icon::by_hash(&hash, &mut conn)
+		.map_err(|err| RouteError::Other(err.into()))?
+		.ok_or(RouteError::NotFound("no such icon"))?;
+
+	let headers = [
+		(header::CONTENT_TYPE, icon.mime.into_owned()),
+		(
+			header::CACHE_CONTROL,
+			"public, max-age=31536000, immutable".to_owned(),
+		),
+		(header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_owned()),
+		// svg images can embed scripts
+		(
+			header::CONTENT_SECURITY_POLICY,
+			"default-src 'none'; style-src 'unsafe-inline'; sandbox".to_owned(),
+		),
+	];
+
+	Ok((headers, icon.content).into_response())
+}
@@ -24,7 +24,10 @@ use crate::front::auth::{Backend, SqliteStore};
 mod api;
 mod auth;
 mod error;
+mod icons;
+mod proxy;
 mod web;
+mod websub;
 
 pub struct App {
 	config: Config,
@@ -59,7 +62,10 @@ impl App {
 
 		let app = Router::new()
 			.merge(web::router())
-			.nest("/api", api::router(&self.ressources));
+			.nest("/api", api::router(&self.ressources))
+			.nest("/websub", websub::router())
+			.nest("/proxy", proxy::router())
+			.nest("/icons", icons::router());
 
 		let x_request_id = HeaderName::from_static("x-request-id");
 		let headers: Arc<[_]> =
@@ -0,0 +1,49 @@
+use axum::{
+	Router,
+	extract::Path,
+	http::header,
+	response::{IntoResponse, Response},
+	routing::get,
+};
+
+use crate::{
+	config::RessourcesRef,
+	front::error::{RouteError, RouteResult},
+};
+
+/// Media of entry contents served from the instance, reachable without authentication as images
+/// are loaded without credentials
+pub fn router() -> Router<RessourcesRef> {
+	Router::new().route("/{signed}", get(media_get_handler))
+}
+
+async fn media_get_handler(
+	ressources: RessourcesRef,
+	Path(signed): Path<String>,
+) -> RouteResult<Response> {
+	let media = ressources
+		.fetcher_handle
+		.proxy_media(&signed)
+		.await
+		.unwrap_or_else(|err| {
+			tracing::debug!(err = %err, "could not proxy media");
+			None
+		})
+		.ok_or(RouteError::NotFound("no such media"))?;
+
+	let headers = [
+		(header::CONTENT_TYPE, media.mime),
+		(
+			header::CACHE_CONTROL,
+			format!("public, max-age={}", media.max_age.as_secs()),
+		),
+		(header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_owned()),
+		// svg images can embed scripts
+		(
+			header::CONTENT_SECURITY_POLICY,
+			"default-src 'none'; style-src 'unsafe-inline'; sandbox".to_owned(),
+		),
+	];
+
+	Ok((headers, media.body).into_response())
+}
@@ -16,7 +16,7 @@ use tower_http::services::{ServeDir, ServeFile};
 
 use crate::{
 	config::RessourcesRef,
-	database::{ResolvedUserEntry, ResolvedUserFeed},
+	database::{ResolvedUserEntry, ResolvedUserFeed, models::User},
 	front::{
 		auth::{AuthSession, Backend, LoginCredentials, UserSession, is_safe_relative_path},
 		error::RouteResult,
@@ -78,8 +78,24 @@ async fn root_get_handler(
 	Ok(Template::render(&tpl))
 }
 
-async fn profile_get_handler(UserSession(user): UserSession) -> RouteResult<Template> {
-	let tpl = templates::Profile { user: Some(&user) };
+async fn profile_get_handler(
+	UserSession(user): UserSession,
+	ressources: RessourcesRef,
+) -> RouteResult<Template> {
+	let newsletter_address = match &ressources.newsletter_domain {
+		Some(domain) => {
+			let mut conn = ressources.database_handle.get()?;
+			let token = User::newsletter_token(user.id, &mut conn)
+				.wrap_err("could not retrieve newsletter address")?;
+			Some(format!("{token}@{domain}"))
+		}
+		None => None,
+	};
+
+	let tpl = templates::Profile {
+		user: Some(&user),
+		newsletter_address,
+	};
 	Ok(Template::render(&tpl))
 }
 
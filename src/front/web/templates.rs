@@ -41,6 +41,9 @@ pub struct Index<'a> {
 #[template(path = "profile.html")]
 pub struct Profile<'a> {
 	pub user: Option<&'a User>,
+
+	/// Address to subscribe to newsletters with, when they are received
+	pub newsletter_address: Option<String>,
 }
 
 #[derive(askama::Template)]
@@ -0,0 +1,58 @@
+use axum::{
+	Router,
+	body::Bytes,
+	extract::{Path, Query},
+	http::HeaderMap,
+	routing::get,
+};
+
+use crate::{
+	config::RessourcesRef,
+	database::models::WebSubSubscriptionId,
+	fetcher::Intent,
+	front::error::{RouteError, RouteResult},
+};
+
+/// Callbacks given to websub hubs, reachable without authentication
+pub fn router() -> Router<RessourcesRef> {
+	Router::new().route(
+		"/{id}/{token}",
+		get(intent_get_handler).post(content_post_handler),
+	)
+}
+
+// Hubs verify that subscription requests come from us by asking to echo a challenge
+async fn intent_get_handler(
+	ressources: RessourcesRef,
+	Path((id, token)): Path<(WebSubSubscriptionId, String)>,
+	Query(intent): Query<Intent>,
+) -> RouteResult<String> {
+	ressources
+		.fetcher_handle
+		.verify_websub_intent(id, &token, &intent)
+		.map_err(|err| RouteError::Other(err.into()))?
+		.ok_or(RouteError::NotFound("no matching subscription"))
+}
+
+// Hubs push the new content of subscribed feeds, signed with the subscription secret
+async fn content_post_handler(
+	ressources: RessourcesRef,
+	Path((id, token)): Path<(WebSubSubscriptionId, String)>,
+	headers: HeaderMap,
+	body: Bytes,
+) -> RouteResult<()> {
+	let signature = headers
+		.get("x-hub-signature")
+		.and_then(|signature| signature.to_str().ok());
+
+	let known = ressources
+		.fetcher_handle
+		.receive_websub_content(id, &token, signature, &body)
+		.map_err(|err| RouteError::Other(err.into()))?;
+
+	if known {
+		Ok(())
+	} else {
+		Err(RouteError::NotFound("no matching subscription"))
+	}
+}
@@ -10,6 +10,9 @@ mod config;
 mod database;
 mod fetcher;
 mod front;
+mod newsletter;
+mod retention;
+mod scheduler;
 mod utils;
 
 fn setup_tracing() {
@@ -0,0 +1,468 @@
+//! Just enough of internet messages to read newsletters: headers, mime parts and their encodings
+
+use std::borrow::Cow;
+
+use base64::{Engine, prelude::BASE64_STANDARD};
+use encoding_rs::{Encoding, UTF_8};
+use time::{OffsetDateTime, format_description::well_known::Rfc2822};
+
+/// Nested multiparts deeper than this are ignored
+const MAX_DEPTH: usize = 8;
+
+/// What a newsletter is made of once parsed
+#[derive(Debug, Default)]
+pub struct Message {
+	/// Address of the `From` header, in lowercase
+	pub from: Option<String>,
+	pub from_name: Option<String>,
+	pub subject: Option<String>,
+	/// Value of the `Message-ID` header, without its angle brackets
+	pub id: Option<String>,
+	pub date: Option<OffsetDateTime>,
+
+	pub html: Option<String>,
+	pub text: Option<String>,
+}
+
+impl Message {
+	pub fn parse(raw: &[u8]) -> Self {
+		let (headers, body) = split_headers(raw);
+
+		let mut message = Self::default();
+
+		if let Some(from) = header(&headers, "from") {
+			let (name, address) = parse_address(&decode_words(from));
+			message.from = address;
+			message.from_name = name;
+		}
+		message.subject = header(&headers, "subject")
+			.map(|subject| decode_words(subject).trim().to_owned())
+			.filter(|subject| !subject.is_empty());
+		message.id = header(&headers, "message-id")
+			.map(|id| {
+				id.trim()
+					.trim_start_matches('<')
+					.trim_end_matches('>')
+					.to_owned()
+			})
+			.filter(|id| !id.is_empty());
+		message.date = header(&headers, "date").and_then(parse_date);
+
+		message.read_part(&headers, body, 0);
+		message
+	}
+
+	/// Html content of the message, plain text is escaped and split into paragraphs
+	pub fn content(&self) -> String {
+		if let Some(html) = &self.html {
+			return html.clone();
+		}
+
+		let text = self
+			.text
+			.as_deref()
+			.unwrap_or_default()
+			.replace("\r\n", "\n");
+		let mut content = String::new();
+		for paragraph in text.split("\n\n").map(str::trim) {
+			if paragraph.is_empty() {
+				continue;
+			}
+
+			let paragraph = paragraph
+				.replace('&', "&amp;")
+				.replace('<', "&lt;")
+				.replace('>', "&gt;")
+				.replace('\n', "<br>");
+			content.push_str("<p>");
+			content.push_str(&paragraph);
+			content.push_str("</p>");
+		}
+
+		content
+	}
+
+	/// Keeps the first html and plain text parts that are not attachments
+	fn read_part(&mut self, headers: &[(String, String)], body: &[u8], depth: usize) {
+		let (mime, params) = header(headers, "content-type")
+			.map_or_else(|| ("text/plain".to_owned(), Vec::new()), parse_content_type);
+
+		if header(headers, "content-disposition").is_some_and(|disposition| {
+			disposition
+				.trim()
+				.to_ascii_lowercase()
+				.starts_with("attachment")
+		}) {
+			return;
+		}
+
+		if mime.starts_with("multipart/") {
+			let Some(boundary) = param(&params, "boundary") else {
+				return;
+			};
+			if depth >= MAX_DEPTH {
+				return;
+			}
+
+			for part in split_multipart(body, boundary) {
+				let (headers, body) = split_headers(part);
+				self.read_part(&headers, body, depth + 1);
+			}
+			return;
+		}
+
+		let slot = match mime.as_str() {
+			"text/html" => &mut self.html,
+			"text/plain" => &mut self.text,
+			_ => return,
+		};
+		if slot.is_some() {
+			return;
+		}
+
+		let encoding = header(headers, "content-transfer-encoding")
+			.map(|encoding| encoding.trim().to_ascii_lowercase());
+		let decoded = match encoding.as_deref() {
+			Some("base64") => decode_base64(body),
+			Some("quoted-printable") => decode_quoted_printable(body),
+			_ => body.to_vec(),
+		};
+
+		*slot = Some(decode_charset(&decoded, param(&params, "charset")));
+	}
+}
+
+/// Unfolded headers, with lowercase names, and the body following them
+fn split_headers(raw: &[u8]) -> (Vec<(String, String)>, &[u8]) {
+	let (head, body) = find(raw, b"\r\n\r\n")
+		.map(|index| (&raw[..index], &raw[index + 4..]))
+		.or_else(|| find(raw, b"\n\n").map(|index| (&raw[..index], &raw[index + 2..])))
+		.unwrap_or((raw, &[]));
+
+	let mut headers = Vec::<(String, String)>::new();
+	for line in String::from_utf8_lossy(head).lines() {
+		// folded lines continue the previous header
+		if line.starts_with([' ', '\t']) {
+			if let Some((_, value)) = headers.last_mut() {
+				value.push(' ');
+				value.push_str(line.trim());
+			}
+			continue;
+		}
+
+		if let Some((name, value)) = line.split_once(':') {
+			headers.push((name.trim().to_ascii_lowercase(), value.trim().to_owned()));
+		}
+	}
+
+	(headers, body)
+}
+
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+	headers
+		.iter()
+		.find(|(header, _)| header == name)
+		.map(|(_, value)| value.as_str())
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+	haystack
+		.windows(needle.len())
+		.position(|window| window == needle)
+}
+
+/// Lowercase mime type and parameters of a `Content-Type` header
+fn parse_content_type(value: &str) -> (String, Vec<(String, String)>) {
+	let mut fields = value.split(';');
+	let mime = fields
+		.next()
+		.unwrap_or_default()
+		.trim()
+		.to_ascii_lowercase();
+
+	let params = fields
+		.filter_map(|field| {
+			let (name, value) = field.split_once('=')?;
+			Some((
+				name.trim().to_ascii_lowercase(),
+				value.trim().trim_matches('"').to_owned(),
+			))
+		})
+		.collect();
+
+	(mime, params)
+}
+
+fn param<'a>(params: &'a [(String, String)], name: &str) -> Option<&'a str> {
+	params
+		.iter()
+		.find(|(param, _)| param == name)
+		.map(|(_, value)| value.as_str())
+}
+
+/// Parts between the delimiters of a multipart body, the preamble and epilogue are dropped
+fn split_multipart<'a>(body: &'a [u8], boundary: &str) -> Vec<&'a [u8]> {
+	let delimiter = format!("--{boundary}");
+
+	let mut parts = Vec::new();
+	let mut start = None;
+	let mut offset = 0;
+	for line in body.split_inclusive(|byte| *byte == b'\n') {
+		let suffix = line.trim_ascii_end().strip_prefix(delimiter.as_bytes());
+		if let Some(suffix @ (b"" | b"--")) = suffix {
+			if let Some(start) = start {
+				// the line break preceding a delimiter belongs to it
+				let part: &[u8] = &body[start..offset];
+				let part = part.strip_suffix(b"\n").unwrap_or(part);
+				parts.push(part.strip_suffix(b"\r").unwrap_or(part));
+			}
+			if suffix == b"--" {
+				break;
+			}
+			start = Some(offset + line.len());
+		}
+		offset += line.len();
+	}
+
+	parts
+}
+
+fn decode_base64(body: &[u8]) -> Vec<u8> {
+	let compact = body
+		.iter()
+		.copied()
+		.filter(|byte| !byte.is_ascii_whitespace())
+		.collect::<Vec<_>>();
+	BASE64_STANDARD.decode(compact).unwrap_or_default()
+}
+
+fn decode_quoted_printable(body: &[u8]) -> Vec<u8> {
+	let hex = |byte: u8| match byte {
+		b'0'..=b'9' => byte - b'0',
+		b'a'..=b'f' => byte - b'a' + 10,
+		_ => byte.to_ascii_uppercase() - b'A' + 10,
+	};
+
+	let mut decoded = Vec::with_capacity(body.len());
+	let mut index = 0;
+	while index < body.len() {
+		let byte = body[index];
+		if byte != b'=' {
+			decoded.push(byte);
+			index += 1;
+			continue;
+		}
+
+		match (body.get(index + 1), body.get(index + 2)) {
+			// soft line breaks join lines
+			(Some(b'\r'), Some(b'\n')) => index += 3,
+			(Some(b'\n'), _) => index += 2,
+			(Some(&high), Some(&low)) if high.is_ascii_hexdigit() && low.is_ascii_hexdigit() => {
+				decoded.push(hex(high) * 16 + hex(low));
+				index += 3;
+			}
+			_ => {
+				decoded.push(byte);
+				index += 1;
+			}
+		}
+	}
+
+	decoded
+}
+
+fn decode_charset(bytes: &[u8], charset: Option<&str>) -> String {
+	let encoding = charset
+		.and_then(|charset| Encoding::for_label(charset.as_bytes()))
+		.unwrap_or(UTF_8);
+	let (decoded, _, _) = encoding.decode(bytes);
+	decoded.into_owned()
+}
+
+/// Decodes the `=?charset?encoding?text?=` words of a header value
+fn decode_words(value: &str) -> Cow<'_, str> {
+	if !value.contains("=?") {
+		return Cow::Borrowed(value);
+	}
+
+	let mut decoded = String::new();
+	let mut rest = value;
+	let mut after_word = false;
+	while let Some(start) = rest.find("=?") {
+		let (before, word) = rest.split_at(start);
+
+		let Some((text, remaining)) = decode_word(word) else {
+			decoded.push_str(before);
+			decoded.push_str("=?");
+			rest = &word[2..];
+			after_word = false;
+			continue;
+		};
+
+		// whitespace between two encoded words is not part of the text
+		if !(after_word && before.trim().is_empty()) {
+			decoded.push_str(before);
+		}
+		decoded.push_str(&text);
+		rest = remaining;
+		after_word = true;
+	}
+	decoded.push_str(rest);
+
+	Cow::Owned(decoded)
+}
+
+/// Decodes the encoded word starting `word`, returns the text following it
+fn decode_word(word: &str) -> Option<(String, &str)> {
+	let inner = word.strip_prefix("=?")?;
+	let (charset, inner) = inner.split_once('?')?;
+	let (encoding, inner) = inner.split_once('?')?;
+	let (text, rest) = inner.split_once("?=")?;
+
+	let bytes = match encoding {
+		"B" | "b" => BASE64_STANDARD.decode(text).ok()?,
+		"Q" | "q" => decode_quoted_printable(text.replace('_', " ").as_bytes()),
+		_ => return None,
+	};
+
+	// charsets may carry a language, e.g. `utf-8*en`
+	let charset = charset.split('*').next().unwrap_or(charset);
+	Some((decode_charset(&bytes, Some(charset)), rest))
+}
+
+/// Display name and lowercase address of a `From` header
+fn parse_address(value: &str) -> (Option<String>, Option<String>) {
+	let value = value.trim();
+
+	let (name, address) = match (value.rfind('<'), value.rfind('>')) {
+		(Some(start), Some(end)) if start < end => (&value[..start], &value[start + 1..end]),
+		_ => ("", value),
+	};
+
+	let name = name.trim().trim_matches('"').trim();
+	let address = address.trim();
+
+	(
+		(!name.is_empty()).then(|| name.to_owned()),
+		address.contains('@').then(|| address.to_ascii_lowercase()),
+	)
+}
+
+fn parse_date(value: &str) -> Option<OffsetDateTime> {
+	// comments like `(UTC)` often follow the date
+	let value = value.split('(').next().unwrap_or(value).trim();
+	OffsetDateTime::parse(value, &Rfc2822).ok()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parses_multipart_alternative() {
+		let raw = b"From: \"The Weekly\" <Weekly@Example.com>\r\n\
+			Subject: Issue 12\r\n\
+			Message-ID: <12@example.com>\r\n\
+			Date: Sat, 06 Sep 2025 10:00:00 +0000 (UTC)\r\n\
+			Content-Type: multipart/alternative; boundary=\"sep\"\r\n\
+			\r\n\
+			preamble\r\n\
+			--sep\r\n\
+			Content-Type: text/plain; charset=utf-8\r\n\
+			\r\n\
+			plain\r\n\
+			--sep\r\n\
+			Content-Type: text/html; charset=utf-8\r\n\
+			\r\n\
+			<p>html</p>\r\n\
+			--sep--\r\n\
+			epilogue\r\n";
+
+		let message = Message::parse(raw);
+		assert_eq!(message.from.as_deref(), Some("weekly@example.com"));
+		assert_eq!(message.from_name.as_deref(), Some("The Weekly"));
+		assert_eq!(message.subject.as_deref(), Some("Issue 12"));
+		assert_eq!(message.id.as_deref(), Some("12@example.com"));
+		assert_eq!(
+			message.date.map(OffsetDateTime::unix_timestamp),
+			Some(1_757_152_800)
+		);
+		assert_eq!(message.text.as_deref(), Some("plain"));
+		assert_eq!(message.html.as_deref(), Some("<p>html</p>"));
+		assert_eq!(message.content(), "<p>html</p>");
+	}
+
+	#[test]
+	fn parses_nested_multipart() {
+		let raw = b"Content-Type: multipart/mixed; boundary=outer\n\
+			\n\
+			--outer\n\
+			Content-Type: text/html\n\
+			Content-Disposition: attachment; filename=\"invoice.html\"\n\
+			\n\
+			<p>attached</p>\n\
+			--outer\n\
+			Content-Type: multipart/alternative; boundary=inner\n\
+			\n\
+			--inner\n\
+			Content-Type: text/plain\n\
+			\n\
+			plain\n\
+			--inner\n\
+			Content-Type: text/html\n\
+			\n\
+			<p>inner</p>\n\
+			--inner--\n\
+			--outer--\n";
+
+		let message = Message::parse(raw);
+		assert_eq!(message.html.as_deref(), Some("<p>inner</p>"));
+		assert_eq!(message.text.as_deref(), Some("plain"));
+	}
+
+	#[test]
+	fn decodes_quoted_printable() {
+		let raw = b"Content-Type: text/plain; charset=utf-8\r\n\
+			Content-Transfer-Encoding: quoted-printable\r\n\
+			\r\n\
+			Caf=C3=A9 au lait, a long line that is =\r\n\
+			broken softly =3D kept\r\n";
+
+		let message = Message::parse(raw);
+		assert_eq!(
+			message.text.as_deref(),
+			Some("Café au lait, a long line that is broken softly = kept\r\n")
+		);
+	}
+
+	#[test]
+	fn decodes_base64() {
+		let raw = b"Content-Type: text/html; charset=iso-8859-1\r\n\
+			Content-Transfer-Encoding: base64\r\n\
+			\r\n\
+			PHA+Q2Fm6Twv\r\n\
+			cD4=\r\n";
+
+		let message = Message::parse(raw);
+		assert_eq!(message.html.as_deref(), Some("<p>Café</p>"));
+	}
+
+	#[test]
+	fn decodes_encoded_words() {
+		let raw = b"From: =?utf-8?Q?Caf=C3=A9_Weekly?= <news@example.com>\r\n\
+			Subject: =?utf-8?B?SGVsbG8=?=\r\n \
+			=?iso-8859-1?q?w=F6rld?= and more\r\n\
+			\r\n\
+			body";
+
+		let message = Message::parse(raw);
+		assert_eq!(message.from_name.as_deref(), Some("Café Weekly"));
+		assert_eq!(message.subject.as_deref(), Some("Hellowörld and more"));
+	}
+
+	#[test]
+	fn escapes_plain_text() {
+		let message = Message::parse(b"Subject: Plain\r\n\r\na < b\r\nc\r\n\r\n\r\nd & e");
+		assert_eq!(message.content(), "<p>a &lt; b<br>c</p><p>d &amp; e</p>");
+	}
+}
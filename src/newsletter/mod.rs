@@ -0,0 +1,604 @@
+//! Embedded mail server receiving the newsletters of users, see [`NewsletterConfig`]
+//!
+//! Each user is given an address, `<token>@<domain>`. Mails are stored as entries of a feed per
+//! sender, created on the first mail. The server speaks a minimal smtp or lmtp, without tls nor
+//! authentication, and is meant to sit behind the mail server of the domain.
+
+use std::{sync::Arc, time::Duration};
+
+use diesel::prelude::*;
+use eyre::{WrapErr, bail};
+use tokio::{
+	io::{AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+	net::{TcpListener, TcpStream},
+	sync::Semaphore,
+	task,
+	time::timeout,
+};
+
+use crate::{
+	config::{MailProtocol, NewsletterConfig},
+	database::{
+		PoolConnection,
+		models::{FeedId, UserId},
+	},
+	fetcher::{FetcherHandle, Letter},
+};
+
+use self::message::Message;
+
+mod message;
+
+/// Time a client can stay silent before the connection is closed
+const IDLE_TIMEOUT: Duration = Duration::from_mins(5);
+/// Longer command lines are refused, the rfc allows 512 bytes
+const MAX_COMMAND_LINE: u64 = 4096;
+/// Longer lines of a mail are cut, the rfc allows 1000 bytes
+const MAX_DATA_LINE: u64 = 64 * 1024;
+const MAX_RECIPIENTS: usize = 100;
+/// Further connections wait in the backlog of the listener
+const MAX_SESSIONS: usize = 64;
+
+#[derive(Debug)]
+pub struct Newsletters<M = Store> {
+	config: NewsletterConfig,
+	mailboxes: M,
+	sessions: Arc<Semaphore>,
+}
+
+/// Where received mails go
+pub trait Mailboxes: Send + Sync + 'static {
+	/// User owning the token of an address
+	fn recipient(&self, token: &str) -> eyre::Result<Option<UserId>>;
+	fn store(&self, user_id: UserId, letter: &Letter<'_>) -> eyre::Result<FeedId>;
+}
+
+/// Mailboxes of the users of the database
+#[derive(Debug)]
+pub struct Store {
+	db_pool: PoolConnection,
+	fetcher_handle: FetcherHandle,
+}
+
+/// Sender and recipients of the mail being received
+#[derive(Debug, Clone, Default)]
+struct Envelope {
+	from: Option<String>,
+	recipients: Vec<UserId>,
+}
+
+impl Newsletters {
+	/// Binds the mail server and spawns it
+	pub fn setup(
+		config: NewsletterConfig,
+		db_pool: PoolConnection,
+		fetcher_handle: FetcherHandle,
+	) -> eyre::Result<()> {
+		let listener = std::net::TcpListener::bind(config.listen)
+			.wrap_err("could not bind newsletter address")?;
+		listener.set_nonblocking(true)?;
+		let listener = TcpListener::from_std(listener)?;
+
+		let newsletters = Arc::new(Self::new(
+			config,
+			Store {
+				db_pool,
+				fetcher_handle,
+			},
+		));
+		task::spawn(newsletters.accept_loop(listener));
+
+		Ok(())
+	}
+}
+
+impl<M: Mailboxes> Newsletters<M> {
+	fn new(config: NewsletterConfig, mailboxes: M) -> Self {
+		Self {
+			config,
+			mailboxes,
+			sessions: Arc::new(Semaphore::new(MAX_SESSIONS)),
+		}
+	}
+
+	async fn accept_loop(self: Arc<Self>, listener: TcpListener) {
+		loop {
+			let permit = Arc::clone(&self.sessions)
+				.acquire_owned()
+				.await
+				.expect("session semaphore is never closed");
+
+			let (stream, peer) = match listener.accept().await {
+				Ok(accepted) => accepted,
+				Err(err) => {
+					tracing::warn!(err = %err, "could not accept mail connection");
+					continue;
+				}
+			};
+
+			let newsletters = Arc::clone(&self);
+			task::spawn(async move {
+				if let Err(err) = newsletters.session(stream).await {
+					tracing::debug!(peer = %peer, err = %err, "mail session ended with an error");
+				}
+				drop(permit);
+			});
+		}
+	}
+
+	async fn session(self: &Arc<Self>, stream: TcpStream) -> eyre::Result<()> {
+		let lmtp = self.config.protocol == MailProtocol::Lmtp;
+		let domain = &self.config.domain;
+
+		let (reader, mut writer) = stream.into_split();
+		let mut reader = BufReader::new(reader);
+
+		let banner = if lmtp { "LMTP" } else { "ESMTP" };
+		reply(&mut writer, &format!("220 {domain} feedr {banner} ready")).await?;
+
+		let mut envelope = Envelope::default();
+		loop {
+			let Some(line) = read_line(&mut reader, MAX_COMMAND_LINE).await? else {
+				return Ok(());
+			};
+			let line = String::from_utf8_lossy(&line);
+			let line = line.trim_end();
+			let (verb, args) = line.split_once(' ').unwrap_or((line, ""));
+
+			match (verb.to_ascii_uppercase().as_str(), lmtp) {
+				("EHLO", false) | ("LHLO", true) => {
+					envelope = Envelope::default();
+					let max_size = self.config.max_message_size;
+					reply(
+						&mut writer,
+						&format!("250-{domain}\r\n250-8BITMIME\r\n250 SIZE {max_size}"),
+					)
+					.await?;
+				}
+				("HELO", false) => {
+					envelope = Envelope::default();
+					reply(&mut writer, &format!("250 {domain}")).await?;
+				}
+				("MAIL", _) => {
+					let Some(from) = path_argument(args, "FROM:") else {
+						reply(&mut writer, "501 syntax: MAIL FROM:<address>").await?;
+						continue;
+					};
+					envelope = Envelope {
+						from: Some(from.to_ascii_lowercase()),
+						recipients: Vec::new(),
+					};
+					reply(&mut writer, "250 ok").await?;
+				}
+				("RCPT", _) => {
+					if envelope.from.is_none() {
+						reply(&mut writer, "503 need MAIL before RCPT").await?;
+						continue;
+					}
+					let Some(to) = path_argument(args, "TO:") else {
+						reply(&mut writer, "501 syntax: RCPT TO:<address>").await?;
+						continue;
+					};
+					if envelope.recipients.len() >= MAX_RECIPIENTS {
+						reply(&mut writer, "452 too many recipients").await?;
+						continue;
+					}
+
+					let newsletters = Arc::clone(self);
+					let to = to.to_owned();
+					let recipient = task::spawn_blocking(move || newsletters.recipient(&to))
+						.await
+						.wrap_err("recipient lookup panicked")??;
+					match recipient {
+						Some(user_id) => {
+							envelope.recipients.push(user_id);
+							reply(&mut writer, "250 ok").await?;
+						}
+						None => reply(&mut writer, "550 no such mailbox").await?,
+					}
+				}
+				("DATA", _) => {
+					if envelope.recipients.is_empty() {
+						reply(&mut writer, "503 need RCPT before DATA").await?;
+						continue;
+					}
+					self.receive(&mut reader, &mut writer, &envelope).await?;
+					envelope = Envelope::default();
+				}
+				("RSET", _) => {
+					envelope = Envelope::default();
+					reply(&mut writer, "250 ok").await?;
+				}
+				("NOOP", _) => reply(&mut writer, "250 ok").await?,
+				("VRFY", _) => reply(&mut writer, "252 cannot verify user").await?,
+				("QUIT", _) => {
+					reply(&mut writer, &format!("221 {domain} bye")).await?;
+					return Ok(());
+				}
+				_ => reply(&mut writer, "502 command not implemented").await?,
+			}
+		}
+	}
+
+	/// Reads a mail after the `DATA` command and answers once it is delivered
+	///
+	/// Mailboxes are synchronous, deliveries are kept off the async workers.
+	async fn receive(
+		self: &Arc<Self>,
+		reader: &mut BufReader<impl AsyncReadExt + Unpin>,
+		writer: &mut (impl AsyncWrite + Unpin),
+		envelope: &Envelope,
+	) -> eyre::Result<()> {
+		reply(writer, "354 end data with <CRLF>.<CRLF>").await?;
+
+		let replies = match self.read_data(reader).await? {
+			Some(data) => {
+				let newsletters = Arc::clone(self);
+				let envelope = envelope.clone();
+				task::spawn_blocking(move || newsletters.deliver(&envelope, &data))
+					.await
+					.wrap_err("delivery panicked")?
+			}
+			None => vec!["552 message exceeds the maximum size"; envelope.recipients.len()],
+		};
+
+		// lmtp answers for each recipient, smtp once for all of them
+		if self.config.protocol == MailProtocol::Lmtp {
+			for answer in replies {
+				reply(writer, answer).await?;
+			}
+		} else {
+			let answer = replies
+				.iter()
+				.find(|answer| !answer.starts_with('2'))
+				.unwrap_or(&"250 ok");
+			reply(writer, answer).await?;
+		}
+
+		Ok(())
+	}
+
+	/// User owning an address of the newsletter domain
+	fn recipient(&self, address: &str) -> eyre::Result<Option<UserId>> {
+		let Some((token, domain)) = address.rsplit_once('@') else {
+			return Ok(None);
+		};
+		if !domain.eq_ignore_ascii_case(&self.config.domain) {
+			return Ok(None);
+		}
+
+		self.mailboxes.recipient(&token.to_ascii_lowercase())
+	}
+
+	/// Reads a mail up to its terminating dot, `None` when it exceeds the maximum size
+	async fn read_data(
+		&self,
+		reader: &mut BufReader<impl AsyncReadExt + Unpin>,
+	) -> eyre::Result<Option<Vec<u8>>> {
+		let mut data = Vec::new();
+		let mut too_large = false;
+
+		loop {
+			let Some(line) = read_line(reader, MAX_DATA_LINE).await? else {
+				bail!("connection closed while receiving a mail");
+			};
+			if line == b".\r\n" || line == b".\n" {
+				break;
+			}
+
+			// leading dots are doubled by clients
+			let line = line.strip_prefix(b".").unwrap_or(&line);
+			if data.len() + line.len() > self.config.max_message_size {
+				too_large = true;
+			} else {
+				data.extend_from_slice(line);
+			}
+		}
+
+		Ok((!too_large).then_some(data))
+	}
+
+	/// Stores a mail for each recipient, returns the reply of each delivery
+	fn deliver(&self, envelope: &Envelope, data: &[u8]) -> Vec<&'static str> {
+		let message = Message::parse(data);
+
+		// the envelope sender is often a bounce address, the author is in the headers
+		let Some(sender) = message
+			.from
+			.as_deref()
+			.or(envelope.from.as_deref())
+			.filter(|from| !from.is_empty())
+		else {
+			return vec!["550 message has no sender"; envelope.recipients.len()];
+		};
+
+		let content = message.content();
+		let letter = Letter {
+			sender,
+			sender_name: message.from_name.as_deref(),
+			subject: message.subject.as_deref(),
+			message_id: message.id.as_deref(),
+			date: message.date,
+			content: &content,
+		};
+
+		envelope
+			.recipients
+			.iter()
+			.map(|user_id| match self.mailboxes.store(*user_id, &letter) {
+				Ok(feed_id) => {
+					tracing::info!(user_id = ?user_id, feed_id = ?feed_id, sender, "received newsletter");
+					"250 ok"
+				}
+				Err(err) => {
+					tracing::error!(user_id = ?user_id, sender, err = %err, "could not store newsletter");
+					"451 could not store message"
+				}
+			})
+			.collect()
+	}
+}
+
+impl Mailboxes for Store {
+	fn recipient(&self, token: &str) -> eyre::Result<Option<UserId>> {
+		use crate::database::schema::*;
+
+		let mut conn = self.db_pool.get()?;
+		let user_id = user_::table
+			.select(user_::id)
+			.filter(user_::newsletter_token.eq(token))
+			.get_result::<UserId>(&mut conn)
+			.optional()
+			.wrap_err("could not retrieve newsletter recipient")?;
+
+		Ok(user_id)
+	}
+
+	fn store(&self, user_id: UserId, letter: &Letter<'_>) -> eyre::Result<FeedId> {
+		Ok(self.fetcher_handle.receive_letter(user_id, letter)?)
+	}
+}
+
+/// Reads a line with its terminator, `None` once the client closed the connection
+///
+/// Longer lines are cut at `limit`, the rest is read as the next line.
+async fn read_line(
+	reader: &mut BufReader<impl AsyncReadExt + Unpin>,
+	limit: u64,
+) -> eyre::Result<Option<Vec<u8>>> {
+	let mut line = Vec::new();
+	let read = timeout(
+		IDLE_TIMEOUT,
+		(&mut *reader).take(limit).read_until(b'\n', &mut line),
+	)
+	.await
+	.wrap_err("client went idle")??;
+
+	Ok((read > 0).then_some(line))
+}
+
+async fn reply(writer: &mut (impl AsyncWrite + Unpin), reply: &str) -> eyre::Result<()> {
+	writer.write_all(reply.as_bytes()).await?;
+	writer.write_all(b"\r\n").await?;
+	Ok(())
+}
+
+/// Address of a `FROM:<address>` or `TO:<address>` argument, parameters that follow are ignored
+fn path_argument<'a>(args: &'a str, prefix: &str) -> Option<&'a str> {
+	let args = args.trim_start();
+	if !args
+		.get(..prefix.len())
+		.is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+	{
+		return None;
+	}
+
+	let path = args[prefix.len()..].trim_start();
+	let path = path.strip_prefix('<')?;
+	let (address, _) = path.split_once('>')?;
+	Some(address.trim())
+}
+
+#[cfg(test)]
+mod tests {
+	use parking_lot::Mutex;
+	use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+
+	use super::*;
+
+	/// Mailboxes of `alice` and `bob`, keeping the subject and content of received mails
+	#[derive(Debug, Default)]
+	struct Memory {
+		letters: Mutex<Vec<(UserId, String, String)>>,
+	}
+
+	impl Mailboxes for Memory {
+		fn recipient(&self, token: &str) -> eyre::Result<Option<UserId>> {
+			Ok(match token {
+				"alice" => Some(user(1)),
+				"bob" => Some(user(2)),
+				_ => None,
+			})
+		}
+
+		fn store(&self, user_id: UserId, letter: &Letter<'_>) -> eyre::Result<FeedId> {
+			self.letters.lock().push((
+				user_id,
+				letter.subject.unwrap_or_default().to_owned(),
+				letter.content.to_owned(),
+			));
+			Ok(FeedId::UNSAVED)
+		}
+	}
+
+	fn user(id: i32) -> UserId {
+		serde_json::from_value(id.into()).expect("user ids are integers")
+	}
+
+	struct Client {
+		reader: BufReader<OwnedReadHalf>,
+		writer: OwnedWriteHalf,
+	}
+
+	impl Client {
+		/// Sends lines and returns the reply that follows
+		async fn send(&mut self, lines: &str) -> String {
+			self.writer
+				.write_all(lines.as_bytes())
+				.await
+				.expect("could not send to server");
+			self.reply().await
+		}
+
+		/// Reads a reply, continuation lines included
+		async fn reply(&mut self) -> String {
+			let mut reply = String::new();
+			loop {
+				let mut line = String::new();
+				self.reader
+					.read_line(&mut line)
+					.await
+					.expect("could not read reply");
+				reply.push_str(&line);
+				if line.as_bytes().get(3) != Some(&b'-') {
+					return reply.trim_end().to_owned();
+				}
+			}
+		}
+	}
+
+	/// Serves a single session over loopback, returns the client once greeted
+	async fn connect(
+		protocol: MailProtocol,
+		max_message_size: usize,
+	) -> (Arc<Newsletters<Memory>>, Client) {
+		let config = NewsletterConfig {
+			protocol,
+			max_message_size,
+			..NewsletterConfig::default()
+		};
+		let newsletters = Arc::new(Newsletters::new(config, Memory::default()));
+
+		let listener = TcpListener::bind("127.0.0.1:0")
+			.await
+			.expect("could not bind loopback");
+		let address = listener.local_addr().expect("listener is bound");
+		let server = Arc::clone(&newsletters);
+		task::spawn(async move {
+			let (stream, _) = listener.accept().await.expect("could not accept client");
+			server.session(stream).await
+		});
+
+		let (reader, writer) = TcpStream::connect(address)
+			.await
+			.expect("could not connect to server")
+			.into_split();
+		let mut client = Client {
+			reader: BufReader::new(reader),
+			writer,
+		};
+		assert!(client.reply().await.starts_with("220 "));
+
+		(newsletters, client)
+	}
+
+	#[tokio::test]
+	async fn smtp_delivers_mail() {
+		let (newsletters, mut client) = connect(MailProtocol::Smtp, 1024).await;
+
+		let ehlo = client.send("EHLO client.example\r\n").await;
+		assert!(ehlo.starts_with("250-feeds.localhost"));
+		assert!(ehlo.ends_with("250 SIZE 1024"));
+		assert_eq!(
+			client
+				.send("MAIL FROM:<news@example.com> BODY=8BITMIME\r\n")
+				.await,
+			"250 ok"
+		);
+		assert_eq!(
+			client.send("RCPT TO:<Alice@feeds.localhost>\r\n").await,
+			"250 ok"
+		);
+		assert!(client.send("DATA\r\n").await.starts_with("354 "));
+		let data =
+			"From: News <news@example.com>\r\nSubject: Hello\r\n\r\n..leading dot\r\nbody\r\n.\r\n";
+		assert_eq!(client.send(data).await, "250 ok");
+		assert!(client.send("QUIT\r\n").await.starts_with("221 "));
+
+		let letters = newsletters.mailboxes.letters.lock().clone();
+		assert_eq!(letters.len(), 1);
+		let (user_id, subject, content) = &letters[0];
+		assert_eq!(*user_id, user(1));
+		assert_eq!(subject, "Hello");
+		assert_eq!(content, "<p>.leading dot<br>body</p>");
+	}
+
+	#[tokio::test]
+	async fn smtp_refuses_unknown_recipients() {
+		let (newsletters, mut client) = connect(MailProtocol::Smtp, 1024).await;
+
+		client.send("HELO client.example\r\n").await;
+		assert_eq!(
+			client.send("RCPT TO:<alice@feeds.localhost>\r\n").await,
+			"503 need MAIL before RCPT"
+		);
+		client.send("MAIL FROM:<news@example.com>\r\n").await;
+		assert_eq!(
+			client.send("RCPT TO:<carol@feeds.localhost>\r\n").await,
+			"550 no such mailbox"
+		);
+		assert_eq!(
+			client.send("RCPT TO:<alice@elsewhere.example>\r\n").await,
+			"550 no such mailbox"
+		);
+		assert_eq!(client.send("DATA\r\n").await, "503 need RCPT before DATA");
+
+		assert!(newsletters.mailboxes.letters.lock().is_empty());
+	}
+
+	#[tokio::test]
+	async fn smtp_refuses_oversized_mail() {
+		let (newsletters, mut client) = connect(MailProtocol::Smtp, 64).await;
+
+		client.send("EHLO client.example\r\n").await;
+		client.send("MAIL FROM:<news@example.com>\r\n").await;
+		client.send("RCPT TO:<alice@feeds.localhost>\r\n").await;
+		client.send("DATA\r\n").await;
+		let data = format!("Subject: Large\r\n\r\n{}\r\n.\r\n", "x".repeat(100));
+		assert_eq!(
+			client.send(&data).await,
+			"552 message exceeds the maximum size"
+		);
+
+		// the session goes on after a refused mail
+		assert_eq!(client.send("NOOP\r\n").await, "250 ok");
+		assert!(newsletters.mailboxes.letters.lock().is_empty());
+	}
+
+	#[tokio::test]
+	async fn lmtp_answers_each_recipient() {
+		let (newsletters, mut client) = connect(MailProtocol::Lmtp, 1024).await;
+
+		assert_eq!(
+			client.send("EHLO client.example\r\n").await,
+			"502 command not implemented"
+		);
+		client.send("LHLO client.example\r\n").await;
+		client.send("MAIL FROM:<news@example.com>\r\n").await;
+		client.send("RCPT TO:<alice@feeds.localhost>\r\n").await;
+		client.send("RCPT TO:<bob@feeds.localhost>\r\n").await;
+		client.send("DATA\r\n").await;
+		assert_eq!(
+			client.send("Subject: Both\r\n\r\nhi\r\n.\r\n").await,
+			"250 ok"
+		);
+		assert_eq!(client.reply().await, "250 ok");
+
+		let letters = newsletters.mailboxes.letters.lock().clone();
+		let recipients = letters
+			.iter()
+			.map(|(user_id, _, _)| *user_id)
+			.collect::<Vec<_>>();
+		assert_eq!(recipients, [user(1), user(2)]);
+	}
+}
@@ -0,0 +1,361 @@
+use std::{
+	cmp::Reverse,
+	sync::{Arc, Mutex},
+	time::{Duration, Instant},
+};
+
+use diesel::{dsl, prelude::*};
+use eyre::WrapErr;
+use serde::Serialize;
+use time::OffsetDateTime;
+use tokio::{
+	task,
+	time::{MissedTickBehavior, interval},
+};
+
+use crate::{
+	config::RetentionConfig,
+	database::{
+		PoolConnection, PooledConnection,
+		models::{FeedId, Icon},
+	},
+};
+
+/// What a run of the retention job purged
+#[derive(Debug, Clone, Serialize)]
+pub struct RetentionReport {
+	#[serde(with = "time::serde::rfc3339")]
+	pub started_at: OffsetDateTime,
+	pub duration_ms: u64,
+
+	pub entries: usize,
+	/// Feeds without subscribers
+	pub feeds: usize,
+	/// Icons no feed uses anymore
+	pub icons: usize,
+}
+
+/// Periodically purges old entries and the feeds nobody subscribes to anymore
+///
+/// Starred entries and the entries listed by the latest fetch of their feed are always kept.
+#[derive(Debug)]
+pub struct Retention {
+	config: RetentionConfig,
+	db_pool: PoolConnection,
+
+	/// Outcome of the latest run, shown through the admin api
+	last_report: Mutex<Option<RetentionReport>>,
+}
+
+impl Retention {
+	/// Spawns the retention job when enabled, the handle can always trigger a run by hand
+	pub fn setup(config: RetentionConfig, db_pool: PoolConnection) -> RetentionHandle {
+		let enabled = config.enabled;
+		let retention = Arc::new(Self {
+			config,
+			db_pool,
+			last_report: Mutex::new(None),
+		});
+
+		if enabled {
+			Arc::clone(&retention).spawn();
+		}
+
+		RetentionHandle { retention }
+	}
+
+	fn spawn(self: Arc<Self>) {
+		task::spawn(self.loop_task());
+	}
+
+	async fn loop_task(self: Arc<Self>) {
+		let mut interval = interval(Duration::from_secs(self.config.interval));
+		interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+		loop {
+			interval.tick().await;
+			if let Err(err) = Arc::clone(&self).run_blocking().await {
+				tracing::error!(err = %err, "error while purging entries");
+			}
+		}
+	}
+
+	/// Database work is synchronous, runs are kept off the async workers
+	async fn run_blocking(self: Arc<Self>) -> eyre::Result<RetentionReport> {
+		task::spawn_blocking(move || self.run())
+			.await
+			.wrap_err("retention job panicked")?
+	}
+
+	fn run(&self) -> eyre::Result<RetentionReport> {
+		use crate::database::schema::*;
+
+		let started_at = OffsetDateTime::now_utc();
+		let timer = Instant::now();
+
+		let mut conn = self.db_pool.get()?;
+		let limits = feed::table
+			.select((
+				feed::id,
+				feed::retention_max_age_secs,
+				feed::retention_max_entries,
+			))
+			.load::<(FeedId, Option<i32>, Option<i32>)>(&mut conn)
+			.wrap_err("could not retrieve feed retention limits")?;
+
+		let mut entries = 0;
+		for (feed_id, max_age, max_entries) in limits {
+			let max_age = max_age.map_or(self.config.max_age, |max_age| {
+				u64::try_from(max_age).unwrap_or_default()
+			});
+			let max_entries = max_entries.map_or(self.config.max_entries, |max_entries| {
+				u64::try_from(max_entries).unwrap_or_default()
+			});
+
+			entries += purge_entries(feed_id, max_age, max_entries, &mut conn)
+				.wrap_err("could not purge feed entries")?;
+		}
+
+		let feeds = purge_orphan_feeds(&mut conn).wrap_err("could not purge orphan feeds")?;
+		let icons = Icon::delete_unused(&mut conn).wrap_err("could not purge unused icons")?;
+
+		let report = RetentionReport {
+			started_at,
+			duration_ms: u64::try_from(timer.elapsed().as_millis()).unwrap_or(u64::MAX),
+			entries,
+			feeds,
+			icons,
+		};
+		tracing::info!(
+			entries = report.entries,
+			feeds = report.feeds,
+			icons = report.icons,
+			duration_ms = report.duration_ms,
+			"purged old entries"
+		);
+
+		*self.last_report.lock().expect("lock is not poisoned") = Some(report.clone());
+		Ok(report)
+	}
+}
+
+/// Entry as seen by the retention job
+#[derive(Debug, Clone, Queryable)]
+struct StoredEntry {
+	id: i32,
+	date: OffsetDateTime,
+	last_seen_at: OffsetDateTime,
+	starred: bool,
+}
+
+/// Deletes the entries of a feed above its age or count limit, `0` disables a limit
+fn purge_entries(
+	feed_id: FeedId,
+	max_age: u64,
+	max_entries: u64,
+	conn: &mut PooledConnection,
+) -> QueryResult<usize> {
+	use crate::database::schema::*;
+
+	if max_age == 0 && max_entries == 0 {
+		return Ok(0);
+	}
+
+	let mut entries = feed_entry::table
+		.select((
+			feed_entry::id,
+			feed_entry::date,
+			feed_entry::last_seen_at,
+			dsl::exists(
+				user_feed_entry_meta::table
+					.filter(user_feed_entry_meta::feed_entry_id.eq(feed_entry::id))
+					.filter(user_feed_entry_meta::starred.ne(0)),
+			),
+		))
+		.filter(feed_entry::feed_id.eq(feed_id))
+		.load::<StoredEntry>(conn)?;
+
+	let expired = expired_entries(
+		&mut entries,
+		OffsetDateTime::now_utc(),
+		max_age,
+		max_entries,
+	);
+	if expired.is_empty() {
+		return Ok(0);
+	}
+
+	// entries starred since they were loaded are kept too
+	dsl::delete(
+		feed_entry::table
+			.filter(feed_entry::id.eq_any(expired))
+			.filter(dsl::not(dsl::exists(
+				user_feed_entry_meta::table
+					.filter(user_feed_entry_meta::feed_entry_id.eq(feed_entry::id))
+					.filter(user_feed_entry_meta::starred.ne(0)),
+			))),
+	)
+	.execute(conn)
+}
+
+/// Ids of the entries older than `max_age` seconds or past the `max_entries` most recent ones
+///
+/// Entries sharing a date are ordered by id so the count limit is exact. Starred entries and the
+/// entries listed by the latest fetch, which were all seen in the same statement, are kept.
+fn expired_entries(
+	entries: &mut [StoredEntry],
+	now: OffsetDateTime,
+	max_age: u64,
+	max_entries: u64,
+) -> Vec<i32> {
+	let Some(latest) = entries.iter().map(|entry| entry.last_seen_at).max() else {
+		return Vec::new();
+	};
+	let age_cutoff = (max_age > 0).then(|| now - Duration::from_secs(max_age));
+	let max_entries = usize::try_from(max_entries).unwrap_or(usize::MAX);
+
+	entries.sort_unstable_by_key(|entry| Reverse((entry.date, entry.id)));
+	entries
+		.iter()
+		.enumerate()
+		.filter(|(rank, entry)| {
+			let too_old = age_cutoff.is_some_and(|cutoff| entry.date < cutoff);
+			let too_many = max_entries > 0 && *rank >= max_entries;
+			(too_old || too_many) && !entry.starred && entry.last_seen_at < latest
+		})
+		.map(|(_, entry)| entry.id)
+		.collect()
+}
+
+/// Deletes the feeds no user subscribes to, unless one of their entries is starred
+fn purge_orphan_feeds(conn: &mut PooledConnection) -> QueryResult<usize> {
+	use crate::database::schema::*;
+
+	dsl::delete(
+		feed::table
+			.filter(dsl::not(dsl::exists(
+				user_feed::table.filter(user_feed::feed_id.eq(feed::id)),
+			)))
+			.filter(dsl::not(dsl::exists(
+				feed_entry::table
+					.inner_join(user_feed_entry_meta::table)
+					.filter(feed_entry::feed_id.eq(feed::id))
+					.filter(user_feed_entry_meta::starred.ne(0)),
+			))),
+	)
+	.execute(conn)
+}
+
+#[derive(Debug, Clone)]
+pub struct RetentionHandle {
+	retention: Arc<Retention>,
+}
+
+impl RetentionHandle {
+	/// Outcome of the latest run of this instance, `None` until a run completes
+	pub fn last_report(&self) -> Option<RetentionReport> {
+		self.retention
+			.last_report
+			.lock()
+			.expect("lock is not poisoned")
+			.clone()
+	}
+
+	/// Runs the retention job right away
+	pub async fn run(&self) -> eyre::Result<RetentionReport> {
+		Arc::clone(&self.retention).run_blocking().await
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const DAY: u64 = 24 * 60 * 60;
+
+	/// Entries one day apart from the newest one, the first `fresh` are listed by the latest fetch
+	fn entries(now: OffsetDateTime, count: i32, fresh: i32) -> Vec<StoredEntry> {
+		(0..count)
+			.map(|id| StoredEntry {
+				id,
+				date: now - Duration::from_secs(DAY) * id.unsigned_abs(),
+				last_seen_at: if id < fresh {
+					now
+				} else {
+					now - Duration::from_secs(DAY)
+				},
+				starred: false,
+			})
+			.collect()
+	}
+
+	fn sorted(mut ids: Vec<i32>) -> Vec<i32> {
+		ids.sort_unstable();
+		ids
+	}
+
+	#[test]
+	fn expires_by_age() {
+		let now = OffsetDateTime::now_utc();
+
+		let mut stored = entries(now, 6, 1);
+		assert_eq!(
+			sorted(expired_entries(&mut stored, now, 3 * DAY + 1, 0)),
+			[4, 5]
+		);
+
+		let mut stored = entries(now, 6, 1);
+		assert!(expired_entries(&mut stored, now, 0, 0).is_empty());
+		assert!(expired_entries(&mut [], now, DAY, 1).is_empty());
+	}
+
+	#[test]
+	fn expires_by_count() {
+		let now = OffsetDateTime::now_utc();
+
+		let mut stored = entries(now, 6, 1);
+		assert_eq!(sorted(expired_entries(&mut stored, now, 0, 4)), [4, 5]);
+
+		// both limits apply, the strictest wins
+		let mut stored = entries(now, 6, 1);
+		assert_eq!(
+			sorted(expired_entries(&mut stored, now, 10 * DAY, 2)),
+			[2, 3, 4, 5]
+		);
+		let mut stored = entries(now, 6, 1);
+		assert_eq!(
+			sorted(expired_entries(&mut stored, now, DAY + 1, 5)),
+			[2, 3, 4, 5]
+		);
+	}
+
+	#[test]
+	fn counts_entries_sharing_a_date() {
+		let now = OffsetDateTime::now_utc();
+		let mut stored = entries(now, 6, 1);
+		for entry in &mut stored {
+			entry.date = now - Duration::from_secs(DAY);
+		}
+
+		// only the entries past the limit go, the lowest ids first, but the first one is still
+		// listed by the feed
+		assert_eq!(sorted(expired_entries(&mut stored, now, 0, 3)), [1, 2]);
+		assert_eq!(
+			sorted(expired_entries(&mut stored, now, 0, 6)),
+			Vec::<i32>::new()
+		);
+	}
+
+	#[test]
+	fn keeps_starred_and_fresh_entries() {
+		let now = OffsetDateTime::now_utc();
+
+		let mut stored = entries(now, 6, 3);
+		stored[4].starred = true;
+		assert_eq!(sorted(expired_entries(&mut stored, now, DAY, 1)), [3, 5]);
+
+		// a feed that only lists old entries keeps them all
+		let mut stored = entries(now, 6, 6);
+		assert!(expired_entries(&mut stored, now, DAY, 1).is_empty());
+	}
+}
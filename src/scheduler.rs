@@ -0,0 +1,330 @@
+use std::time::Duration;
+
+use diesel::{dsl, prelude::*};
+use eyre::WrapErr;
+use time::OffsetDateTime;
+use tokio::{
+	task,
+	time::{MissedTickBehavior, interval},
+};
+
+use crate::{
+	config::SchedulerConfig,
+	database::{PoolConnection, models::FeedId},
+	fetcher::{self, FetcherHandle, Priority},
+};
+
+/// Maximum number of feeds enqueued on a single tick
+const BATCH_SIZE: i64 = 100;
+
+/// Periodically walks the `feed` table to enqueue feeds that are due for a refresh
+#[derive(Debug)]
+pub struct Scheduler {
+	config: SchedulerConfig,
+	db_pool: PoolConnection,
+	fetcher_handle: FetcherHandle,
+}
+
+impl Scheduler {
+	pub fn setup(config: SchedulerConfig, db_pool: PoolConnection, fetcher_handle: FetcherHandle) {
+		let scheduler = Self {
+			config,
+			db_pool,
+			fetcher_handle,
+		};
+		scheduler.spawn();
+	}
+
+	fn spawn(self) {
+		task::spawn(self.loop_task());
+	}
+
+	async fn loop_task(self) {
+		let mut interval = interval(Duration::from_secs(self.config.tick_interval));
+		interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+		loop {
+			interval.tick().await;
+			if let Err(err) = self.tick() {
+				tracing::error!(err = %err, "error while scheduling feeds");
+			}
+		}
+	}
+
+	fn tick(&self) -> eyre::Result<()> {
+		let enqueued = self.enqueue_due_feeds()?;
+
+		if enqueued > 0 {
+			tracing::debug!(count = enqueued, "enqueued due feeds");
+			self.fetcher_handle.wake();
+		}
+
+		Ok(())
+	}
+
+	/// Queues feeds with subscribers that are due and pushes back their next fetch date so
+	/// they are not picked up again while being fetched
+	fn enqueue_due_feeds(&self) -> eyre::Result<usize> {
+		use crate::database::schema::*;
+
+		let mut conn = self.db_pool.get()?;
+		conn.transaction::<_, diesel::result::Error, _>(|conn| {
+			let due_feeds = feed::table
+				.select(feed::id)
+				.filter(
+					feed::next_fetch_at
+						.is_null()
+						.or(feed::next_fetch_at.le(dsl::now)),
+				)
+				.filter(dsl::exists(
+					user_feed::table.filter(user_feed::feed_id.eq(feed::id)),
+				))
+				.filter(feed::source.ne("newsletter"))
+				.order_by(feed::next_fetch_at.asc().nulls_first())
+				.limit(BATCH_SIZE)
+				.for_update()
+				.skip_locked()
+				.load::<FeedId>(conn)?;
+
+			for feed_id in &due_feeds {
+				dsl::update(feed::table.find(feed_id))
+					.set(feed::next_fetch_at.eq(self.next_fetch_at()))
+					.execute(conn)?;
+			}
+
+			fetcher::enqueue(due_feeds, Priority::Background, conn)
+		})
+		.wrap_err("could not enqueue due feeds")
+	}
+
+	/// Lease given to claimed feeds, overwritten by the fetcher once the fetch completes
+	fn next_fetch_at(&self) -> OffsetDateTime {
+		jittered(
+			&self.config,
+			Duration::from_secs(self.config.refresh_interval),
+		)
+	}
+}
+
+/// Spreads refreshes with a random delay so feeds added together are not all fetched at once
+///
+/// Dates out of range fall back to the longest refresh interval.
+pub fn jittered(config: &SchedulerConfig, interval: Duration) -> OffsetDateTime {
+	let jitter = Duration::from_secs(rand::random_range(0..=config.jitter));
+	let now = OffsetDateTime::now_utc();
+
+	time::Duration::try_from(interval.saturating_add(jitter))
+		.ok()
+		.and_then(|delay| now.checked_add(delay))
+		.unwrap_or_else(|| now + Duration::from_secs(config.max_interval))
+}
+
+/// Refresh interval of a successfully fetched feed
+///
+/// Follows the observed posting frequency, never going below what the publisher or the server
+/// advertised.
+pub fn success_interval(
+	config: &SchedulerConfig,
+	entry_dates: &[OffsetDateTime],
+	publisher_interval: Option<Duration>,
+	max_age: Option<Duration>,
+) -> Duration {
+	let interval = posting_interval(entry_dates)
+		.unwrap_or_else(|| Duration::from_secs(config.refresh_interval));
+
+	[publisher_interval, max_age]
+		.into_iter()
+		.flatten()
+		.fold(interval, Duration::max)
+		.max(Duration::from_secs(config.min_interval))
+		.min(Duration::from_secs(config.max_interval))
+}
+
+/// Refresh interval of a feed pushed by a websub hub
+///
+/// Polling is only a fallback but must happen before half of the lease elapsed so it is renewed
+/// in time.
+pub fn push_interval(
+	config: &SchedulerConfig,
+	interval: Duration,
+	lease_expires_at: OffsetDateTime,
+) -> Duration {
+	let renewal =
+		Duration::try_from((lease_expires_at - OffsetDateTime::now_utc()) / 2).unwrap_or_default();
+
+	interval
+		.max(Duration::from_secs(config.push_interval))
+		.min(renewal)
+		.max(Duration::from_secs(config.min_interval))
+}
+
+/// Refresh interval of a failing feed, doubling on each consecutive failure
+///
+/// `Retry-After` can only delay the next attempt up to the longest backoff.
+pub fn failure_interval(
+	config: &SchedulerConfig,
+	consecutive_failures: u32,
+	retry_after: Option<Duration>,
+) -> Duration {
+	let factor = 2_u32.saturating_pow(consecutive_failures.saturating_sub(1));
+	let max_backoff = Duration::from_secs(config.max_backoff);
+	let backoff = Duration::from_secs(config.min_interval)
+		.saturating_mul(factor)
+		.min(max_backoff);
+
+	retry_after.map_or(backoff, |retry_after| {
+		backoff.max(retry_after.min(max_backoff))
+	})
+}
+
+/// Average delay between the given entry dates, sorted from newest to oldest
+///
+/// Feeds that stopped posting are slowed down according to the age of their latest entry.
+fn posting_interval(entry_dates: &[OffsetDateTime]) -> Option<Duration> {
+	let (newest, oldest) = (entry_dates.first()?, entry_dates.last()?);
+	let gaps = u32::try_from(entry_dates.len() - 1)
+		.ok()
+		.filter(|gaps| *gaps > 0)?;
+
+	let average = Duration::try_from((*newest - *oldest) / gaps).ok()?;
+	let staleness = Duration::try_from(OffsetDateTime::now_utc() - *newest).unwrap_or_default() / 4;
+
+	Some(average.max(staleness))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const MINUTE: u64 = 60;
+	const HOUR: u64 = 60 * MINUTE;
+	const DAY: u64 = 24 * HOUR;
+
+	fn config() -> SchedulerConfig {
+		SchedulerConfig {
+			auto_refresh: true,
+			refresh_interval: HOUR,
+			min_interval: 10 * MINUTE,
+			max_interval: DAY,
+			max_backoff: 2 * DAY,
+			jitter: 0,
+			tick_interval: MINUTE,
+			push_interval: 3 * DAY,
+		}
+	}
+
+	fn hours_ago(hours: &[i64]) -> Vec<OffsetDateTime> {
+		let now = OffsetDateTime::now_utc();
+		hours
+			.iter()
+			.map(|hours| now - time::Duration::hours(*hours))
+			.collect()
+	}
+
+	#[test]
+	fn averages_posting_gaps() {
+		assert_eq!(posting_interval(&[]), None);
+		assert_eq!(posting_interval(&hours_ago(&[1])), None);
+
+		let interval = posting_interval(&hours_ago(&[0, 2, 4, 6])).expect("has gaps");
+		assert_eq!(interval.as_secs() / MINUTE, 2 * 60);
+
+		// the latest entry is 40 hours old, a quarter of it outweighs the two hours gaps
+		let interval = posting_interval(&hours_ago(&[40, 42, 44])).expect("has gaps");
+		assert_eq!(interval.as_secs() / HOUR, 10);
+	}
+
+	#[test]
+	fn bounds_success_interval() {
+		let config = config();
+		let success = |dates: &[i64], publisher: Option<u64>, max_age: Option<u64>| {
+			success_interval(
+				&config,
+				&hours_ago(dates),
+				publisher.map(Duration::from_secs),
+				max_age.map(Duration::from_secs),
+			)
+		};
+
+		assert_eq!(success(&[], None, None), Duration::from_secs(HOUR));
+		assert_eq!(success(&[0, 2, 4], None, None).as_secs() / MINUTE, 2 * 60);
+		assert_eq!(
+			success(&[0, 2, 4], Some(5 * HOUR), None),
+			Duration::from_secs(5 * HOUR)
+		);
+		assert_eq!(
+			success(&[0, 2, 4], None, Some(6 * HOUR)),
+			Duration::from_secs(6 * HOUR)
+		);
+		assert_eq!(success(&[], Some(MINUTE), None), Duration::from_secs(HOUR));
+
+		// the next fetch always happens between the configured bounds
+		assert_eq!(success(&[], None, Some(1)), Duration::from_secs(HOUR));
+		assert_eq!(
+			success(&[0], Some(30 * DAY), Some(u64::MAX)),
+			Duration::from_secs(DAY)
+		);
+		let config = SchedulerConfig {
+			refresh_interval: MINUTE,
+			..config
+		};
+		assert_eq!(
+			success_interval(&config, &[], None, None),
+			Duration::from_secs(10 * MINUTE)
+		);
+	}
+
+	#[test]
+	fn backs_off_failures() {
+		let config = config();
+
+		assert_eq!(
+			failure_interval(&config, 1, None),
+			Duration::from_secs(10 * MINUTE)
+		);
+		assert_eq!(
+			failure_interval(&config, 2, None),
+			Duration::from_secs(20 * MINUTE)
+		);
+		assert_eq!(
+			failure_interval(&config, 4, None),
+			Duration::from_secs(80 * MINUTE)
+		);
+		assert_eq!(
+			failure_interval(&config, 40, None),
+			Duration::from_secs(2 * DAY)
+		);
+		assert_eq!(
+			failure_interval(&config, u32::MAX, None),
+			Duration::from_secs(2 * DAY)
+		);
+	}
+
+	#[test]
+	fn caps_retry_after() {
+		let config = config();
+		let retry = |secs| failure_interval(&config, 1, Some(Duration::from_secs(secs)));
+
+		assert_eq!(retry(MINUTE), Duration::from_secs(10 * MINUTE));
+		assert_eq!(retry(3 * HOUR), Duration::from_secs(3 * HOUR));
+		assert_eq!(retry(365 * DAY), Duration::from_secs(2 * DAY));
+		assert_eq!(retry(u64::MAX), Duration::from_secs(2 * DAY));
+	}
+
+	#[test]
+	fn jitters_without_overflowing() {
+		let config = SchedulerConfig {
+			jitter: 10,
+			..config()
+		};
+		let now = OffsetDateTime::now_utc();
+
+		let next = jittered(&config, Duration::from_secs(HOUR));
+		assert!(next >= now + Duration::from_secs(HOUR));
+		assert!(next <= now + Duration::from_secs(HOUR + 11));
+
+		let next = jittered(&config, Duration::MAX);
+		assert!(next >= now + Duration::from_secs(DAY));
+		assert!(next <= now + Duration::from_secs(DAY + 1));
+	}
+}